@@ -0,0 +1,42 @@
+//! Compare the time to compute a Blake3 digest by building the constraint system with the
+//! time to evaluate the same gadgets without any script.
+
+use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+use bitvm_memory::compression::blake3::eval::blake3_eval;
+use bitvm_memory::compression::blake3::{hash, Blake3ConstantVar, MAX_BLOCKS};
+use bitvm_memory::limbs::u32::U32Var;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use std::time::Instant;
+
+fn main() {
+    let mut prng = ChaCha20Rng::seed_from_u64(0);
+    let messages = (0..16 * MAX_BLOCKS)
+        .map(|_| prng.gen())
+        .collect::<Vec<u32>>();
+
+    let start = Instant::now();
+    let cs = ConstraintSystem::new_ref();
+    let messages_var = messages
+        .iter()
+        .map(|&v| U32Var::new_program_input(&cs, v).unwrap())
+        .collect::<Vec<_>>();
+    let constant = Blake3ConstantVar::new(&cs);
+    let var_hash = hash(&constant, messages_var.as_slice());
+    let var_time = start.elapsed();
+
+    let start = Instant::now();
+    let eval_hash = blake3_eval(&messages);
+    let eval_time = start.elapsed();
+
+    assert_eq!(var_hash.hash.map(|word| word.value().unwrap()), eval_hash);
+
+    println!("Blake3 over {} blocks", MAX_BLOCKS);
+    println!("  with the constraint system: {:?}", var_time);
+    println!("  evaluation only: {:?}", eval_time);
+    println!(
+        "  speedup: {:.0}x",
+        var_time.as_secs_f64() / eval_time.as_secs_f64()
+    );
+}
@@ -0,0 +1,85 @@
+//! Compare SHA-256 and Blake3 hash chains for Winternitz signatures over a 256-bit message.
+//!
+//! Only SHA-256 chains can be verified in Bitcoin script natively, so the script size of the
+//! SHA-256 verifier is measured, while the script size of a Blake3 verifier is estimated from
+//! the size of one in-script Blake3 compression times the number of chain steps it has to
+//! cover.
+
+use bitcoin_script_dsl::builtins::u8::U8Var;
+use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode};
+use bitcoin_script_dsl::compiler::Compiler;
+use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+use bitvm_memory::commitment::winternitz::{
+    Winternitz, WinternitzMetadata, WinternitzSignatureVar,
+};
+use bitvm_memory::compression::blake3::{hash, Blake3ConstantVar};
+use bitvm_memory::limbs::u32::U32Var;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+const NUM_BITS: usize = 256;
+
+fn sha256_script_size(w: usize, bits: &[bool], prng: &mut ChaCha20Rng) -> usize {
+    let l = NUM_BITS / w;
+    let winternitz = Winternitz::keygen(prng);
+    let secret_key = winternitz.get_secret_key("bench", w, l);
+    let public_key = secret_key.to_public_key();
+    let signature = secret_key.sign(bits);
+
+    let cs = ConstraintSystem::new_ref();
+    let units = bits
+        .chunks_exact(w)
+        .map(|chunk| {
+            let mut unit = 0u8;
+            for (i, &bit) in chunk.iter().enumerate() {
+                unit |= (bit as u8) << i;
+            }
+            U8Var::new_program_input(&cs, unit).unwrap()
+        })
+        .collect::<Vec<_>>();
+    let signature_var =
+        WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+            .unwrap();
+    signature_var.verify(&units, &public_key).unwrap();
+
+    Compiler::compile(cs).unwrap().script.len()
+}
+
+fn blake3_compression_script_size() -> usize {
+    let cs = ConstraintSystem::new_ref();
+    let constant = Blake3ConstantVar::new(&cs);
+    let words = (0..8)
+        .map(|_| U32Var::new_program_input(&cs, 0).unwrap())
+        .collect::<Vec<_>>();
+    let res = hash(&constant, words.as_slice());
+    res.set_program_output().unwrap();
+
+    Compiler::compile(cs).unwrap().script.len()
+}
+
+fn main() {
+    let mut prng = ChaCha20Rng::seed_from_u64(0);
+    let bits = (0..NUM_BITS).map(|_| prng.gen()).collect::<Vec<bool>>();
+
+    let blake3_compression = blake3_compression_script_size();
+
+    println!("Winternitz over a {}-bit message", NUM_BITS);
+    for w in [4, 8] {
+        let l = NUM_BITS / w;
+        let metadata = WinternitzMetadata::new("bench", w, l);
+        let num_chains = l + metadata.checksum_length();
+        let max_hash_ops = num_chains * ((1 << w) - 1);
+
+        let sha256_size = sha256_script_size(w, &bits, &mut prng);
+
+        println!(
+            "w = {}: {} chains, up to {} hash operations in total",
+            w, num_chains, max_hash_ops
+        );
+        println!("  SHA-256 script size: {} bytes", sha256_size);
+        println!(
+            "  Blake3 script size (estimated): {} bytes",
+            max_hash_ops * blake3_compression
+        );
+    }
+}
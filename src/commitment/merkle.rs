@@ -0,0 +1,288 @@
+use crate::compression::blake3::{hash_checked, Blake3ConstantVar, Blake3HashVar, ToU4LimbVar};
+use crate::limbs::u4::U4Var;
+use anyhow::{Error, Result};
+use bitcoin_circle_stark::treepp::*;
+use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+use bitcoin_script_dsl::constraint_system::ConstraintSystemRef;
+
+/// A Merkle tree over Blake3, where the leaves are hashed as blake3(leaf) and the internal
+/// nodes as blake3(left || right).
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    /// The layers from the leaf hashes up to the root.
+    pub layers: Vec<Vec<[u8; 32]>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerklePath {
+    /// The position of the leaf.
+    pub index: usize,
+    /// The siblings from the leaf level up to the level below the root.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleTree {
+    pub fn new(leaves: &[Vec<u8>]) -> Self {
+        assert!(leaves.len().is_power_of_two());
+
+        let mut layers = vec![leaves
+            .iter()
+            .map(|leaf| *blake3::hash(leaf).as_bytes())
+            .collect::<Vec<_>>()];
+
+        while layers.last().unwrap().len() > 1 {
+            let layer = layers
+                .last()
+                .unwrap()
+                .chunks_exact(2)
+                .map(|pair| hash_pair(&pair[0], &pair[1]))
+                .collect();
+            layers.push(layer);
+        }
+
+        Self { layers }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.layers.len() - 1
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.layers.last().unwrap()[0]
+    }
+
+    pub fn query(&self, index: usize) -> MerklePath {
+        assert!(index < self.layers[0].len());
+
+        let mut siblings = vec![];
+        let mut cur = index;
+        for layer in self.layers.iter().take(self.depth()) {
+            siblings.push(layer[cur ^ 1]);
+            cur >>= 1;
+        }
+
+        MerklePath { index, siblings }
+    }
+
+    /// Replace the leaf at `index` and recompute the nodes on its path to the root.
+    pub fn update(&mut self, index: usize, leaf: &[u8]) {
+        assert!(index < self.layers[0].len());
+
+        self.layers[0][index] = *blake3::hash(leaf).as_bytes();
+
+        let mut cur = index;
+        for level in 0..self.depth() {
+            let parent = cur >> 1;
+            let node = hash_pair(
+                &self.layers[level][parent << 1],
+                &self.layers[level][(parent << 1) | 1],
+            );
+            self.layers[level + 1][parent] = node;
+            cur = parent;
+        }
+    }
+}
+
+impl MerklePath {
+    /// The direction at each level, from the leaf upwards, where `true` means that the current
+    /// node is the right child.
+    pub fn direction_bits(&self) -> Vec<bool> {
+        (0..self.siblings.len())
+            .map(|i| (self.index >> i) & 1 == 1)
+            .collect()
+    }
+
+    pub fn verify(&self, leaf: &[u8], root: &[u8; 32]) -> Result<()> {
+        let mut cur = *blake3::hash(leaf).as_bytes();
+        for (sibling, is_right) in self.siblings.iter().zip(self.direction_bits()) {
+            cur = if is_right {
+                hash_pair(sibling, &cur)
+            } else {
+                hash_pair(&cur, sibling)
+            };
+        }
+
+        if &cur != root {
+            return Err(Error::msg("The Merkle path does not lead to the root."));
+        }
+        Ok(())
+    }
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+/// Convert a Blake3 digest into the eight little-endian words that `Blake3HashVar` holds.
+pub fn blake3_digest_to_words(digest: &[u8; 32]) -> [u32; 8] {
+    let mut words = [0u32; 8];
+    for (word, chunk) in words.iter_mut().zip(digest.chunks_exact(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    words
+}
+
+pub struct MerklePathVar {
+    pub siblings: Vec<Blake3HashVar>,
+}
+
+impl MerklePathVar {
+    /// Allocate the siblings of the path as hints, with their limbs checked to be in range.
+    pub fn new_hint(cs: &ConstraintSystemRef, path: &MerklePath) -> Result<Self> {
+        let mut siblings = vec![];
+        for sibling in path.siblings.iter() {
+            siblings.push(Blake3HashVar::new_hint(cs, *sibling)?);
+        }
+        Ok(Self { siblings })
+    }
+
+    /// Verify that `leaf` is in the tree with the given root, where `direction_bits[i]` is 1 if
+    /// the node at level `i` is the right child and 0 otherwise.
+    pub fn verify<T: ToU4LimbVar>(
+        &self,
+        constant: &Blake3ConstantVar,
+        leaf: T,
+        direction_bits: &[U4Var],
+        root: &Blake3HashVar,
+    ) -> Result<()> {
+        if direction_bits.len() != self.siblings.len() {
+            return Err(Error::msg(
+                "The number of direction bits does not match the depth of the Merkle path.",
+            ));
+        }
+
+        let mut cur = hash_checked(constant, leaf)?;
+        for (sibling, direction_bit) in self.siblings.iter().zip(direction_bits.iter()) {
+            let children = order_children(&cur, sibling, direction_bit)?;
+            cur = hash_checked(constant, children.as_slice())?;
+        }
+
+        cur.equalverify(root)
+    }
+}
+
+/// Place the current node and its sibling in the order given by the direction bit.
+pub(crate) fn order_children(
+    cur: &Blake3HashVar,
+    sibling: &Blake3HashVar,
+    direction_bit: &U4Var,
+) -> Result<Vec<U4Var>> {
+    let cs = cur.hash[0]
+        .cs()
+        .and(&sibling.hash[0].cs())
+        .and(&direction_bit.cs());
+
+    let cur_limbs = cur.to_u4_limbs();
+    let sibling_limbs = sibling.to_u4_limbs();
+
+    let is_right = direction_bit.value()? == 1;
+    let (left, right) = if is_right {
+        (sibling_limbs.clone(), cur_limbs.clone())
+    } else {
+        (cur_limbs.clone(), sibling_limbs.clone())
+    };
+
+    cs.insert_script(
+        merkle_order_children,
+        cur_limbs
+            .iter()
+            .chain(sibling_limbs.iter())
+            .map(|limb| limb.variable)
+            .chain(std::iter::once(direction_bit.variable)),
+    )?;
+
+    let mut res = vec![];
+    for limb in left.iter().chain(right.iter()) {
+        res.push(U4Var::new_function_output(&cs, limb.value)?);
+    }
+    Ok(res)
+}
+
+fn merkle_order_children() -> Script {
+    script! {
+        OP_DUP 0 2 OP_WITHIN OP_VERIFY
+        OP_IF
+            // move the current node above the sibling
+            for _ in 0..64 {
+                127 OP_ROLL
+            }
+        OP_ENDIF
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commitment::merkle::{blake3_digest_to_words, MerklePathVar, MerkleTree};
+    use crate::compression::blake3::{Blake3ConstantVar, Blake3HashVar};
+    use crate::limbs::u32::U32Var;
+    use crate::limbs::u4::U4Var;
+    use crate::test_utils::expect_program_failure_without_opcat;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::AllocVar;
+    use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+    use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn merkle_test_case(depth: usize, corrupt: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut leaves = vec![];
+        for _ in 0..(1 << depth) {
+            let words: [u32; 8] = prng.gen();
+            leaves.push(words);
+        }
+        let leaves_bytes = leaves
+            .iter()
+            .map(|words| words.iter().flat_map(|w| w.to_le_bytes()).collect())
+            .collect::<Vec<Vec<u8>>>();
+
+        let tree = MerkleTree::new(&leaves_bytes);
+        let index = prng.gen_range(0..leaves.len());
+        let mut path = tree.query(index);
+        path.verify(&leaves_bytes[index], &tree.root()).unwrap();
+
+        if corrupt {
+            path.siblings[depth / 2][0] ^= 1;
+        }
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let leaf_var = leaves[index]
+            .iter()
+            .map(|&w| U32Var::new_program_input(&cs, w).unwrap())
+            .collect::<Vec<_>>();
+        let direction_bits = path
+            .direction_bits()
+            .iter()
+            .map(|&bit| U4Var::new_program_input(&cs, bit as u32).unwrap())
+            .collect::<Vec<_>>();
+        let root_var = Blake3HashVar {
+            hash: blake3_digest_to_words(&tree.root())
+                .map(|w| U32Var::new_constant(&cs, w).unwrap()),
+        };
+
+        let path_var = MerklePathVar::new_hint(&cs, &path).unwrap();
+        path_var
+            .verify(&constant, leaf_var.as_slice(), &direction_bits, &root_var)
+            .unwrap();
+
+        cs
+    }
+
+    #[test]
+    fn test_merkle_path() {
+        for depth in [1, 4, 10] {
+            test_program_without_opcat(merkle_test_case(depth, false), script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_merkle_path_corrupted_sibling() {
+        expect_program_failure_without_opcat(merkle_test_case(4, true), script! {}).unwrap();
+    }
+}
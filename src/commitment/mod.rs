@@ -1 +1,4 @@
+pub mod merkle;
+pub mod scalar;
+pub mod signed_hash;
 pub mod winternitz;
@@ -0,0 +1,117 @@
+use crate::commitment::winternitz::{
+    bytes_to_bits, WinternitzPublicKey, WinternitzSecretKey, WinternitzSignature,
+    WinternitzSignatureVar,
+};
+use crate::limbs::u32::{U32CompactVar, U32Var};
+use anyhow::{Error, Result};
+use bitcoin_script_dsl::builtins::u8::U8Var;
+use bitcoin_script_dsl::bvar::AllocVar;
+use bitcoin_script_dsl::constraint_system::ConstraintSystemRef;
+
+/// Allocate `value` as a program input and sign it with a Winternitz key with w = 4 and l = 8,
+/// so that each unit of the signature is one nibble of the value.
+pub fn commit_u32(
+    cs: &ConstraintSystemRef,
+    value: u32,
+    secret_key: &WinternitzSecretKey,
+) -> Result<(U32CompactVar, WinternitzSignature)> {
+    check_u32_metadata(secret_key.metadata.w, secret_key.metadata.l)?;
+
+    let value_var = U32CompactVar::new_program_input(cs, value)?;
+    let signature = secret_key.sign(&bytes_to_bits(&value.to_le_bytes()));
+
+    Ok((value_var, signature))
+}
+
+/// Verify in script that `value` is signed by `signature`, and return its nibbles.
+pub fn open_u32(
+    value: &U32CompactVar,
+    signature: &WinternitzSignatureVar,
+    public_key: &WinternitzPublicKey,
+) -> Result<U32Var> {
+    check_u32_metadata(public_key.metadata.w, public_key.metadata.l)?;
+
+    let limbs = U32Var::from(value);
+
+    // the nibbles are the units of the signature, and they always fit in U8Var
+    let units = limbs
+        .limbs()
+        .iter()
+        .map(|limb| U8Var {
+            variable: limb.variable,
+            cs: limb.cs.clone(),
+        })
+        .collect::<Vec<_>>();
+    signature.verify(&units, public_key)?;
+
+    Ok(limbs)
+}
+
+fn check_u32_metadata(w: usize, l: usize) -> Result<()> {
+    if w != 4 || l != 8 {
+        return Err(Error::msg(
+            "Committing to a u32 requires a Winternitz key with w = 4 and l = 8.",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commitment::scalar::{commit_u32, open_u32};
+    use crate::commitment::winternitz::{Winternitz, WinternitzSignatureVar};
+    use crate::limbs::u32::{U32CompactVar, U32Var};
+    use crate::test_utils::expect_program_failure;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode};
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::test_program;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_commit_open_u32() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("value", 4, 8);
+        let public_key = secret_key.to_public_key();
+
+        let value: u32 = prng.gen();
+
+        let cs = ConstraintSystem::new_ref();
+        let (value_var, signature) = commit_u32(&cs, value, &secret_key).unwrap();
+
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        let opened_var = open_u32(&value_var, &signature_var, &public_key).unwrap();
+
+        let expected_var = U32Var::new_constant(&cs, value).unwrap();
+        opened_var.equalverify(&expected_var).unwrap();
+
+        test_program(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_open_u32_wrong_value() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("value", 4, 8);
+        let public_key = secret_key.to_public_key();
+
+        let value: u32 = prng.gen();
+
+        let cs = ConstraintSystem::new_ref();
+        let (_, signature) = commit_u32(&cs, value, &secret_key).unwrap();
+
+        let other_var = U32CompactVar::new_program_input(&cs, value ^ 0x10).unwrap();
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        open_u32(&other_var, &signature_var, &public_key).unwrap();
+
+        expect_program_failure(cs, script! {}).unwrap();
+    }
+}
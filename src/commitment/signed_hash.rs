@@ -0,0 +1,203 @@
+use crate::commitment::winternitz::{WinternitzPublicKey, WinternitzSignatureVar};
+use crate::compression::blake3::{Blake3HashVar, ToU4LimbVar};
+use crate::limbs::u32::OP_16MUL;
+use anyhow::{Error, Result};
+use bitcoin_circle_stark::treepp::*;
+use bitcoin_script_dsl::builtins::i32::I32Var;
+use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+use bitcoin_script_dsl::options::Options;
+use bitcoin_script_dsl::stack::Stack;
+
+/// The largest group of bits, covering a whole number of nibbles and of digits, that is
+/// recomposed as a single script number.
+const MAX_GROUP_BITS: usize = 28;
+
+/// Verify that `signature` signs the digest held in `hash`, where the digest is signed off-chain
+/// with `WinternitzSecretKey::sign_bytes` over its 32 bytes.
+///
+/// The digest is read as a stream of bits from the lowest bit of the first byte, which is the
+/// order of the nibbles of `hash`, and split into digits of w bits that are provided as hints.
+/// The script checks the digits against the nibbles in groups of lcm(w, 4) bits, so w must keep
+/// such a group within 28 bits, and l must be the number of digits that the 256 bits need.
+pub fn verify_signed_hash(
+    signature: &WinternitzSignatureVar,
+    public_key: &WinternitzPublicKey,
+    hash: &Blake3HashVar,
+) -> Result<()> {
+    let w = public_key.metadata.w;
+    let l = public_key.metadata.l;
+
+    let group_bits = lcm(w, 4);
+    if group_bits > MAX_GROUP_BITS {
+        return Err(Error::msg(format!(
+            "Verifying a signed hash does not support w = {}.",
+            w
+        )));
+    }
+    if l != 256usize.div_ceil(w) {
+        return Err(Error::msg(
+            "The length of the public key does not match the 256 bits of a Blake3 digest.",
+        ));
+    }
+
+    let nibbles = hash.to_u4_limbs();
+    let cs = hash.hash[0].cs();
+
+    let mut bits = vec![];
+    for nibble in nibbles.iter() {
+        for i in 0..4 {
+            bits.push((nibble.value >> i) & 1);
+        }
+    }
+    bits.resize(l * w, 0);
+
+    let mut digits = vec![];
+    for chunk in bits.chunks_exact(w) {
+        let value = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, bit)| acc | (bit << i));
+        digits.push(I32Var::new_hint(&cs, value as i32)?);
+    }
+
+    // the bits past the digest are zero on both sides, so each group only includes the
+    // nibbles and the digits that exist
+    let nibbles_per_group = group_bits / 4;
+    let digits_per_group = group_bits / w;
+    for (group_nibbles, group_digits) in nibbles
+        .chunks(nibbles_per_group)
+        .zip(digits.chunks(digits_per_group))
+    {
+        cs.insert_script_complex(
+            check_nibbles_recompose_digits,
+            group_nibbles
+                .iter()
+                .map(|nibble| nibble.variable)
+                .chain(group_digits.iter().map(|digit| digit.variables()[0])),
+            &Options::new()
+                .with_u32("w", w as u32)
+                .with_u32("num_nibbles", group_nibbles.len() as u32)
+                .with_u32("num_digits", group_digits.len() as u32),
+        )?;
+    }
+
+    signature.verify_digits(&digits, public_key)
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    let mut x = a;
+    let mut y = b;
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
+
+fn check_nibbles_recompose_digits(_: &mut Stack, options: &Options) -> Result<Script> {
+    let w = options.get_u32("w")? as usize;
+    let num_nibbles = options.get_u32("num_nibbles")? as usize;
+    let num_digits = options.get_u32("num_digits")? as usize;
+
+    // the digits are range-checked by `verify_digits`
+    Ok(script! {
+        // the highest digit is on the top of the stack
+        for _ in 1..num_digits {
+            for _ in 0..w {
+                OP_DUP OP_ADD
+            }
+            OP_ADD
+        }
+        OP_TOALTSTACK
+
+        for _ in 1..num_nibbles {
+            OP_16MUL OP_ADD
+        }
+        OP_FROMALTSTACK OP_EQUALVERIFY
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commitment::signed_hash::verify_signed_hash;
+    use crate::commitment::winternitz::{Winternitz, WinternitzSignatureVar};
+    use crate::compression::blake3::{hash, Blake3ConstantVar};
+    use crate::limbs::u32::U32Var;
+    use crate::test_utils::expect_program_failure_without_opcat;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
+    use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+    use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn signed_hash_test_case(w: usize, flip_bit: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("root", w, 256usize.div_ceil(w));
+        let public_key = secret_key.to_public_key();
+
+        let message: [u32; 8] = prng.gen();
+        let message_bytes = message
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+        let digest = *::blake3::hash(&message_bytes).as_bytes();
+        let signature = secret_key.sign_bytes(&digest);
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let mut message_vars = vec![];
+        for (i, &word) in message.iter().enumerate() {
+            let word = if flip_bit && i == 3 {
+                word ^ 0x100
+            } else {
+                word
+            };
+            message_vars.push(U32Var::new_program_input(&cs, word).unwrap());
+        }
+        let hash_var = hash(&constant, message_vars.as_slice());
+        if !flip_bit {
+            assert_eq!(hash_var.value().unwrap(), digest);
+        }
+
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        verify_signed_hash(&signature_var, &public_key, &hash_var).unwrap();
+
+        cs
+    }
+
+    #[test]
+    fn test_verify_signed_hash() {
+        for w in [3, 4, 6, 8] {
+            test_program_without_opcat(signed_hash_test_case(w, false), script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_verify_signed_hash_flipped_message_bit() {
+        expect_program_failure_without_opcat(signed_hash_test_case(4, true), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_verify_signed_hash_unsupported_w() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("root", 9, 29);
+        let signature = secret_key.sign_bytes(&[0u8; 32]);
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+        let hash_var = hash(&constant, U32Var::new_program_input(&cs, 0).unwrap());
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+
+        assert!(
+            verify_signed_hash(&signature_var, &secret_key.to_public_key(), &hash_var).is_err()
+        );
+    }
+}
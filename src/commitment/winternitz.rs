@@ -1,16 +1,28 @@
+use crate::commitment::merkle::{blake3_digest_to_words, order_children};
+use crate::compression::blake3::native::parent_reference;
+use crate::compression::blake3::{
+    blake3_parent, hash_checked, maybe_hash, Blake3ConstantVar, Blake3HashVar, IV,
+};
+use crate::limbs::bytes::BytesVar;
+use crate::limbs::u32::U32Var;
+use crate::limbs::u4::U4Var;
+use crate::watchdog::{track_alloc, track_var, WatchdogExt};
 use anyhow::{Error, Result};
+use bitcoin::hashes::{hash160, sha256d, Hash};
 use bitcoin_circle_stark::treepp::*;
 use bitcoin_script_dsl::builtins::hash::HashVar;
 use bitcoin_script_dsl::builtins::i32::I32Var;
 use bitcoin_script_dsl::builtins::u8::U8Var;
-use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode};
+use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
 use bitcoin_script_dsl::constraint_system::ConstraintSystemRef;
 use bitcoin_script_dsl::options::Options;
 use bitcoin_script_dsl::stack::Stack;
 use rand::{CryptoRng, Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
-use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Winternitz {
@@ -27,6 +39,346 @@ pub struct WinternitzMetadata {
     /// The number of units.
     /// w * l is the number of bits of the accepted message.
     pub l: usize,
+    /// The number of bytes kept from each SHA-256 output in the hash chains.
+    ///
+    /// The default is 32, i.e., no truncation. A smaller value, such as 20, shrinks the keys and
+    /// the signatures, but the chains then only offer preimage resistance of `8 * digest_bytes`
+    /// bits, and the succinct public key only offers collision resistance of `4 * digest_bytes`
+    /// bits. Bitcoin script has no opcode to truncate a stack element, so truncated digests can
+    /// only be verified off-chain.
+    #[serde(default = "default_digest_bytes")]
+    pub digest_bytes: usize,
+    /// The hash function used for the hash chains and for folding the succinct public key.
+    #[serde(default)]
+    pub hash_function: HashFunction,
+    /// How each step applies SHA-256, which must be `ChainHash::Sha256` for Blake3.
+    #[serde(default)]
+    pub chain_hash: ChainHash,
+    /// The version of the serialization format, where unknown versions are rejected.
+    #[serde(default = "default_version", deserialize_with = "deserialize_version")]
+    pub version: u8,
+}
+
+/// The current version of the serialization format of `WinternitzMetadata`.
+pub const WINTERNITZ_METADATA_VERSION: u8 = 0;
+
+fn default_version() -> u8 {
+    WINTERNITZ_METADATA_VERSION
+}
+
+fn deserialize_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let version = u8::deserialize(deserializer)?;
+    if version != WINTERNITZ_METADATA_VERSION {
+        return Err(D::Error::custom(format!(
+            "Unknown Winternitz metadata version {}, expected {}.",
+            version, WINTERNITZ_METADATA_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+/// The largest supported w. Signatures with w > 8 are verified in script by
+/// `WinternitzSignatureVar::verify_digits`.
+pub const MAX_W: usize = 16;
+
+fn default_digest_bytes() -> usize {
+    32
+}
+
+/// A hash function that can be used in the Winternitz hash chains.
+pub trait HashFn {
+    fn hash(data: &[u8]) -> Vec<u8>;
+
+    fn hash_pair(a: &[u8], b: &[u8]) -> Vec<u8> {
+        let mut data = a.to_vec();
+        data.extend_from_slice(b);
+        Self::hash(&data)
+    }
+}
+
+pub struct Sha256Hash;
+
+impl HashFn for Sha256Hash {
+    fn hash(data: &[u8]) -> Vec<u8> {
+        Sha256::digest(data).to_vec()
+    }
+}
+
+/// Blake3 over inputs of at most 1KiB, which coincides with Blake3ic that the
+/// `compression::blake3` module computes.
+pub struct Blake3Hash;
+
+impl HashFn for Blake3Hash {
+    fn hash(data: &[u8]) -> Vec<u8> {
+        blake3::hash(data).as_bytes().to_vec()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HashFunction {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashFunction {
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashFunction::Sha256 => Sha256Hash::hash(data),
+            HashFunction::Blake3 => Blake3Hash::hash(data),
+        }
+    }
+
+    pub fn hash_pair(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        match self {
+            HashFunction::Sha256 => Sha256Hash::hash_pair(a, b),
+            HashFunction::Blake3 => Blake3Hash::hash_pair(a, b),
+        }
+    }
+}
+
+/// How each step of the hash chains, and of folding the succinct public key, applies SHA-256,
+/// matching the opcode that the script uses for it.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ChainHash {
+    /// A single SHA-256, where the script applies two steps at once with OP_HASH256.
+    #[default]
+    Sha256,
+    /// Double SHA-256, i.e., OP_HASH256.
+    Hash256,
+    /// RIPEMD-160 of SHA-256, i.e., OP_HASH160, which shrinks the elements to 20 bytes.
+    Hash160,
+}
+
+impl ChainHash {
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ChainHash::Sha256 => Sha256Hash::hash(data),
+            ChainHash::Hash256 => sha256d::Hash::hash(data).to_byte_array().to_vec(),
+            ChainHash::Hash160 => hash160::Hash::hash(data).to_byte_array().to_vec(),
+        }
+    }
+
+    /// The number of bytes of each output.
+    pub fn output_bytes(&self) -> usize {
+        match self {
+            ChainHash::Sha256 | ChainHash::Hash256 => 32,
+            ChainHash::Hash160 => 20,
+        }
+    }
+
+    fn to_u32(self) -> u32 {
+        match self {
+            ChainHash::Sha256 => 0,
+            ChainHash::Hash256 => 1,
+            ChainHash::Hash160 => 2,
+        }
+    }
+
+    fn from_options(options: &Options) -> Result<Self> {
+        match options.get_u32("chain_hash").unwrap_or(0) {
+            0 => Ok(ChainHash::Sha256),
+            1 => Ok(ChainHash::Hash256),
+            2 => Ok(ChainHash::Hash160),
+            _ => Err(Error::msg("Unknown chain hash.")),
+        }
+    }
+
+    /// The script that applies `n` steps to the element on the top of the stack.
+    fn steps_script(&self, n: usize) -> Script {
+        match self {
+            ChainHash::Sha256 => script! {
+                for _ in 0..n / 2 {
+                    OP_HASH256
+                }
+                if n % 2 == 1 {
+                    OP_SHA256
+                }
+            },
+            ChainHash::Hash256 => script! {
+                for _ in 0..n {
+                    OP_HASH256
+                }
+            },
+            ChainHash::Hash160 => script! {
+                for _ in 0..n {
+                    OP_HASH160
+                }
+            },
+        }
+    }
+}
+
+impl WinternitzMetadata {
+    pub fn new(name: impl ToString, w: usize, l: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            w,
+            l,
+            digest_bytes: default_digest_bytes(),
+            hash_function: HashFunction::default(),
+            chain_hash: ChainHash::default(),
+            version: WINTERNITZ_METADATA_VERSION,
+        }
+    }
+
+    pub fn with_digest_bytes(mut self, digest_bytes: usize) -> Self {
+        self.digest_bytes = digest_bytes;
+        self
+    }
+
+    /// Panics for Blake3 unless the chain hash is `ChainHash::Sha256`.
+    pub fn with_hash_function(mut self, hash_function: HashFunction) -> Self {
+        self.hash_function = hash_function;
+        assert!(
+            self.has_valid_hashes(),
+            "Blake3 keys only use ChainHash::Sha256."
+        );
+        self
+    }
+
+    /// Panics for Blake3 unless the chain hash is `ChainHash::Sha256`.
+    pub fn with_chain_hash(mut self, chain_hash: ChainHash) -> Self {
+        self.chain_hash = chain_hash;
+        assert!(
+            self.has_valid_hashes(),
+            "Blake3 keys only use ChainHash::Sha256."
+        );
+        self
+    }
+
+    /// The chain hash only applies to SHA-256, so Blake3 keys keep the default.
+    fn has_valid_hashes(&self) -> bool {
+        self.hash_function == HashFunction::Sha256 || self.chain_hash == ChainHash::Sha256
+    }
+
+    /// The number of bytes of each key and signature element, which is `digest_bytes` unless
+    /// the chain hash has shorter outputs.
+    pub fn element_bytes(&self) -> usize {
+        self.digest_bytes.min(self.chain_hash.output_bytes())
+    }
+
+    /// Encode the metadata in the canonical form shared by the binary encodings of the public
+    /// key and the signature.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = vec![];
+        res.extend_from_slice(&(self.name.len() as u32).to_le_bytes());
+        res.extend_from_slice(self.name.as_bytes());
+        res.extend_from_slice(&(self.w as u32).to_le_bytes());
+        res.extend_from_slice(&(self.l as u32).to_le_bytes());
+        res.extend_from_slice(&(self.digest_bytes as u32).to_le_bytes());
+        // the chain hash shares the byte of the hash function, which keeps the default encoding,
+        // and every pair has its own value, as both are in the domain separator
+        res.push(match (self.hash_function, self.chain_hash) {
+            (HashFunction::Sha256, ChainHash::Sha256) => 0,
+            (HashFunction::Blake3, ChainHash::Sha256) => 1,
+            (HashFunction::Sha256, ChainHash::Hash256) => 2,
+            (HashFunction::Sha256, ChainHash::Hash160) => 3,
+            (HashFunction::Blake3, ChainHash::Hash256) => 4,
+            (HashFunction::Blake3, ChainHash::Hash160) => 5,
+        });
+        res
+    }
+
+    /// Decode the metadata written by `to_bytes` and advance the reader past it.
+    fn read_from(reader: &mut &[u8]) -> Result<Self> {
+        let name_len = read_u32(reader)?;
+        let name = String::from_utf8(read_bytes(reader, name_len)?.to_vec())?;
+        let w = read_u32(reader)?;
+        let l = read_u32(reader)?;
+        let digest_bytes = read_u32(reader)?;
+        let (hash_function, chain_hash) = match read_bytes(reader, 1)?[0] {
+            0 => (HashFunction::Sha256, ChainHash::Sha256),
+            1 => (HashFunction::Blake3, ChainHash::Sha256),
+            2 => (HashFunction::Sha256, ChainHash::Hash256),
+            3 => (HashFunction::Sha256, ChainHash::Hash160),
+            4 => (HashFunction::Blake3, ChainHash::Hash256),
+            5 => (HashFunction::Blake3, ChainHash::Hash160),
+            _ => {
+                return Err(Error::msg(
+                    "The encoded metadata has an unknown hash function.",
+                ))
+            }
+        };
+
+        if w == 0 || w > MAX_W || l == 0 || digest_bytes == 0 || digest_bytes > 32 {
+            return Err(Error::msg("The encoded metadata is invalid."));
+        }
+        Ok(Self {
+            name,
+            w,
+            l,
+            digest_bytes,
+            hash_function,
+            chain_hash,
+            version: WINTERNITZ_METADATA_VERSION,
+        })
+    }
+
+    /// The number of units for the checksum.
+    pub fn checksum_length(&self) -> usize {
+        (self.l * ((1 << self.w) - 1) + 1)
+            .next_power_of_two()
+            .ilog2()
+            .div_ceil(self.w as u32) as usize
+    }
+
+    /// Decompose the checksum into `checksum_length` digits of w bits, the lowest digit first.
+    /// This decomposition is the one that both the signer and the verifiers use.
+    pub fn checksum_digits(&self, mut checksum: usize) -> Vec<usize> {
+        let mut digits = vec![];
+        for _ in 0..self.checksum_length() {
+            digits.push(checksum & ((1 << self.w) - 1));
+            checksum >>= self.w;
+        }
+        digits
+    }
+
+    /// Fold the public key elements into the succinct public key.
+    pub fn fold(&self, elems: &[Vec<u8>]) -> Vec<u8> {
+        assert!(elems.len() > 0);
+        let mut cur = elems[0].clone();
+        for elem in elems.iter().skip(1) {
+            cur = self.hash_pair(&cur, elem);
+        }
+        cur
+    }
+
+    /// Hash a single step of the chain, truncated to `digest_bytes`.
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        let mut res = match self.hash_function {
+            HashFunction::Sha256 => self.chain_hash.hash(data),
+            HashFunction::Blake3 => self.hash_function.hash(data),
+        };
+        res.truncate(self.digest_bytes);
+        res
+    }
+
+    /// Hash two elements together, truncated to `digest_bytes`.
+    pub fn hash_pair(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        let mut res = match self.hash_function {
+            HashFunction::Sha256 => self.chain_hash.hash(&[a, b].concat()),
+            HashFunction::Blake3 => self.hash_function.hash_pair(a, b),
+        };
+        res.truncate(self.digest_bytes);
+        res
+    }
+
+    /// The domain separator for deriving the secret key, which is unchanged for the default
+    /// options so that existing keys remain the same.
+    pub(crate) fn domain_separator(&self) -> String {
+        let mut res = format!("{},{},{}", self.name, self.w, self.l);
+        if self.digest_bytes != default_digest_bytes() {
+            res.push_str(&format!(",{}", self.digest_bytes));
+        }
+        if self.hash_function != HashFunction::default() {
+            res.push_str(&format!(",{:?}", self.hash_function));
+        }
+        if self.chain_hash != ChainHash::default() {
+            res.push_str(&format!(",{:?}", self.chain_hash));
+        }
+        res
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -56,31 +408,52 @@ impl Winternitz {
     }
 
     pub fn get_secret_key(&self, name: impl ToString, w: usize, l: usize) -> WinternitzSecretKey {
-        assert!(w <= 8);
+        self.get_secret_key_from_metadata(WinternitzMetadata::new(name, w, l))
+    }
+
+    pub fn get_secret_key_with_digest_bytes(
+        &self,
+        name: impl ToString,
+        w: usize,
+        l: usize,
+        digest_bytes: usize,
+    ) -> WinternitzSecretKey {
+        self.get_secret_key_from_metadata(
+            WinternitzMetadata::new(name, w, l).with_digest_bytes(digest_bytes),
+        )
+    }
+
+    pub fn get_secret_key_from_metadata(
+        &self,
+        metadata: WinternitzMetadata,
+    ) -> WinternitzSecretKey {
+        let w = metadata.w;
+        let l = metadata.l;
+        let digest_bytes = metadata.digest_bytes;
+        assert!(w >= 1 && w <= MAX_W);
+        assert!(digest_bytes > 0 && digest_bytes <= 32);
+        assert!(
+            metadata.has_valid_hashes(),
+            "Blake3 keys only use ChainHash::Sha256."
+        );
+        let element_bytes = metadata.element_bytes();
 
         let mut sha = sha2::Sha256::new();
         Digest::update(&mut sha, &self.secret_seed);
-        Digest::update(&mut sha, format!("{},{},{}", name.to_string(), w, l));
+        Digest::update(&mut sha, metadata.domain_separator());
         let seed = sha.finalize().to_vec();
 
-        let checksum_l = (l * ((1 << w) - 1) + 1)
-            .next_power_of_two()
-            .ilog2()
-            .div_ceil(w as u32) as usize;
+        let checksum_l = metadata.checksum_length();
         let total_l = l + checksum_l;
 
         let mut prng = ChaCha20Rng::from_seed(seed.try_into().unwrap());
         let mut res = vec![];
         for _ in 0..total_l {
-            res.push(prng.gen::<[u8; 32]>().to_vec());
+            res.push(prng.gen::<[u8; 32]>()[..element_bytes].to_vec());
         }
 
         WinternitzSecretKey {
-            metadata: WinternitzMetadata {
-                name: name.to_string(),
-                w,
-                l,
-            },
+            metadata,
             secret_key: res,
         }
     }
@@ -88,8 +461,244 @@ impl Winternitz {
     pub fn get_public_key(&self, name: impl ToString, w: usize, l: usize) -> WinternitzPublicKey {
         self.get_secret_key(name, w, l).to_public_key()
     }
+
+    /// Derive the public keys for a list of `(name, w, l)` in one pass.
+    ///
+    /// With the `parallel` feature, the hash chains of different keys are computed in parallel.
+    pub fn get_public_keys(&self, names: &[(String, usize, usize)]) -> Vec<WinternitzPublicKey> {
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            names
+                .par_iter()
+                .map(|(name, w, l)| self.get_public_key(name, *w, *l))
+                .collect()
+        }
+
+        #[cfg(not(feature = "parallel"))]
+        {
+            names
+                .iter()
+                .map(|(name, w, l)| self.get_public_key(name, *w, *l))
+                .collect()
+        }
+    }
+
+    /// Derive the public keys named `{prefix}/{i}` for `i` in `0..count`, e.g., one key per
+    /// memory cell. This goes through `get_public_keys`, so it is parallel with the `parallel`
+    /// feature.
+    pub fn derive_batch(
+        &self,
+        prefix: &str,
+        count: usize,
+        w: usize,
+        l: usize,
+    ) -> Vec<WinternitzPublicKey> {
+        let names = (0..count)
+            .map(|i| (batch_key_name(prefix, i), w, l))
+            .collect::<Vec<_>>();
+        self.get_public_keys(&names)
+    }
+
+    /// Commit to the succinct public keys with a Blake3 Merkle tree, where the leaves are padded
+    /// with empty leaves to a power of two.
+    pub fn public_root(&self, keys: &[WinternitzPublicKey]) -> [u8; 32] {
+        WinternitzPublicKeySet::new(keys.to_vec()).root()
+    }
+}
+
+/// Many public keys aggregated into a Merkle tree over their succinct public keys, so that a
+/// single root commits to all of them.
+///
+/// A leaf is the Blake3 digest of a succinct public key, and an internal node is the Blake3
+/// parent compression of its two children, as `blake3_parent` computes it in script.
+#[derive(Debug, Clone)]
+pub struct WinternitzPublicKeySet {
+    pub keys: Vec<WinternitzPublicKey>,
+    /// The layers from the leaves up to the root.
+    pub layers: Vec<Vec<[u8; 32]>>,
+}
+
+impl WinternitzPublicKeySet {
+    /// Build the tree, where the leaves are padded with empty leaves to a power of two.
+    pub fn new(keys: Vec<WinternitzPublicKey>) -> Self {
+        assert!(!keys.is_empty());
+
+        let mut leaves = keys
+            .iter()
+            .map(|key| *blake3::hash(&key.succinct_public_key).as_bytes())
+            .collect::<Vec<_>>();
+        leaves.resize(
+            keys.len().next_power_of_two(),
+            *blake3::hash(&[]).as_bytes(),
+        );
+
+        let mut layers = vec![leaves];
+        while layers.last().unwrap().len() > 1 {
+            let layer = layers
+                .last()
+                .unwrap()
+                .chunks_exact(2)
+                .map(|pair| parent_node(&pair[0], &pair[1]))
+                .collect();
+            layers.push(layer);
+        }
+
+        Self { keys, layers }
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.layers.last().unwrap()[0]
+    }
+
+    /// The siblings on the path from the key at `index` to the root.
+    pub fn membership_proof(&self, index: usize) -> Result<WinternitzMembershipProof> {
+        if index >= self.keys.len() {
+            return Err(Error::msg(format!(
+                "The index {} is out of range for a set of {} keys.",
+                index,
+                self.keys.len()
+            )));
+        }
+
+        let mut siblings = vec![];
+        let mut cur = index;
+        for layer in self.layers.iter().take(self.layers.len() - 1) {
+            siblings.push(layer[cur ^ 1]);
+            cur >>= 1;
+        }
+        Ok(WinternitzMembershipProof { index, siblings })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WinternitzMembershipProof {
+    /// The position of the key in the set.
+    pub index: usize,
+    /// The siblings from the leaf level up to the level below the root.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl WinternitzMembershipProof {
+    /// The direction at each level, from the leaf upwards, where `true` means that the current
+    /// node is the right child.
+    pub fn direction_bits(&self) -> Vec<bool> {
+        (0..self.siblings.len())
+            .map(|i| (self.index >> i) & 1 == 1)
+            .collect()
+    }
+
+    pub fn verify(&self, succinct_public_key: &[u8], root: &[u8; 32]) -> Result<()> {
+        let mut cur = *blake3::hash(succinct_public_key).as_bytes();
+        for (sibling, is_right) in self.siblings.iter().zip(self.direction_bits()) {
+            cur = if is_right {
+                parent_node(sibling, &cur)
+            } else {
+                parent_node(&cur, sibling)
+            };
+        }
+
+        if &cur != root {
+            return Err(Error::msg(
+                "The membership proof does not lead to the root of the key set.",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The Blake3 parent compression of two nodes, as computed by `blake3_parent`.
+fn parent_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let words = parent_reference(
+        &blake3_digest_to_words(left),
+        &blake3_digest_to_words(right),
+        &IV,
+        0,
+    );
+
+    let mut res = [0u8; 32];
+    for (chunk, word) in res.chunks_exact_mut(4).zip(words.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    res
+}
+
+pub struct WinternitzMembershipProofVar {
+    pub siblings: Vec<Blake3HashVar>,
+}
+
+impl WinternitzMembershipProofVar {
+    /// Allocate the siblings of the proof as hints, with their limbs checked to be in range.
+    pub fn new_hint(cs: &ConstraintSystemRef, proof: &WinternitzMembershipProof) -> Result<Self> {
+        let mut siblings = vec![];
+        for sibling in proof.siblings.iter() {
+            siblings.push(Blake3HashVar::new_hint(cs, *sibling)?);
+        }
+        Ok(Self { siblings })
+    }
 }
 
+/// Many public keys that are checked in script against a single commitment, instead of
+/// allocating the elements of each key as constants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregatedWinternitzKey {
+    keys: Vec<WinternitzPublicKey>,
+    commitment: Vec<u8>,
+}
+
+impl AggregatedWinternitzKey {
+    pub fn keys(&self) -> &[WinternitzPublicKey] {
+        &self.keys
+    }
+
+    /// The SHA-256 fold of the succinct public keys of `keys`.
+    pub fn commitment(&self) -> &[u8] {
+        &self.commitment
+    }
+}
+
+/// Verify in script that `succinct_public_key` is in the set with the given root, where
+/// `direction_bits` are those of the membership proof. Each level is one `blake3_parent`
+/// compression of the two children.
+pub fn verify_membership(
+    constant: &Blake3ConstantVar,
+    succinct_public_key: &BytesVar,
+    proof: &WinternitzMembershipProofVar,
+    direction_bits: &[U4Var],
+    root: &Blake3HashVar,
+) -> Result<()> {
+    if direction_bits.len() != proof.siblings.len() {
+        return Err(Error::msg(
+            "The number of direction bits does not match the depth of the membership proof.",
+        ));
+    }
+
+    let mut cur = hash_checked(constant, succinct_public_key.clone())?;
+    for (sibling, direction_bit) in proof.siblings.iter().zip(direction_bits.iter()) {
+        let children = order_children(&cur, sibling, direction_bit)?;
+
+        let mut words = vec![];
+        for limbs in children.chunks_exact(8) {
+            words.push(U32Var::from_limbs(limbs.to_vec().try_into().unwrap())?);
+        }
+        let right = Blake3HashVar {
+            hash: words.split_off(8).try_into().unwrap(),
+        };
+        let left = Blake3HashVar {
+            hash: words.try_into().unwrap(),
+        };
+        cur = blake3_parent(constant, &left, &right);
+    }
+
+    cur.equalverify(root)
+}
+
+/// The name of the `i`-th key derived by `Winternitz::derive_batch`.
+pub fn batch_key_name(prefix: &str, i: usize) -> String {
+    format!("{}/{}", prefix, i)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct WinternitzSignature {
     /// The metadata.
     pub metadata: WinternitzMetadata,
@@ -99,14 +708,140 @@ pub struct WinternitzSignature {
     pub signature_checksum: Vec<Vec<u8>>,
 }
 
+impl WinternitzSignature {
+    /// Concatenate the hash outputs of the messages and then of the checksum.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = vec![];
+        for s in self
+            .signature_messages
+            .iter()
+            .chain(self.signature_checksum.iter())
+        {
+            res.extend_from_slice(s);
+        }
+        res
+    }
+
+    /// Encode the metadata once, followed by `to_bytes`, so that the signature can be decoded
+    /// without knowing the public key.
+    pub fn to_wire_bytes(&self) -> Vec<u8> {
+        let mut res = self.metadata.to_bytes();
+        res.extend(self.to_bytes());
+        res
+    }
+
+    pub fn from_wire_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let metadata = WinternitzMetadata::read_from(&mut reader)?;
+        Self::from_bytes(&metadata, reader)
+    }
+
+    pub fn from_bytes(metadata: &WinternitzMetadata, bytes: &[u8]) -> Result<Self> {
+        let checksum_l = metadata.checksum_length();
+        let total_l = metadata.l + checksum_l;
+
+        if bytes.len() != total_l * metadata.element_bytes() {
+            return Err(Error::msg(format!(
+                "The signature has {} bytes, but {} bytes are expected.",
+                bytes.len(),
+                total_l * metadata.element_bytes()
+            )));
+        }
+
+        let mut chunks = bytes.chunks_exact(metadata.element_bytes());
+        let signature_messages = chunks
+            .by_ref()
+            .take(metadata.l)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        let signature_checksum = chunks.map(|chunk| chunk.to_vec()).collect();
+
+        Ok(Self {
+            metadata: metadata.clone(),
+            signature_messages,
+            signature_checksum,
+        })
+    }
+}
+
+/// Expand bytes into bits, from the lowest bit of each byte to the highest.
+pub fn bytes_to_bits(data: &[u8]) -> Vec<bool> {
+    let mut bits = vec![];
+    for byte in data.iter() {
+        for i in 0..8 {
+            bits.push((byte >> i) & 1 == 1);
+        }
+    }
+    bits
+}
+
+/// Expand a Blake3 digest held as eight words into bits, with each word read in little-endian,
+/// which matches the bytes of `Blake3HashVar::to_u8_vars`.
+pub fn blake3_words_to_bits(words: &[u32; 8]) -> Vec<bool> {
+    let bytes = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect::<Vec<_>>();
+    bytes_to_bits(&bytes)
+}
+
 impl WinternitzSecretKey {
+    /// Sign the bytes, whose bits are split into units of `w` bits from the lowest bit of the
+    /// first byte, consistently with `WinternitzSignatureVar::verify_bytes`.
+    pub fn sign_bytes(&self, data: &[u8]) -> WinternitzSignature {
+        self.sign(&bytes_to_bits(data))
+    }
+
+    /// Sign and consume the key, so that it cannot sign a second message. Keys derived again
+    /// by name are not covered, for which `Keystore::sign_winternitz_once` keeps a record.
+    pub fn sign_once(self, data: &[bool]) -> WinternitzSignature {
+        self.sign(data)
+    }
+
     pub fn sign(&self, data: &[bool]) -> WinternitzSignature {
+        self.sign_with_chain(data, |start, t| {
+            let mut cur = start.to_vec();
+            for _ in 0..t {
+                cur = self.metadata.hash(&cur);
+            }
+            cur
+        })
+    }
+
+    /// Sign as `sign` does, but apply all the `(1 << w) - 1` steps of every chain and select
+    /// the signature element without branching, so that the time does not depend on the
+    /// message. This takes about twice as long as `sign` on average, as many steps as
+    /// `to_public_key`. Verification only handles public data, so it needs no such variant.
+    pub fn sign_constant_time(&self, data: &[bool]) -> WinternitzSignature {
+        self.sign_with_chain(data, |start, t| {
+            let mut res = start.to_vec();
+            let mut cur = start.to_vec();
+            for step in 1..(1usize << self.metadata.w) {
+                cur = self.metadata.hash(&cur);
+
+                // all ones if step == t, and zero otherwise
+                let diff = (step ^ t) as u64;
+                let mask = ((((diff | diff.wrapping_neg()) >> 63) as u8) ^ 1).wrapping_neg();
+                for (r, c) in res.iter_mut().zip(cur.iter()) {
+                    *r = (*r & !mask) | (c & mask);
+                }
+            }
+            res
+        })
+    }
+
+    /// Sign, where `chain(secret_key_elem, t)` applies `t` steps of the hash chain.
+    fn sign_with_chain(
+        &self,
+        data: &[bool],
+        chain: impl Fn(&[u8], usize) -> Vec<u8>,
+    ) -> WinternitzSignature {
         assert!(data.len() <= self.metadata.l * self.metadata.w);
 
         let mut data = data.to_vec();
         data.resize(self.metadata.l * self.metadata.w, false);
 
-        let mut checksum = 0u32;
+        let mut checksum = 0usize;
 
         let mut signature_messages = vec![];
         for (secret_key, slice) in self
@@ -115,53 +850,23 @@ impl WinternitzSecretKey {
             .take(self.metadata.l)
             .zip(data.chunks_exact(self.metadata.w))
         {
-            let mut t = 0;
-            for i in 0..self.metadata.w {
-                if slice[i] {
-                    t |= 1 << i;
-                }
+            let mut t = 0usize;
+            for (i, &bit) in slice.iter().enumerate() {
+                t |= (bit as usize) << i;
             }
 
             checksum += (1 << self.metadata.w) - 1 - t;
-
-            let mut cur = secret_key.to_vec();
-            for _ in 0..t {
-                cur = sha2::Sha256::digest(&cur).to_vec();
-            }
-            signature_messages.push(cur);
-        }
-
-        let checksum_l = (self.metadata.l * ((1 << self.metadata.w) - 1) + 1)
-            .next_power_of_two()
-            .ilog2()
-            .div_ceil(self.metadata.w as u32) as usize;
-
-        let mut checksum_bits = vec![];
-        while checksum != 0 {
-            checksum_bits.push(checksum & 1 == 1);
-            checksum >>= 1;
+            signature_messages.push(chain(secret_key, t));
         }
-        checksum_bits.resize(checksum_l * self.metadata.w, false);
 
         let mut signature_checksum = vec![];
-        for (secret_key, slice) in self
+        for (secret_key, &t) in self
             .secret_key
             .iter()
             .skip(self.metadata.l)
-            .zip(checksum_bits.chunks_exact(self.metadata.w))
+            .zip(self.metadata.checksum_digits(checksum).iter())
         {
-            let mut t = 0;
-            for i in 0..self.metadata.w {
-                if slice[i] {
-                    t |= 1 << i;
-                }
-            }
-
-            let mut cur = secret_key.to_vec();
-            for _ in 0..t {
-                cur = sha2::Sha256::digest(&cur).to_vec();
-            }
-            signature_checksum.push(cur);
+            signature_checksum.push(chain(secret_key, t));
         }
 
         WinternitzSignature {
@@ -176,41 +881,97 @@ impl WinternitzSecretKey {
         for key in self.secret_key.iter() {
             let mut cur = key.to_vec();
             for _ in 0..((1 << self.metadata.w) - 1) {
-                cur = Sha256::digest(&cur).to_vec();
+                cur = self.metadata.hash(&cur);
             }
             res.push(cur);
         }
 
-        assert!(res.len() > 0);
-        let mut cur = res[0].clone();
-        for key in res.iter().skip(1) {
-            let mut sha256 = Sha256::new();
-            sha256.update(&cur);
-            sha256.update(key);
-            cur = sha256.finalize().to_vec();
-        }
-
         WinternitzPublicKey {
             metadata: self.metadata.clone(),
+            succinct_public_key: self.metadata.fold(&res),
             public_key: res,
-            succinct_public_key: cur,
         }
     }
 }
 
 impl WinternitzPublicKey {
-    pub fn verify(&self, data: &[bool], signature: &WinternitzSignature) -> Result<()> {
-        assert_eq!(data.len(), self.metadata.l * self.metadata.w);
-        assert_eq!(self.metadata, signature.metadata);
-        assert_eq!(signature.signature_messages.len(), self.metadata.l);
-        assert_eq!(
-            signature.signature_checksum.len(),
-            self.succinct_public_key.len() - self.metadata.l
-        );
-
-        let mut checksum = 0u32;
+    /// Aggregate the keys under a single commitment, which is the SHA-256 fold of their
+    /// succinct public keys. There must be at least one key.
+    pub fn aggregate(keys: &[WinternitzPublicKey]) -> Result<AggregatedWinternitzKey> {
+        if keys.is_empty() {
+            return Err(Error::msg(
+                "There are no Winternitz public keys to aggregate.",
+            ));
+        }
 
-        let mut hashes = vec![];
+        let mut commitment = keys[0].succinct_public_key.clone();
+        for key in keys.iter().skip(1) {
+            commitment = Sha256Hash::hash_pair(&commitment, &key.succinct_public_key);
+        }
+
+        Ok(AggregatedWinternitzKey {
+            keys: keys.to_vec(),
+            commitment,
+        })
+    }
+
+    /// Encode the metadata, the public key elements, and the succinct public key in a
+    /// canonical form, so that the prover and the verifier derive the same script.
+    pub fn to_script_pubkey_bytes(&self) -> Vec<u8> {
+        let mut res = self.metadata.to_bytes();
+        for elem in self.public_key.iter() {
+            res.extend_from_slice(elem);
+        }
+        res.extend_from_slice(&self.succinct_public_key);
+        res
+    }
+
+    pub fn from_script_pubkey_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+
+        let metadata = WinternitzMetadata::read_from(&mut reader)?;
+        let (l, element_bytes) = (metadata.l, metadata.element_bytes());
+
+        let total_l = l + metadata.checksum_length();
+        let mut public_key = vec![];
+        for _ in 0..total_l {
+            public_key.push(read_bytes(&mut reader, element_bytes)?.to_vec());
+        }
+        let succinct_public_key = read_bytes(&mut reader, element_bytes)?.to_vec();
+        if !reader.is_empty() {
+            return Err(Error::msg("The encoded public key has trailing bytes."));
+        }
+
+        if metadata.fold(&public_key) != succinct_public_key {
+            return Err(Error::msg(
+                "The encoded public key does not match its succinct public key.",
+            ));
+        }
+
+        Ok(Self {
+            metadata,
+            public_key,
+            succinct_public_key,
+        })
+    }
+
+    pub fn verify(&self, data: &[bool], signature: &WinternitzSignature) -> Result<()> {
+        if signature.metadata.chain_hash != self.metadata.chain_hash {
+            return Err(Error::msg(
+                "The signature and the public key use different chain hashes.",
+            ));
+        }
+        assert_eq!(data.len(), self.metadata.l * self.metadata.w);
+        assert_eq!(self.metadata, signature.metadata);
+        assert_eq!(signature.signature_messages.len(), self.metadata.l);
+        assert_eq!(
+            signature.signature_checksum.len(),
+            self.public_key.len() - self.metadata.l
+        );
+
+        let mut checksum = 0usize;
+
+        let mut hashes = vec![];
 
         for (signature, slice) in signature
             .signature_messages
@@ -229,79 +990,133 @@ impl WinternitzPublicKey {
 
             let mut cur = signature.to_vec();
             for _ in 0..t {
-                cur = Sha256::digest(&cur).to_vec();
+                cur = self.metadata.hash(&cur);
             }
             hashes.push(cur);
         }
 
-        let checksum_l = (self.metadata.l * ((1 << self.metadata.w) - 1) + 1)
-            .next_power_of_two()
-            .ilog2()
-            .div_ceil(self.metadata.w as u32) as usize;
-
-        let mut checksum_bits = vec![];
-        while checksum != 0 {
-            checksum_bits.push(checksum & 1 == 1);
-            checksum >>= 1;
-        }
-        checksum_bits.resize(checksum_l * self.metadata.w, false);
-
-        for (signature, slice) in signature
+        for (signature, &t) in signature
             .signature_checksum
             .iter()
-            .zip(checksum_bits.chunks_exact(self.metadata.w))
+            .zip(self.metadata.checksum_digits(checksum).iter())
         {
-            let mut t = 0;
-            for i in 0..self.metadata.w {
-                if slice[i] {
-                    t |= 1 << i;
-                }
-            }
-
             let t = (1 << self.metadata.w) - 1 - t;
 
             let mut cur = signature.to_vec();
             for _ in 0..t {
-                cur = Sha256::digest(&cur).to_vec();
+                cur = self.metadata.hash(&cur);
             }
             hashes.push(cur);
         }
 
-        assert!(hashes.len() > 0);
-        let mut cur = hashes[0].clone();
-        for key in hashes.iter().skip(1) {
-            let mut sha256 = Sha256::new();
-            sha256.update(&cur);
-            sha256.update(key);
-            cur = sha256.finalize().to_vec();
+        assert_eq!(hashes.len(), self.public_key.len());
+        for (index, (hash, public_key_elem)) in
+            hashes.iter().zip(self.public_key.iter()).enumerate()
+        {
+            if hash != public_key_elem {
+                return Err(WinternitzError::LimbMismatch { index }.into());
+            }
         }
 
-        if cur != *self.succinct_public_key {
-            return Err(Error::msg("The signature does not match the public key."));
+        if self.metadata.fold(&hashes) != self.succinct_public_key {
+            return Err(WinternitzError::SuccinctPublicKeyMismatch.into());
         }
 
         Ok(())
     }
 }
 
+fn read_bytes<'a>(reader: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if reader.len() < len {
+        return Err(Error::msg("The encoding is truncated."));
+    }
+    let (head, tail) = reader.split_at(len);
+    *reader = tail;
+    Ok(head)
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<usize> {
+    Ok(u32::from_le_bytes(read_bytes(reader, 4)?.try_into().unwrap()) as usize)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WinternitzError {
+    /// The hash chain at `index` does not end at the corresponding public key element.
+    /// Indices from `l` onwards refer to the checksum.
+    LimbMismatch { index: usize },
+    /// The public key elements do not fold into the succinct public key.
+    SuccinctPublicKeyMismatch,
+}
+
+impl std::fmt::Display for WinternitzError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WinternitzError::LimbMismatch { index } => write!(
+                f,
+                "The signature does not match the public key at position {}.",
+                index
+            ),
+            WinternitzError::SuccinctPublicKeyMismatch => {
+                write!(f, "The signature does not match the succinct public key.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WinternitzError {}
+
 pub struct WinternitzSignatureVar {
+    pub metadata: WinternitzMetadata,
     pub signature_messages: Vec<HashVar>,
     pub signature_checksum: Vec<HashVar>,
 }
 
+/// Only SHA-256 hash chains can be verified with a single stack element per hash, so keys and
+/// signatures that use Blake3 are rejected before any gadget is inserted, and are verified by
+/// `WinternitzBlake3SignatureVar` instead.
+fn check_script_hash_function(metadata: &WinternitzMetadata) -> Result<()> {
+    if metadata.hash_function != HashFunction::Sha256 {
+        return Err(Error::msg(
+            "Only SHA-256 hash chains can be verified as single stack elements, use WinternitzBlake3SignatureVar for Blake3.",
+        ));
+    }
+    Ok(())
+}
+
+fn check_blake3_metadata(metadata: &WinternitzMetadata) -> Result<()> {
+    if metadata.hash_function != HashFunction::Blake3 {
+        return Err(Error::msg(
+            "WinternitzBlake3SignatureVar only verifies hash chains that use Blake3.",
+        ));
+    }
+    if metadata.element_bytes() != 32 {
+        return Err(Error::msg(
+            "Truncated Winternitz digests cannot be verified in Bitcoin script.",
+        ));
+    }
+    Ok(())
+}
+
 impl WinternitzSignatureVar {
     pub fn from_signature(
         cs: &ConstraintSystemRef,
         signature: &WinternitzSignature,
         allocation_mode: AllocationMode,
     ) -> Result<Self> {
-        let message_l = signature.metadata.l;
-        let checksum_l = (signature.metadata.l * ((1 << signature.metadata.w) - 1) + 1)
-            .next_power_of_two()
-            .ilog2()
-            .div_ceil(signature.metadata.w as u32) as usize;
-        assert_eq!(signature.signature_messages.len(), message_l);
-        assert_eq!(signature.signature_checksum.len(), checksum_l);
+        if signature.metadata.element_bytes() != signature.metadata.chain_hash.output_bytes() {
+            return Err(Error::msg(
+                "Truncated Winternitz digests cannot be verified in Bitcoin script.",
+            ));
+        }
+        check_script_hash_function(&signature.metadata)?;
+
+        if signature.signature_messages.len() != signature.metadata.l
+            || signature.signature_checksum.len() != signature.metadata.checksum_length()
+        {
+            return Err(Error::msg(
+                "The number of signature elements does not match the metadata.",
+            ));
+        }
 
         let mut signature_messages = vec![];
         for s in signature.signature_messages.iter() {
@@ -313,7 +1128,14 @@ impl WinternitzSignatureVar {
             signature_checksum.push(HashVar::new_variable(&cs, s.clone(), allocation_mode)?);
         }
 
+        if allocation_mode != AllocationMode::Constant {
+            for s in signature_messages.iter().chain(signature_checksum.iter()) {
+                track_alloc(cs, s.variable);
+            }
+        }
+
         Ok(Self {
+            metadata: signature.metadata.clone(),
             signature_messages,
             signature_checksum,
         })
@@ -321,180 +1143,1851 @@ impl WinternitzSignatureVar {
 }
 
 impl WinternitzSignatureVar {
+    /// Verify the signature over units of w bits, each held in a U8Var, which supports
+    /// w up to 8. When w < 8, each unit is checked to be within 0..2^w.
     pub fn verify(&self, bytes: &[U8Var], public_key: &WinternitzPublicKey) -> Result<()> {
-        let mut cs = bytes[0].cs.clone();
-        for byte in bytes.iter().skip(1) {
-            cs = cs.and(&byte.cs);
+        let public_key_variables =
+            self.public_key_variables(bytes, public_key, &mut HashMap::new())?;
+        self.verify_with_public_key_variables(
+            &self.cs(bytes),
+            bytes,
+            &public_key.metadata,
+            &public_key_variables,
+        )
+    }
+
+    /// Verify a signature produced by `WinternitzSecretKey::sign_bytes`, where each element of
+    /// `data` is a full byte. When w < 8, each byte is split into units of w bits, which are
+    /// provided as hints and checked to recompose the byte.
+    pub fn verify_bytes(&self, data: &[U8Var], public_key: &WinternitzPublicKey) -> Result<()> {
+        let w = public_key.metadata.w;
+        if 8 % w != 0 {
+            return Err(Error::msg(
+                "Verifying bytes requires w to be a divisor of 8.",
+            ));
         }
-        for signature in self.signature_messages.iter() {
-            cs = cs.and(&signature.cs);
+        if data.len() * 8 != public_key.metadata.l * w {
+            return Err(Error::msg(
+                "The number of bytes does not match the length of the public key.",
+            ));
         }
-        for signature in self.signature_checksum.iter() {
-            cs = cs.and(&signature.cs);
+        if w == 8 {
+            return self.verify(data, public_key);
         }
 
-        let mut checksum = I32Var::new_constant(
-            &cs,
-            (((1 << public_key.metadata.w) - 1) * public_key.metadata.l) as i32,
-        )?;
+        let num_digits = 8 / w;
+        let mut digits = vec![];
+        for byte in data.iter() {
+            let cs = byte.cs();
+            let mut value = byte.value()?;
+
+            let mut byte_digits = vec![];
+            for _ in 0..num_digits {
+                byte_digits
+                    .push(U8Var::new_hint(&cs, value & ((1 << w) - 1) as u8).map(track_var)?);
+                value >>= w;
+            }
+
+            cs.insert_gadget_complex(
+                "check_digits_recompose",
+                check_digits_recompose,
+                std::iter::once(byte.variable)
+                    .chain(byte_digits.iter().map(|digit| digit.variable)),
+                &Options::new()
+                    .with_u32("w", w as u32)
+                    .with_u32("num_digits", num_digits as u32),
+            )?;
+
+            digits.extend(byte_digits);
+        }
+
+        self.verify(&digits, public_key)
+    }
+
+    /// Verify the signature against a commitment to the succinct public key, so that the
+    /// public key elements do not need to be in the script.
+    ///
+    /// The public key elements are recomputed from the signature and supplied as hints. The
+    /// script checks each of them against its hash chain, folds them with OP_CAT, and compares
+    /// the result with the commitment.
+    pub fn verify_with_committed_pubkey(
+        &self,
+        bytes: &[U8Var],
+        succinct_pubkey_commitment: &[u8; 32],
+    ) -> Result<()> {
+        if self.metadata.chain_hash.output_bytes() != 32 {
+            return Err(Error::msg(
+                "A committed public key requires a chain hash with 32-byte outputs.",
+            ));
+        }
+        let w = self.metadata.w;
+        let max_digit = (1usize << w) - 1;
+
+        let mut digits = vec![];
+        let mut checksum = 0;
         for byte in bytes.iter() {
-            checksum = &checksum - byte;
+            let digit = byte.value()? as usize;
+            if digit > max_digit {
+                return Err(Error::msg(format!(
+                    "The message unit {} does not fit in {} bits.",
+                    digit, w
+                )));
+            }
+            checksum += max_digit - digit;
+            digits.push(digit);
+        }
+        digits.extend(self.metadata.checksum_digits(checksum));
+
+        let mut public_key_elems = vec![];
+        for (signature, digit) in self
+            .signature_messages
+            .iter()
+            .chain(self.signature_checksum.iter())
+            .zip(digits.iter())
+        {
+            let mut cur = signature.value()?;
+            for _ in *digit..max_digit {
+                cur = self.metadata.hash(&cur);
+            }
+            public_key_elems.push(cur);
         }
 
-        assert_eq!(bytes.len(), public_key.metadata.l);
+        self.verify_with_public_key_hints(bytes, &public_key_elems, succinct_pubkey_commitment)
+    }
 
-        let checksum_l = (public_key.metadata.l * ((1 << public_key.metadata.w) - 1) + 1)
-            .next_power_of_two()
-            .ilog2()
-            .div_ceil(public_key.metadata.w as u32) as usize;
+    /// Verify the signature over the first `bytes.len()` units of a message of `total_l` units,
+    /// for a key provisioned for more units than this transaction reveals.
+    ///
+    /// The unrevealed units are recovered from the signature and supplied as hints. Their chains
+    /// are still checked against the public key, so the checksum covers the whole message.
+    pub fn verify_prefix(
+        &self,
+        bytes: &[U8Var],
+        public_key: &WinternitzPublicKey,
+        total_l: usize,
+    ) -> Result<()> {
+        check_script_hash_function(&public_key.metadata)?;
+        if public_key.metadata.w > 8 {
+            return Err(Error::msg(
+                "Units of more than 8 bits do not fit in U8Var, use verify_digits instead.",
+            ));
+        }
+        if total_l != public_key.metadata.l {
+            return Err(Error::msg(
+                "The total number of units does not match the length of the public key.",
+            ));
+        }
+        if bytes.is_empty() || bytes.len() > total_l {
+            return Err(Error::msg(
+                "The prefix must be nonempty and no longer than the message committed by the public key.",
+            ));
+        }
+        if self.signature_messages.len() != total_l {
+            return Err(Error::msg(
+                "The number of signature elements does not match the length of the public key.",
+            ));
+        }
 
-        assert_eq!(self.signature_messages.len(), public_key.metadata.l);
-        assert_eq!(self.signature_checksum.len(), checksum_l);
+        let max_digit = (1usize << public_key.metadata.w) - 1;
 
-        for ((byte, signature), public_key_elem) in bytes
+        let mut suffix = vec![];
+        for (signature, public_key_elem) in self
+            .signature_messages
             .iter()
-            .zip(self.signature_messages.iter())
-            .zip(public_key.public_key.iter().take(public_key.metadata.l))
+            .zip(public_key.public_key.iter())
+            .skip(bytes.len())
         {
-            cs.insert_script_complex(
-                apply_and_check_repeated_hash,
-                [
-                    HashVar::new_constant(&cs, public_key_elem.clone())?.variable,
-                    signature.variable,
-                    byte.variable,
-                ],
-                &Options::new().with_u32("w", public_key.metadata.w as u32),
+            // the unit is the number of hashes that the chain still needs to reach the public key
+            let mut cur = signature.value()?;
+            let mut remaining = 0;
+            while &cur != public_key_elem {
+                if remaining == max_digit {
+                    return Err(Error::msg(
+                        "The signature over an unrevealed unit does not match the public key.",
+                    ));
+                }
+                cur = public_key.metadata.hash(&cur);
+                remaining += 1;
+            }
+            suffix.push((max_digit - remaining) as u8);
+        }
+
+        self.verify_with_suffix_hints(bytes, &suffix, public_key)
+    }
+
+    fn verify_with_suffix_hints(
+        &self,
+        bytes: &[U8Var],
+        suffix: &[u8],
+        public_key: &WinternitzPublicKey,
+    ) -> Result<()> {
+        let cs = self.cs(bytes);
+        let w = public_key.metadata.w;
+
+        let mut digits = bytes.to_vec();
+        for &digit in suffix.iter() {
+            let digit_var = U8Var::new_hint(&cs, digit).map(track_var)?;
+            cs.insert_gadget_complex(
+                "check_digit_range",
+                check_digit_range,
+                [digit_var.variable],
+                &Options::new().with_u32("w", w as u32),
             )?;
+            digits.push(digit_var);
+        }
+
+        self.verify(&digits, public_key)
+    }
+
+    fn verify_with_public_key_hints(
+        &self,
+        bytes: &[U8Var],
+        public_key_elems: &[Vec<u8>],
+        succinct_pubkey_commitment: &[u8; 32],
+    ) -> Result<()> {
+        let cs = self.cs(bytes);
+
+        let mut public_key_variables = vec![];
+        for public_key_elem in public_key_elems.iter() {
+            public_key_variables
+                .push(track_var(HashVar::new_hint(&cs, public_key_elem.clone())?).variable);
+        }
+
+        self.verify_with_public_key_variables(&cs, bytes, &self.metadata, &public_key_variables)?;
+
+        let commitment = HashVar::new_constant(&cs, succinct_pubkey_commitment.to_vec())?;
+        cs.insert_gadget_complex(
+            "fold_and_check_public_key",
+            fold_and_check_public_key,
+            public_key_variables
+                .iter()
+                .copied()
+                .chain(std::iter::once(commitment.variable)),
+            &Options::new()
+                .with_u32("n", public_key_variables.len() as u32)
+                .with_u32("chain_hash", self.metadata.chain_hash.to_u32()),
+        )?;
+
+        Ok(())
+    }
+
+    /// Verify the signature over digits of w bits each, which supports any w up to `MAX_W`.
+    ///
+    /// The digits are range-checked in script, and the checksum is decomposed into digits
+    /// provided as hints.
+    pub fn verify_digits(&self, digits: &[I32Var], public_key: &WinternitzPublicKey) -> Result<()> {
+        let metadata = &public_key.metadata;
+        check_script_hash_function(metadata)?;
+        let w = metadata.w;
+        let max_digit = (1i32 << w) - 1;
+
+        if digits.len() != metadata.l {
+            return Err(Error::msg(
+                "The number of digits does not match the length of the public key.",
+            ));
+        }
+
+        if self.metadata.chain_hash != metadata.chain_hash {
+            return Err(Error::msg(
+                "The signature and the public key use different chain hashes.",
+            ));
+        }
+
+        let checksum_l = metadata.checksum_length();
+        assert_eq!(self.signature_messages.len(), metadata.l);
+        assert_eq!(self.signature_checksum.len(), checksum_l);
+
+        let cs = self.cs(digits);
+
+        let mut checksum_value = 0;
+        for digit in digits.iter() {
+            checksum_value += max_digit - digit.value()?;
         }
+        cs.insert_gadget_complex(
+            "winternitz_checksum",
+            winternitz_checksum,
+            digits.iter().map(|digit| digit.variables()[0]),
+            &Options::new()
+                .with_u32("w", w as u32)
+                .with_u32("l", metadata.l as u32),
+        )?;
+        let checksum = I32Var::new_function_output(&cs, checksum_value).map(track_var)?;
 
-        let checksum_bytes = checksum.to_positive_limbs(checksum_l, public_key.metadata.w)?;
-        assert_eq!(checksum_bytes.len(), checksum_l);
+        let mut checksum_digits = vec![];
+        for _ in 0..checksum_l {
+            checksum_digits.push(I32Var::new_hint(&cs, checksum_value & max_digit).map(track_var)?);
+            checksum_value >>= w;
+        }
+        cs.insert_gadget_complex(
+            "check_digits_recompose",
+            check_digits_recompose,
+            std::iter::once(checksum.variables()[0])
+                .chain(checksum_digits.iter().map(|digit| digit.variables()[0])),
+            &Options::new()
+                .with_u32("w", w as u32)
+                .with_u32("num_digits", checksum_l as u32),
+        )?;
 
-        for ((byte, signature), public_key_elem) in checksum_bytes
+        let mut cache = HashMap::new();
+        for ((digit, signature), public_key_elem) in digits
             .iter()
-            .zip(self.signature_checksum.iter())
-            .zip(public_key.public_key.iter().skip(public_key.metadata.l))
+            .chain(checksum_digits.iter())
+            .zip(
+                self.signature_messages
+                    .iter()
+                    .chain(self.signature_checksum.iter()),
+            )
+            .zip(public_key.public_key.iter())
         {
-            cs.insert_script_complex(
+            cs.insert_gadget_complex(
+                "apply_and_check_repeated_hash",
                 apply_and_check_repeated_hash,
                 [
-                    HashVar::new_constant(&cs, public_key_elem.clone())?.variable,
+                    get_or_alloc_public_key_elem(&cs, &mut cache, public_key_elem)?,
                     signature.variable,
-                    byte.variable,
+                    digit.variables()[0],
                 ],
-                &Options::new().with_u32("w", public_key.metadata.w as u32),
+                &Options::new()
+                    .with_u32("w", w as u32)
+                    .with_u32("range_check", 1)
+                    .with_u32("chain_hash", metadata.chain_hash.to_u32()),
             )?;
         }
 
         Ok(())
     }
-}
 
-fn apply_and_check_repeated_hash(_: &mut Stack, options: &Options) -> Result<Script> {
-    let w = options.get_u32("w")? as usize;
+    /// Allocate the public key elements as constants, each only once across all the calls that
+    /// share the same `cache`.
+    fn public_key_variables(
+        &self,
+        bytes: &[U8Var],
+        public_key: &WinternitzPublicKey,
+        cache: &mut HashMap<Vec<u8>, usize>,
+    ) -> Result<Vec<usize>> {
+        // check the length before allocating any public key element
+        if bytes.len() != public_key.metadata.l {
+            return Err(Error::msg(format!(
+                "The message has {} units, but the public key is for {} units.",
+                bytes.len(),
+                public_key.metadata.l
+            )));
+        }
 
-    Ok(script! {
-        { (1 << w) - 1 } OP_SWAP OP_SUB
-        OP_TOALTSTACK
+        let cs = self.cs(bytes);
 
-        for i in 0..w {
-            OP_FROMALTSTACK
+        let mut public_key_variables = vec![];
+        for public_key_elem in public_key.public_key.iter() {
+            public_key_variables.push(get_or_alloc_public_key_elem(&cs, cache, public_key_elem)?);
+        }
+        Ok(public_key_variables)
+    }
 
-            if i != w - 1 {
-                OP_DUP { 1 << (w - 1 - i) } OP_GREATERTHANOREQUAL OP_IF
-                    { 1 << (w - 1 - i) } OP_SUB OP_TOALTSTACK
-                    for _ in 0..1 << (w - 2 - i) {
-                        OP_HASH256
-                    }
-                OP_ELSE
-                    OP_TOALTSTACK
-                OP_ENDIF
-            } else {
-                OP_IF
-                    OP_SHA256
-                OP_ENDIF
+    /// Verify one signature per key of the aggregate, where `messages[i]` is signed under the
+    /// `i`-th key. The public key elements are hints, which are folded in script into each
+    /// succinct public key, and then into a single comparison with the aggregate commitment.
+    pub fn verify_batch(
+        signatures: &[WinternitzSignatureVar],
+        messages: &[Vec<U8Var>],
+        agg: &AggregatedWinternitzKey,
+    ) -> Result<()> {
+        if agg.keys.is_empty() {
+            return Err(Error::msg("The aggregated key has no public keys."));
+        }
+        if signatures.len() != agg.keys.len() || messages.len() != agg.keys.len() {
+            return Err(Error::msg(
+                "The numbers of signatures and messages do not match the aggregated key.",
+            ));
+        }
+        for (bytes, key) in messages.iter().zip(agg.keys.iter()) {
+            if bytes.len() != key.metadata.l {
+                return Err(Error::msg(format!(
+                    "The message has {} units, but the public key is for {} units.",
+                    bytes.len(),
+                    key.metadata.l
+                )));
             }
         }
 
-        OP_EQUALVERIFY
-    })
-}
+        let mut cs = signatures[0].cs(&messages[0]);
+        for (signature, bytes) in signatures.iter().zip(messages.iter()).skip(1) {
+            cs = cs.and(&signature.cs(bytes));
+        }
 
-#[cfg(test)]
-mod test {
-    use crate::commitment::winternitz::{Winternitz, WinternitzSignatureVar};
-    use bitcoin_circle_stark::treepp::*;
-    use bitcoin_script_dsl::builtins::u8::U8Var;
-    use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode};
-    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
-    use bitcoin_script_dsl::test_program;
-    use rand::{Rng, SeedableRng};
-    use rand_chacha::ChaCha20Rng;
+        let mut succinct_public_keys = vec![];
+        for ((signature, bytes), key) in signatures.iter().zip(messages.iter()).zip(agg.keys.iter())
+        {
+            let mut public_key_variables = vec![];
+            for public_key_elem in key.public_key.iter() {
+                public_key_variables
+                    .push(track_var(HashVar::new_hint(&cs, public_key_elem.clone())?).variable);
+            }
 
-    #[test]
-    fn test_winternitz() {
-        let mut prng = ChaCha20Rng::seed_from_u64(0);
+            signature.verify_with_public_key_variables(
+                &cs,
+                bytes,
+                &key.metadata,
+                &public_key_variables,
+            )?;
 
-        let mut test_bits = Vec::<bool>::new();
-        for _ in 0..1000 {
-            test_bits.push(prng.gen());
+            cs.insert_gadget_complex(
+                "fold_public_key",
+                fold_public_key,
+                public_key_variables.iter().copied(),
+                &Options::new()
+                    .with_u32("n", public_key_variables.len() as u32)
+                    .with_u32("chain_hash", key.metadata.chain_hash.to_u32()),
+            )?;
+            succinct_public_keys.push(
+                HashVar::new_function_output(&cs, key.succinct_public_key.clone())
+                    .map(track_var)?,
+            );
         }
 
-        let winternitz = Winternitz::keygen(&mut prng);
-        let secret_key = winternitz.get_secret_key("test", 8, 125);
-        let public_key = secret_key.to_public_key();
+        let commitment = HashVar::new_constant(&cs, agg.commitment.clone())?;
+        cs.insert_gadget_complex(
+            "fold_and_check_public_key",
+            fold_and_check_public_key,
+            succinct_public_keys
+                .iter()
+                .map(|key| key.variable)
+                .chain(std::iter::once(commitment.variable)),
+            &Options::new()
+                .with_u32("n", succinct_public_keys.len() as u32)
+                .with_u32("chain_hash", ChainHash::Sha256.to_u32()),
+        )?;
 
-        let signature = secret_key.sign(&test_bits);
-        public_key.verify(&test_bits, &signature).unwrap();
+        Ok(())
     }
 
-    #[test]
-    fn test_winternitz_var_ok() {
-        const W: usize = 6;
-
-        let l = (1000 + W - 1) / W;
+    fn cs<T: BVar>(&self, digits: &[T]) -> ConstraintSystemRef {
+        let mut cs = digits[0].cs();
+        for digit in digits.iter().skip(1) {
+            cs = cs.and(&digit.cs());
+        }
+        for signature in self.signature_messages.iter() {
+            cs = cs.and(&signature.cs);
+        }
+        for signature in self.signature_checksum.iter() {
+            cs = cs.and(&signature.cs);
+        }
+        cs
+    }
 
-        let mut prng = ChaCha20Rng::seed_from_u64(0);
+    fn verify_with_public_key_variables(
+        &self,
+        cs: &ConstraintSystemRef,
+        bytes: &[U8Var],
+        metadata: &WinternitzMetadata,
+        public_key_variables: &[usize],
+    ) -> Result<()> {
+        let checksum_digits = self.verify_chains(cs, bytes, metadata, public_key_variables)?;
 
-        let mut test_bits = Vec::<bool>::new();
-        for _ in 0..1000 {
-            test_bits.push(prng.gen());
+        let mut checksum = I32Var::new_constant(cs, (((1 << metadata.w) - 1) * metadata.l) as i32)?;
+        for byte in bytes.iter() {
+            checksum = &checksum - byte;
         }
-        test_bits.resize(W * l, false);
 
-        let winternitz = Winternitz::keygen(&mut prng);
-        let secret_key = winternitz.get_secret_key("test", W, l);
-        let public_key = secret_key.to_public_key();
+        // the checksum digits are hints in the same decomposition as the signer's, checked to
+        // recompose the checksum
+        cs.insert_gadget_complex(
+            "check_digits_recompose",
+            check_digits_recompose,
+            std::iter::once(checksum.variables()[0])
+                .chain(checksum_digits.iter().map(|digit| digit.variables()[0])),
+            &Options::new()
+                .with_u32("w", metadata.w as u32)
+                .with_u32("num_digits", checksum_digits.len() as u32),
+        )
+    }
 
-        let signature = secret_key.sign(&test_bits);
+    /// Check the hash chains of the units and of the checksum digits, where the checksum digits
+    /// are hints that the caller checks to recompose the checksum of the units.
+    fn verify_chains(
+        &self,
+        cs: &ConstraintSystemRef,
+        bytes: &[U8Var],
+        metadata: &WinternitzMetadata,
+        public_key_variables: &[usize],
+    ) -> Result<Vec<I32Var>> {
+        check_script_hash_function(metadata)?;
+        if metadata.w > 8 {
+            return Err(Error::msg(
+                "Units of more than 8 bits do not fit in U8Var, use verify_digits instead.",
+            ));
+        }
+        if self.metadata.chain_hash != metadata.chain_hash {
+            return Err(Error::msg(
+                "The signature and the public key use different chain hashes.",
+            ));
+        }
 
-        let cs = ConstraintSystem::new_ref();
+        let checksum_l = metadata.checksum_length();
 
-        let mut data_var = vec![];
-        for chunk in test_bits.chunks(W) {
-            let mut constant = 0;
-            for i in 0..W {
-                if chunk[i] {
-                    constant += 1 << i;
-                }
-            }
-            data_var.push(U8Var::new_program_input(&cs, constant).unwrap());
+        if bytes.len() != metadata.l {
+            return Err(Error::msg(format!(
+                "The message has {} units, but the public key is for {} units.",
+                bytes.len(),
+                metadata.l
+            )));
+        }
+        if self.signature_messages.len() != metadata.l
+            || self.signature_checksum.len() != checksum_l
+        {
+            return Err(Error::msg(
+                "The number of signature elements does not match the length of the public key.",
+            ));
+        }
+        if public_key_variables.len() != metadata.l + checksum_l {
+            return Err(Error::msg(
+                "The number of public key elements does not match the metadata.",
+            ));
         }
 
-        let signature_var =
-            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
-                .unwrap();
-        signature_var.verify(&data_var, &public_key).unwrap();
+        let mut checksum_value = (((1 << metadata.w) - 1) * metadata.l) as i32;
+        for byte in bytes.iter() {
+            checksum_value -= byte.value()? as i32;
+        }
 
-        test_program(cs, script! {}).unwrap();
-    }
+        // a U8Var is only known to be a byte, so narrower units need their own range check
+        let range_check = if metadata.w < 8 { 1 } else { 0 };
+        for ((byte, signature), &public_key_variable) in bytes
+            .iter()
+            .zip(self.signature_messages.iter())
+            .zip(public_key_variables.iter().take(metadata.l))
+        {
+            cs.insert_gadget_complex(
+                "apply_and_check_repeated_hash",
+                apply_and_check_repeated_hash,
+                [public_key_variable, signature.variable, byte.variable],
+                &Options::new()
+                    .with_u32("w", metadata.w as u32)
+                    .with_u32("range_check", range_check)
+                    .with_u32("chain_hash", metadata.chain_hash.to_u32()),
+            )?;
+        }
 
-    #[test]
-    #[should_panic]
-    fn test_winternitz_var_err() {
-        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut checksum_digits = vec![];
+        for digit in metadata.checksum_digits(checksum_value as usize) {
+            checksum_digits.push(I32Var::new_hint(cs, digit as i32).map(track_var)?);
+        }
 
-        let mut test_bits = Vec::<bool>::new();
+        for ((digit, signature), &public_key_variable) in checksum_digits
+            .iter()
+            .zip(self.signature_checksum.iter())
+            .zip(public_key_variables.iter().skip(metadata.l))
+        {
+            cs.insert_gadget_complex(
+                "apply_and_check_repeated_hash",
+                apply_and_check_repeated_hash,
+                [
+                    public_key_variable,
+                    signature.variable,
+                    digit.variables()[0],
+                ],
+                &Options::new()
+                    .with_u32("w", metadata.w as u32)
+                    .with_u32("chain_hash", metadata.chain_hash.to_u32()),
+            )?;
+        }
+
+        Ok(checksum_digits)
+    }
+}
+
+/// A signature whose hash chains use Blake3, held as limbs so that the chains are computed by
+/// the Blake3 gadgets, as Blake3 cannot be applied to a hash stored as a single stack element.
+///
+/// Each chain computes all of its `(1 << w) - 1` compressions and only keeps those that the
+/// digit asks for, so the script is only practical for small w and l.
+pub struct WinternitzBlake3SignatureVar {
+    pub metadata: WinternitzMetadata,
+    pub signature_messages: Vec<Blake3HashVar>,
+    pub signature_checksum: Vec<Blake3HashVar>,
+}
+
+impl WinternitzBlake3SignatureVar {
+    pub fn from_signature(
+        cs: &ConstraintSystemRef,
+        signature: &WinternitzSignature,
+        allocation_mode: AllocationMode,
+    ) -> Result<Self> {
+        check_blake3_metadata(&signature.metadata)?;
+        if signature.signature_messages.len() != signature.metadata.l
+            || signature.signature_checksum.len() != signature.metadata.checksum_length()
+        {
+            return Err(Error::msg(
+                "The number of signature elements does not match the metadata.",
+            ));
+        }
+
+        let alloc = |s: &Vec<u8>| -> Result<Blake3HashVar> {
+            let var = Blake3HashVar::new_variable(cs, s.as_slice().try_into()?, allocation_mode)?;
+            if allocation_mode != AllocationMode::Constant {
+                Ok(track_var(var))
+            } else {
+                Ok(var)
+            }
+        };
+
+        Ok(Self {
+            metadata: signature.metadata.clone(),
+            signature_messages: signature
+                .signature_messages
+                .iter()
+                .map(alloc)
+                .collect::<Result<Vec<_>>>()?,
+            signature_checksum: signature
+                .signature_checksum
+                .iter()
+                .map(alloc)
+                .collect::<Result<Vec<_>>>()?,
+        })
+    }
+
+    /// Verify the signature over units of w bits, each held in a U8Var, which supports w up to
+    /// 8. When w < 8, each unit is checked to be within 0..2^w.
+    pub fn verify(
+        &self,
+        constant: &Blake3ConstantVar,
+        bytes: &[U8Var],
+        public_key: &WinternitzPublicKey,
+    ) -> Result<()> {
+        let metadata = &public_key.metadata;
+        check_blake3_metadata(metadata)?;
+        if self.metadata != *metadata {
+            return Err(Error::msg(
+                "The signature and the public key have different metadata.",
+            ));
+        }
+        if metadata.w > 8 {
+            return Err(Error::msg("Units of more than 8 bits do not fit in U8Var."));
+        }
+        if bytes.len() != metadata.l {
+            return Err(Error::msg(format!(
+                "The message has {} units, but the public key is for {} units.",
+                bytes.len(),
+                metadata.l
+            )));
+        }
+
+        let mut cs = constant.cs.clone();
+        for byte in bytes.iter() {
+            cs = cs.and(&byte.cs());
+        }
+        let checksum_l = metadata.checksum_length();
+
+        let mut checksum =
+            I32Var::new_constant(&cs, (((1 << metadata.w) - 1) * metadata.l) as i32)?;
+        for byte in bytes.iter() {
+            checksum = &checksum - byte;
+        }
+
+        let mut checksum_digits = vec![];
+        for digit in metadata.checksum_digits(checksum.value()? as usize) {
+            checksum_digits.push(I32Var::new_hint(&cs, digit as i32).map(track_var)?);
+        }
+        cs.insert_gadget_complex(
+            "check_digits_recompose",
+            check_digits_recompose,
+            std::iter::once(checksum.variables()[0])
+                .chain(checksum_digits.iter().map(|digit| digit.variables()[0])),
+            &Options::new()
+                .with_u32("w", metadata.w as u32)
+                .with_u32("num_digits", checksum_l as u32),
+        )?;
+
+        // the checksum digits are range-checked by the recomposition
+        let range_check = metadata.w < 8;
+        let digits = bytes
+            .iter()
+            .map(|byte| -> Result<_> { Ok((byte.variable, byte.value()? as usize, range_check)) })
+            .chain(checksum_digits.iter().map(|digit| -> Result<_> {
+                Ok((digit.variables()[0], digit.value()? as usize, false))
+            }))
+            .collect::<Result<Vec<_>>>()?;
+
+        for ((digit, signature), public_key_elem) in digits
+            .into_iter()
+            .zip(
+                self.signature_messages
+                    .iter()
+                    .chain(self.signature_checksum.iter()),
+            )
+            .zip(public_key.public_key.iter())
+        {
+            Self::verify_chain(constant, metadata.w, digit, signature, public_key_elem)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply to the signature element as many steps as the digit leaves to the end of the chain,
+    /// and check the result against the public key element.
+    fn verify_chain(
+        constant: &Blake3ConstantVar,
+        w: usize,
+        (variable, value, range_check): (usize, usize, bool),
+        signature: &Blake3HashVar,
+        public_key_elem: &[u8],
+    ) -> Result<()> {
+        let cs = constant.cs.clone();
+        let max_digit = (1usize << w) - 1;
+
+        cs.insert_gadget_complex(
+            "winternitz_chain_steps",
+            winternitz_chain_steps,
+            [variable],
+            &Options::new()
+                .with_u32("w", w as u32)
+                .with_u32("range_check", range_check as u32),
+        )?;
+        let mut steps = vec![];
+        for i in 0..max_digit {
+            steps.push(
+                I32Var::new_function_output(&cs, (value + i < max_digit) as i32).map(track_var)?,
+            );
+        }
+
+        let mut cur = signature.clone();
+        for step in steps.iter() {
+            cur = maybe_hash(constant, step, cur.clone(), &cur);
+        }
+        cur.equalverify(&Blake3HashVar::new_constant(
+            &cs,
+            public_key_elem.try_into()?,
+        )?)
+    }
+}
+
+fn get_or_alloc_public_key_elem(
+    cs: &ConstraintSystemRef,
+    cache: &mut HashMap<Vec<u8>, usize>,
+    public_key_elem: &[u8],
+) -> Result<usize> {
+    if let Some(&variable) = cache.get(public_key_elem) {
+        return Ok(variable);
+    }
+    let variable = HashVar::new_constant(cs, public_key_elem.to_vec())?.variable;
+    cache.insert(public_key_elem.to_vec(), variable);
+    Ok(variable)
+}
+
+/// Verify many Winternitz signatures in the same constraint system, sharing the constants of
+/// identical public key elements across all of them.
+///
+/// The checksums of all the signatures with the same w and l are checked by a single gadget,
+/// which adds the units of each signature to its recomposed checksum digits in place, instead
+/// of a chain of `I32Var` subtractions and a recomposition gadget per signature.
+#[derive(Default)]
+pub struct WinternitzBatchVerifier<'a> {
+    pub entries: Vec<(
+        &'a [U8Var],
+        &'a WinternitzSignatureVar,
+        &'a WinternitzPublicKey,
+    )>,
+}
+
+impl<'a> WinternitzBatchVerifier<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(
+        &mut self,
+        bytes: &'a [U8Var],
+        signature: &'a WinternitzSignatureVar,
+        public_key: &'a WinternitzPublicKey,
+    ) {
+        self.entries.push((bytes, signature, public_key));
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        let mut cache = HashMap::new();
+        // the units and the checksum digits of each signature, grouped by w and l
+        let mut groups =
+            BTreeMap::<(usize, usize), (ConstraintSystemRef, Vec<usize>, usize)>::new();
+
+        for (bytes, signature, public_key) in self.entries.iter() {
+            let public_key_variables =
+                signature.public_key_variables(bytes, public_key, &mut cache)?;
+            let cs = signature.cs(bytes);
+            let checksum_digits =
+                signature.verify_chains(&cs, bytes, &public_key.metadata, &public_key_variables)?;
+
+            let metadata = &public_key.metadata;
+            let (group_cs, variables, num_digits) = groups
+                .entry((metadata.w, metadata.l))
+                .or_insert_with(|| (cs.clone(), vec![], checksum_digits.len()));
+            *group_cs = group_cs.and(&cs);
+            variables.extend(bytes.iter().map(|byte| byte.variable));
+            variables.extend(checksum_digits.iter().map(|digit| digit.variables()[0]));
+            assert_eq!(*num_digits, checksum_digits.len());
+        }
+
+        for ((w, l), (cs, variables, num_digits)) in groups.into_iter() {
+            cs.insert_gadget_complex(
+                "winternitz_batch_checksums",
+                winternitz_batch_checksums,
+                variables.iter().copied(),
+                &Options::new()
+                    .with_u32("w", w as u32)
+                    .with_u32("l", l as u32)
+                    .with_u32("num_digits", num_digits as u32)
+                    .with_u32("n", (variables.len() / (l + num_digits)) as u32),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn winternitz_checksum(_: &mut Stack, options: &Options) -> Result<Script> {
+    let w = options.get_u32("w")? as i64;
+    let l = options.get_u32("l")? as i64;
+
+    Ok(script! {
+        for _ in 1..l {
+            OP_ADD
+        }
+        { ((1i64 << w) - 1) * l } OP_SWAP OP_SUB
+    })
+}
+
+/// Check the checksums of `n` signatures, where each signature has its `l` units followed by
+/// its checksum digits on the stack, and the last signature is on the top.
+fn winternitz_batch_checksums(_: &mut Stack, options: &Options) -> Result<Script> {
+    let w = options.get_u32("w")? as usize;
+    let l = options.get_u32("l")? as usize;
+    let num_digits = options.get_u32("num_digits")? as usize;
+    let n = options.get_u32("n")? as usize;
+
+    Ok(script! {
+        for _ in 0..n {
+            // the checksum is the largest sum of the units minus their sum, so the recomposed
+            // digits plus the units add up to that largest sum
+            { recompose_script(w, num_digits) }
+            for _ in 0..l {
+                OP_ADD
+            }
+            { ((1i64 << w) - 1) * l as i64 } OP_EQUALVERIFY
+        }
+    })
+}
+
+/// Replace the digit on the top of the stack with `(1 << w) - 1` flags, where the i-th flag from
+/// the bottom is 1 if the chain still needs more than i steps to reach the public key.
+fn winternitz_chain_steps(_: &mut Stack, options: &Options) -> Result<Script> {
+    let w = options.get_u32("w")? as usize;
+    let range_check = options.get_u32("range_check").unwrap_or(0) == 1;
+    let max_digit = (1usize << w) - 1;
+
+    Ok(script! {
+        if range_check {
+            OP_DUP 0 { 1 << w } OP_WITHIN OP_VERIFY
+        }
+        { max_digit } OP_SWAP OP_SUB
+        for i in 0..max_digit {
+            OP_DUP { i } OP_GREATERTHAN OP_SWAP
+        }
+        OP_DROP
+    })
+}
+
+fn check_digits_recompose(_: &mut Stack, options: &Options) -> Result<Script> {
+    let w = options.get_u32("w")? as usize;
+    let num_digits = options.get_u32("num_digits")? as usize;
+
+    Ok(script! {
+        { recompose_script(w, num_digits) }
+        OP_EQUALVERIFY
+    })
+}
+
+/// Replace the `num_digits` digits on the top of the stack, the highest one on the top, with
+/// the number that they recompose, checking that each digit is below 2^w.
+fn recompose_script(w: usize, num_digits: usize) -> Script {
+    script! {
+        OP_DUP 0 { 1 << w } OP_WITHIN OP_VERIFY
+        for _ in 1..num_digits {
+            for _ in 0..w {
+                OP_DUP OP_ADD
+            }
+            OP_SWAP
+            OP_DUP 0 { 1 << w } OP_WITHIN OP_VERIFY
+            OP_ADD
+        }
+    }
+}
+
+fn check_digit_range(_: &mut Stack, options: &Options) -> Result<Script> {
+    let w = options.get_u32("w")? as usize;
+
+    Ok(script! {
+        0 { 1 << w } OP_WITHIN OP_VERIFY
+    })
+}
+
+/// Fold the `n` elements on the top of the stack, the last one on the top, as `fold` does.
+fn fold_script(n: usize, chain_hash: ChainHash) -> Script {
+    script! {
+        for _ in 1..n {
+            OP_TOALTSTACK
+        }
+        for _ in 1..n {
+            OP_FROMALTSTACK OP_CAT { chain_hash.steps_script(1) }
+        }
+    }
+}
+
+fn fold_public_key(_: &mut Stack, options: &Options) -> Result<Script> {
+    let n = options.get_u32("n")? as usize;
+    let chain_hash = ChainHash::from_options(options)?;
+
+    Ok(fold_script(n, chain_hash))
+}
+
+fn fold_and_check_public_key(_: &mut Stack, options: &Options) -> Result<Script> {
+    let n = options.get_u32("n")? as usize;
+    let chain_hash = ChainHash::from_options(options)?;
+
+    Ok(script! {
+        // the commitment is on the top of the stack, followed by the last public key element
+        OP_TOALTSTACK
+        { fold_script(n, chain_hash) }
+        OP_FROMALTSTACK OP_EQUALVERIFY
+    })
+}
+
+fn apply_and_check_repeated_hash(_: &mut Stack, options: &Options) -> Result<Script> {
+    let w = options.get_u32("w")? as usize;
+    let range_check = options.get_u32("range_check").unwrap_or(0) == 1;
+    let chain_hash = ChainHash::from_options(options)?;
+
+    Ok(script! {
+        if range_check {
+            OP_DUP 0 { 1 << w } OP_WITHIN OP_VERIFY
+        }
+        { (1 << w) - 1 } OP_SWAP OP_SUB
+        OP_TOALTSTACK
+
+        for i in 0..w {
+            OP_FROMALTSTACK
+
+            if i != w - 1 {
+                OP_DUP { 1 << (w - 1 - i) } OP_GREATERTHANOREQUAL OP_IF
+                    { 1 << (w - 1 - i) } OP_SUB OP_TOALTSTACK
+                    { chain_hash.steps_script(1 << (w - 1 - i)) }
+                OP_ELSE
+                    OP_TOALTSTACK
+                OP_ENDIF
+            } else {
+                OP_IF
+                    { chain_hash.steps_script(1) }
+                OP_ENDIF
+            }
+        }
+
+        OP_EQUALVERIFY
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commitment::winternitz::{
+        batch_key_name, bytes_to_bits, verify_membership, ChainHash, HashFn, HashFunction,
+        Sha256Hash, Winternitz, WinternitzBatchVerifier, WinternitzBlake3SignatureVar,
+        WinternitzError, WinternitzMembershipProofVar, WinternitzMetadata, WinternitzPublicKey,
+        WinternitzPublicKeySet, WinternitzSignature, WinternitzSignatureVar,
+        WINTERNITZ_METADATA_VERSION,
+    };
+    use crate::compression::blake3::{Blake3ConstantVar, Blake3HashVar};
+    use crate::limbs::bytes::BytesVar;
+    use crate::limbs::u4::U4Var;
+    use crate::test_utils::{expect_program_failure, expect_program_failure_without_opcat};
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::builtins::i32::I32Var;
+    use bitcoin_script_dsl::builtins::u8::U8Var;
+    use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode};
+    use bitcoin_script_dsl::compiler::Compiler;
+    use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+    use bitcoin_script_dsl::{test_program, test_program_without_opcat};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_winternitz() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut test_bits = Vec::<bool>::new();
+        for _ in 0..1000 {
+            test_bits.push(prng.gen());
+        }
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 8, 125);
+        let public_key = secret_key.to_public_key();
+
+        let signature = secret_key.sign(&test_bits);
+        public_key.verify(&test_bits, &signature).unwrap();
+    }
+
+    fn committed_pubkey_test_case(corrupt: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut data = vec![0u8; 20];
+        prng.fill(data.as_mut_slice());
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 8, data.len());
+        let public_key = secret_key.to_public_key();
+        let signature = secret_key.sign_bytes(&data);
+
+        let cs = ConstraintSystem::new_ref();
+
+        let data_var = data
+            .iter()
+            .map(|&byte| U8Var::new_program_input(&cs, byte).unwrap())
+            .collect::<Vec<_>>();
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        let commitment: [u8; 32] = public_key.succinct_public_key.clone().try_into().unwrap();
+
+        if corrupt {
+            let mut public_key_elems = public_key.public_key.clone();
+            public_key_elems[3][0] ^= 1;
+            signature_var
+                .verify_with_public_key_hints(&data_var, &public_key_elems, &commitment)
+                .unwrap();
+        } else {
+            signature_var
+                .verify_with_committed_pubkey(&data_var, &commitment)
+                .unwrap();
+        }
+
+        cs
+    }
+
+    #[test]
+    fn test_winternitz_verify_with_committed_pubkey() {
+        test_program(committed_pubkey_test_case(false), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_winternitz_verify_with_committed_pubkey_corrupted_hint() {
+        expect_program_failure(committed_pubkey_test_case(true), script! {}).unwrap();
+    }
+
+    fn verify_digits_test_case(w: usize, num_bits: usize) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let l = num_bits.div_ceil(w);
+        let mut bits = vec![];
+        for _ in 0..num_bits {
+            bits.push(prng.gen::<bool>());
+        }
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", w, l);
+        let public_key = secret_key.to_public_key();
+
+        let signature = secret_key.sign(&bits);
+        public_key.verify(&bits, &signature).unwrap();
+
+        let cs = ConstraintSystem::new_ref();
+
+        bits.resize(l * w, false);
+        let digits_var = bits
+            .chunks_exact(w)
+            .map(|chunk| {
+                let mut digit = 0;
+                for (i, &bit) in chunk.iter().enumerate() {
+                    if bit {
+                        digit |= 1 << i;
+                    }
+                }
+                I32Var::new_program_input(&cs, digit).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        signature_var
+            .verify_digits(&digits_var, &public_key)
+            .unwrap();
+
+        cs
+    }
+
+    #[test]
+    fn test_winternitz_verify_digits() {
+        for w in [4, 10, 12] {
+            test_program(verify_digits_test_case(w, 64), script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_winternitz_script_size_by_w() {
+        // a longer chain per unit outweighs the smaller number of units in the script size,
+        // whereas the signature size shrinks with w
+        let mut last: Option<(usize, usize)> = None;
+        for w in [4, 8, 12] {
+            let cs = verify_digits_test_case(w, 256);
+            let script_len = Compiler::compile(cs).unwrap().script.len();
+            let signature_len = (256usize.div_ceil(w)
+                + WinternitzMetadata::new("test", w, 256usize.div_ceil(w)).checksum_length())
+                * 32;
+            if let Some((last_script_len, last_signature_len)) = last {
+                assert!(script_len > last_script_len, "w = {}", w);
+                assert!(signature_len < last_signature_len, "w = {}", w);
+            }
+            last = Some((script_len, signature_len));
+        }
+    }
+
+    #[test]
+    fn test_winternitz_sign_bytes() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut data = vec![0u8; 16];
+        prng.fill(data.as_mut_slice());
+
+        for w in [2, 4, 8] {
+            let l = data.len() * 8 / w;
+
+            let winternitz = Winternitz::keygen(&mut prng);
+            let secret_key = winternitz.get_secret_key("test", w, l);
+            let public_key = secret_key.to_public_key();
+
+            let signature = secret_key.sign_bytes(&data);
+            public_key
+                .verify(&bytes_to_bits(&data), &signature)
+                .unwrap();
+
+            let cs = ConstraintSystem::new_ref();
+
+            let data_var = data
+                .iter()
+                .map(|&byte| U8Var::new_program_input(&cs, byte).unwrap())
+                .collect::<Vec<_>>();
+            let signature_var = WinternitzSignatureVar::from_signature(
+                &cs,
+                &signature,
+                AllocationMode::ProgramInput,
+            )
+            .unwrap();
+            signature_var.verify_bytes(&data_var, &public_key).unwrap();
+
+            test_program(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_winternitz_script_pubkey_bytes() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let public_key = winternitz.get_public_key("test", 6, 20);
+
+        let bytes = public_key.to_script_pubkey_bytes();
+        let recovered = WinternitzPublicKey::from_script_pubkey_bytes(&bytes).unwrap();
+        assert_eq!(recovered, public_key);
+
+        let mut tampered = bytes.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(WinternitzPublicKey::from_script_pubkey_bytes(&tampered).is_err());
+
+        let mut tampered = bytes.clone();
+        tampered[40] ^= 1;
+        assert!(WinternitzPublicKey::from_script_pubkey_bytes(&tampered).is_err());
+
+        assert!(WinternitzPublicKey::from_script_pubkey_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(WinternitzPublicKey::from_script_pubkey_bytes(&extended).is_err());
+    }
+
+    #[test]
+    fn test_winternitz_verify_length_mismatch() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut data = vec![0u8; 20];
+        prng.fill(data.as_mut_slice());
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 8, data.len());
+        let public_key = secret_key.to_public_key();
+        let signature = secret_key.sign_bytes(&data);
+
+        let cs = ConstraintSystem::new_ref();
+        let data_var = data
+            .iter()
+            .map(|&byte| U8Var::new_program_input(&cs, byte).unwrap())
+            .collect::<Vec<_>>();
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+
+        assert!(signature_var
+            .verify(&data_var[..data.len() - 1], &public_key)
+            .is_err());
+        assert!(signature_var
+            .verify_prefix(&data_var, &public_key, data.len() + 1)
+            .is_err());
+
+        // the failed calls leave nothing behind, so the full verification still goes through
+        signature_var.verify(&data_var, &public_key).unwrap();
+        test_program(cs, script! {}).unwrap();
+    }
+
+    fn verify_prefix_test_case(w: usize, corrupt: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let total_l = 32;
+        let revealed_l = 20;
+
+        let digits = (0..total_l)
+            .map(|_| prng.gen_range(0..1u8 << w))
+            .collect::<Vec<_>>();
+        let mut bits = vec![];
+        for &digit in digits.iter() {
+            for i in 0..w {
+                bits.push((digit >> i) & 1 == 1);
+            }
+        }
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", w, total_l);
+        let public_key = secret_key.to_public_key();
+        let signature = secret_key.sign(&bits);
+
+        let cs = ConstraintSystem::new_ref();
+        let prefix_var = digits[..revealed_l]
+            .iter()
+            .map(|&digit| U8Var::new_program_input(&cs, digit).unwrap())
+            .collect::<Vec<_>>();
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+
+        if corrupt {
+            let mut suffix = digits[revealed_l..].to_vec();
+            suffix[5] ^= 1;
+            signature_var
+                .verify_with_suffix_hints(&prefix_var, &suffix, &public_key)
+                .unwrap();
+        } else {
+            signature_var
+                .verify_prefix(&prefix_var, &public_key, total_l)
+                .unwrap();
+        }
+
+        cs
+    }
+
+    #[test]
+    fn test_winternitz_verify_prefix() {
+        for w in [4, 8] {
+            test_program(verify_prefix_test_case(w, false), script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_winternitz_verify_prefix_wrong_suffix_hint() {
+        expect_program_failure(verify_prefix_test_case(8, true), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_winternitz_verify_prefix_rejects_large_w() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let bits = (0..12 * 4).map(|_| prng.gen()).collect::<Vec<bool>>();
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 12, 4);
+        let public_key = secret_key.to_public_key();
+        let signature = secret_key.sign(&bits);
+
+        let cs = ConstraintSystem::new_ref();
+        let prefix_var = vec![U8Var::new_program_input(&cs, 1).unwrap()];
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        assert!(signature_var
+            .verify_prefix(&prefix_var, &public_key, 4)
+            .is_err());
+    }
+
+    #[test]
+    fn test_winternitz_limb_mismatch() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut test_bits = Vec::<bool>::new();
+        for _ in 0..1000 {
+            test_bits.push(prng.gen());
+        }
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 8, 125);
+        let mut public_key = secret_key.to_public_key();
+        let signature = secret_key.sign(&test_bits);
+
+        // flipping a bit of the 4th byte only breaks the chain at index 3 and the checksum
+        test_bits[3 * 8] = !test_bits[3 * 8];
+        let err = public_key.verify(&test_bits, &signature).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<WinternitzError>(),
+            Some(&WinternitzError::LimbMismatch { index: 3 })
+        );
+        test_bits[3 * 8] = !test_bits[3 * 8];
+
+        public_key.succinct_public_key[0] ^= 1;
+        let err = public_key.verify(&test_bits, &signature).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<WinternitzError>(),
+            Some(&WinternitzError::SuccinctPublicKeyMismatch)
+        );
+    }
+
+    #[test]
+    fn test_winternitz_metadata_version() {
+        let metadata = WinternitzMetadata::new("test", 4, 64);
+        assert_eq!(metadata.version, WINTERNITZ_METADATA_VERSION);
+
+        let bytes = bincode::serialize(&metadata).unwrap();
+        let recovered: WinternitzMetadata = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(recovered, metadata);
+
+        let mut future = metadata.clone();
+        future.version = WINTERNITZ_METADATA_VERSION + 1;
+        assert_ne!(future, metadata);
+
+        let bytes = bincode::serialize(&future).unwrap();
+        let err = bincode::deserialize::<WinternitzMetadata>(&bytes).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Unknown Winternitz metadata version"));
+    }
+
+    #[test]
+    fn test_winternitz_metadata_encoding_round_trip() {
+        for hash_function in [HashFunction::Sha256, HashFunction::Blake3] {
+            for chain_hash in [ChainHash::Sha256, ChainHash::Hash256, ChainHash::Hash160] {
+                // set directly, as the builders reject Blake3 with another chain hash
+                let mut metadata = WinternitzMetadata::new("test", 4, 8);
+                metadata.hash_function = hash_function;
+                metadata.chain_hash = chain_hash;
+
+                let bytes = metadata.to_bytes();
+                let mut reader = bytes.as_slice();
+                let decoded = WinternitzMetadata::read_from(&mut reader).unwrap();
+                assert!(reader.is_empty());
+                assert_eq!(decoded, metadata);
+                assert_eq!(decoded.domain_separator(), metadata.domain_separator());
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Blake3 keys only use ChainHash::Sha256.")]
+    fn test_winternitz_metadata_rejects_blake3_chain_hash() {
+        let _ = WinternitzMetadata::new("test", 4, 8)
+            .with_hash_function(HashFunction::Blake3)
+            .with_chain_hash(ChainHash::Hash256);
+    }
+
+    #[test]
+    fn test_winternitz_signature_bytes() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut test_bits = Vec::<bool>::new();
+        for _ in 0..1000 {
+            test_bits.push(prng.gen());
+        }
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 8, 125);
+        let public_key = secret_key.to_public_key();
+
+        let signature = secret_key.sign(&test_bits);
+        let bytes = signature.to_bytes();
+        assert_eq!(bytes.len(), (125 + 2) * 32);
+
+        let recovered = WinternitzSignature::from_bytes(&public_key.metadata, &bytes).unwrap();
+        assert_eq!(recovered, signature);
+        public_key.verify(&test_bits, &recovered).unwrap();
+
+        assert!(
+            WinternitzSignature::from_bytes(&public_key.metadata, &bytes[..bytes.len() - 1])
+                .is_err()
+        );
+        assert!(WinternitzSignature::from_bytes(&public_key.metadata, &[]).is_err());
+    }
+
+    #[test]
+    fn test_winternitz_signature_wire_and_serde() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut test_bits = Vec::<bool>::new();
+        for _ in 0..40 {
+            test_bits.push(prng.gen());
+        }
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 4, 10);
+        let signature = secret_key.sign(&test_bits);
+
+        let bytes = signature.to_wire_bytes();
+        let recovered = WinternitzSignature::from_wire_bytes(&bytes).unwrap();
+        assert_eq!(recovered, signature);
+
+        let err = WinternitzSignature::from_wire_bytes(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert!(err.to_string().contains("bytes are expected"));
+        assert!(WinternitzSignature::from_wire_bytes(&bytes[..3]).is_err());
+
+        // the digits of more than 8 bits are decoded as well
+        let signature = winternitz
+            .get_secret_key("test", 12, 2)
+            .sign(&test_bits[..24]);
+        let recovered = WinternitzSignature::from_wire_bytes(&signature.to_wire_bytes()).unwrap();
+        assert_eq!(recovered, signature);
+
+        let json = serde_json::to_string(&signature).unwrap();
+        let recovered: WinternitzSignature = serde_json::from_str(&json).unwrap();
+        assert_eq!(recovered, signature);
+    }
+
+    #[test]
+    fn test_winternitz_get_public_keys() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        let mut names = vec![];
+        for i in 0..10 {
+            names.push((format!("field{}", i), 4 + i % 5, 10 + i));
+        }
+
+        let public_keys = winternitz.get_public_keys(&names);
+        assert_eq!(public_keys.len(), names.len());
+        for ((name, w, l), public_key) in names.iter().zip(public_keys.iter()) {
+            assert_eq!(*public_key, winternitz.get_public_key(name, *w, *l));
+        }
+    }
+
+    #[test]
+    fn test_winternitz_derive_batch() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        let keys = winternitz.derive_batch("cell", 5, 4, 8);
+        assert_eq!(keys.len(), 5);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                *key,
+                winternitz.get_public_key(batch_key_name("cell", i), 4, 8)
+            );
+        }
+        assert_ne!(keys[0], keys[1]);
+
+        let root = winternitz.public_root(&keys);
+        assert_eq!(
+            winternitz.public_root(&winternitz.derive_batch("cell", 5, 4, 8)),
+            root
+        );
+        assert_ne!(winternitz.public_root(&keys[..4]), root);
+
+        let other = Winternitz::keygen(&mut prng);
+        assert_ne!(
+            other.public_root(&other.derive_batch("cell", 5, 4, 8)),
+            root
+        );
+    }
+
+    #[test]
+    fn test_winternitz_blake3() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut test_bits = Vec::<bool>::new();
+        for _ in 0..1000 {
+            test_bits.push(prng.gen());
+        }
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let metadata =
+            WinternitzMetadata::new("test", 8, 125).with_hash_function(HashFunction::Blake3);
+        let secret_key = winternitz.get_secret_key_from_metadata(metadata);
+        let public_key = secret_key.to_public_key();
+
+        let sha256_public_key = winternitz.get_public_key("test", 8, 125);
+        assert_ne!(
+            public_key.succinct_public_key,
+            sha256_public_key.succinct_public_key
+        );
+
+        let signature = secret_key.sign(&test_bits);
+        public_key.verify(&test_bits, &signature).unwrap();
+
+        let cs = ConstraintSystem::new_ref();
+        assert!(WinternitzSignatureVar::from_signature(
+            &cs,
+            &signature,
+            AllocationMode::ProgramInput
+        )
+        .is_err());
+
+        // a SHA-256 signature cannot be checked against the Blake3 public key in script either
+        let sha256_signature = winternitz.get_secret_key("test", 8, 125).sign(&test_bits);
+        assert!(WinternitzBlake3SignatureVar::from_signature(
+            &cs,
+            &sha256_signature,
+            AllocationMode::ProgramInput
+        )
+        .is_err());
+        let signature_var = WinternitzSignatureVar::from_signature(
+            &cs,
+            &sha256_signature,
+            AllocationMode::ProgramInput,
+        )
+        .unwrap();
+        let bytes_var = test_bits
+            .chunks(8)
+            .map(|bits| {
+                let byte = bits
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (i, &bit)| acc | ((bit as u8) << i));
+                U8Var::new_program_input(&cs, byte).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let err = signature_var
+            .verify(&bytes_var, &public_key)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("SHA-256"), "{}", err);
+    }
+
+    #[test]
+    fn test_winternitz_blake3_in_script() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        // each chain computes 2^w - 1 compressions, so the key is kept small
+        let metadata =
+            WinternitzMetadata::new("test", 2, 1).with_hash_function(HashFunction::Blake3);
+        let secret_key = winternitz.get_secret_key_from_metadata(metadata);
+        let public_key = secret_key.to_public_key();
+
+        let build = |signed: u8, unit: u8| {
+            let signature = secret_key.sign(&[signed & 1 == 1, signed & 2 == 2]);
+
+            let cs = ConstraintSystem::new_ref();
+            let constant = Blake3ConstantVar::new(&cs);
+            let signature_var = WinternitzBlake3SignatureVar::from_signature(
+                &cs,
+                &signature,
+                AllocationMode::ProgramInput,
+            )
+            .unwrap();
+            let unit_var = U8Var::new_program_input(&cs, unit).unwrap();
+            signature_var
+                .verify(&constant, &[unit_var], &public_key)
+                .unwrap();
+            cs
+        };
+
+        for unit in [0, 3] {
+            test_program_without_opcat(build(unit, unit), script! {}).unwrap();
+        }
+
+        // a larger unit would need the chain to be computed backwards
+        expect_program_failure_without_opcat(build(1, 2), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_winternitz_truncated_digest() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut test_bits = Vec::<bool>::new();
+        for _ in 0..1000 {
+            test_bits.push(prng.gen());
+        }
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key_with_digest_bytes("test", 8, 125, 20);
+        let public_key = secret_key.to_public_key();
+        assert!(public_key.public_key.iter().all(|key| key.len() == 20));
+        assert_eq!(public_key.succinct_public_key.len(), 20);
+
+        let signature = secret_key.sign(&test_bits);
+        assert!(signature
+            .signature_messages
+            .iter()
+            .chain(signature.signature_checksum.iter())
+            .all(|s| s.len() == 20));
+        public_key.verify(&test_bits, &signature).unwrap();
+
+        test_bits[0] = !test_bits[0];
+        assert!(public_key.verify(&test_bits, &signature).is_err());
+    }
+
+    #[test]
+    fn test_winternitz_var_ok() {
+        const W: usize = 6;
+
+        let l = (1000 + W - 1) / W;
+
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut test_bits = Vec::<bool>::new();
+        for _ in 0..1000 {
+            test_bits.push(prng.gen());
+        }
+        test_bits.resize(W * l, false);
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", W, l);
+        let public_key = secret_key.to_public_key();
+
+        let signature = secret_key.sign(&test_bits);
+
+        let cs = ConstraintSystem::new_ref();
+
+        let mut data_var = vec![];
+        for chunk in test_bits.chunks(W) {
+            let mut constant = 0;
+            for i in 0..W {
+                if chunk[i] {
+                    constant += 1 << i;
+                }
+            }
+            data_var.push(U8Var::new_program_input(&cs, constant).unwrap());
+        }
+
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        signature_var.verify(&data_var, &public_key).unwrap();
+
+        test_program(cs, script! {}).unwrap();
+    }
+
+    fn var_units_test_case(w: usize, tamper: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let l = 256usize.div_ceil(w);
+
+        let max = ((1u16 << w) - 1) as u8;
+        let mut units: Vec<u8> = (0..l).map(|_| prng.gen_range(0..=max)).collect();
+        let to_bits = |units: &[u8]| {
+            let mut bits = Vec::<bool>::new();
+            for &unit in units.iter() {
+                for i in 0..w {
+                    bits.push((unit >> i) & 1 == 1);
+                }
+            }
+            bits
+        };
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", w, l);
+        let public_key = secret_key.to_public_key();
+
+        // without the range check, a unit above the range leaves a negative number of steps, of
+        // which the chain applies exactly one, as it does for the unit 2^w - 2
+        if tamper {
+            units[0] = max - 1;
+        }
+        let mut signature = secret_key.sign(&to_bits(&units));
+        let mut data = units.clone();
+        if tamper {
+            data[0] += 1 << w;
+
+            // the checksum is signed for another message in range with the same sum as the data
+            let mut same_sum = units.clone();
+            let mut excess = 1u16 << w;
+            for unit in same_sum.iter_mut().skip(1) {
+                let step = excess.min((max - *unit) as u16);
+                *unit += step as u8;
+                excess -= step;
+            }
+            assert_eq!(excess, 0);
+            signature.signature_checksum = secret_key.sign(&to_bits(&same_sum)).signature_checksum;
+        }
+
+        let cs = ConstraintSystem::new_ref();
+        let mut data_var = vec![];
+        for &unit in data.iter() {
+            data_var.push(U8Var::new_program_input(&cs, unit).unwrap());
+        }
+
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        signature_var.verify(&data_var, &public_key).unwrap();
+        cs
+    }
+
+    #[test]
+    fn test_winternitz_var_supported_w() {
+        for w in [2, 4, 6, 8] {
+            let cs = var_units_test_case(w, false);
+            test_program(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_winternitz_var_unit_out_of_range() {
+        for w in [2, 4, 6] {
+            let cs = var_units_test_case(w, true);
+            expect_program_failure(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_winternitz_extreme_messages() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        for (w, l) in [(4, 64), (5, 52), (6, 43), (6, 167), (7, 37), (8, 32)] {
+            let secret_key = winternitz.get_secret_key("test", w, l);
+            let public_key = secret_key.to_public_key();
+
+            // the largest checksum and a zero checksum
+            for digit in [0u8, ((1u16 << w) - 1) as u8] {
+                let mut bits = vec![];
+                for _ in 0..l {
+                    for i in 0..w {
+                        bits.push((digit >> i) & 1 == 1);
+                    }
+                }
+
+                let signature = secret_key.sign(&bits);
+                public_key.verify(&bits, &signature).unwrap();
+
+                let cs = ConstraintSystem::new_ref();
+                let data_var = (0..l)
+                    .map(|_| U8Var::new_program_input(&cs, digit).unwrap())
+                    .collect::<Vec<_>>();
+                let signature_var = WinternitzSignatureVar::from_signature(
+                    &cs,
+                    &signature,
+                    AllocationMode::ProgramInput,
+                )
+                .unwrap();
+                signature_var.verify(&data_var, &public_key).unwrap();
+
+                test_program(cs, script! {}).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_winternitz_batch_verifier() {
+        const N: usize = 8;
+        const W: usize = 4;
+        const L: usize = 8;
+
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        // a one-time key per message, so that no public key element is shared within the batch
+        let mut public_keys = vec![];
+        let mut messages = vec![];
+        let mut signatures = vec![];
+        for i in 0..N {
+            let secret_key = winternitz.get_secret_key(batch_key_name("test", i), W, L);
+            public_keys.push(secret_key.to_public_key());
+
+            let mut digits = vec![];
+            let mut bits = vec![];
+            for _ in 0..L {
+                let digit: u8 = prng.gen_range(0..(1 << W) - 1);
+                for j in 0..W {
+                    bits.push((digit >> j) & 1 == 1);
+                }
+                digits.push(digit);
+            }
+            signatures.push(secret_key.sign(&bits));
+            messages.push(digits);
+        }
+
+        // raise a unit of one message by hashing its chain one step further, which only the
+        // checksum rejects
+        let mut forged_messages = messages.clone();
+        let mut forged_signatures = signatures.clone();
+        forged_messages[3][0] += 1;
+        forged_signatures[3].signature_messages[0] = public_keys[3]
+            .metadata
+            .hash(&forged_signatures[3].signature_messages[0]);
+
+        let build = |batch: bool, messages: &[Vec<u8>], signatures: &[WinternitzSignature]| {
+            let cs = ConstraintSystem::new_ref();
+
+            let mut data_vars = vec![];
+            let mut signature_vars = vec![];
+            for (digits, signature) in messages.iter().zip(signatures.iter()) {
+                data_vars.push(
+                    digits
+                        .iter()
+                        .map(|&digit| U8Var::new_program_input(&cs, digit).unwrap())
+                        .collect::<Vec<_>>(),
+                );
+                signature_vars.push(
+                    WinternitzSignatureVar::from_signature(
+                        &cs,
+                        signature,
+                        AllocationMode::ProgramInput,
+                    )
+                    .unwrap(),
+                );
+            }
+
+            if batch {
+                let mut verifier = WinternitzBatchVerifier::new();
+                for ((data_var, signature_var), public_key) in data_vars
+                    .iter()
+                    .zip(signature_vars.iter())
+                    .zip(public_keys.iter())
+                {
+                    verifier.add(data_var, signature_var, public_key);
+                }
+                verifier.verify().unwrap();
+            } else {
+                for ((data_var, signature_var), public_key) in data_vars
+                    .iter()
+                    .zip(signature_vars.iter())
+                    .zip(public_keys.iter())
+                {
+                    signature_var.verify(data_var, public_key).unwrap();
+                }
+            }
+            cs
+        };
+
+        test_program(build(true, &messages, &signatures), script! {}).unwrap();
+        expect_program_failure(
+            build(true, &forged_messages, &forged_signatures),
+            script! {},
+        )
+        .unwrap();
+
+        let batch_len = Compiler::compile(build(true, &messages, &signatures))
+            .unwrap()
+            .script
+            .len();
+        let individual_len = Compiler::compile(build(false, &messages, &signatures))
+            .unwrap()
+            .script
+            .len();
+        assert!(batch_len < individual_len);
+    }
+
+    #[test]
+    fn test_winternitz_var_err() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut test_bits = Vec::<bool>::new();
         for _ in 0..1000 {
             test_bits.push(prng.gen());
         }
@@ -525,6 +3018,303 @@ mod test {
                 .unwrap();
         signature_var.verify(&data_var, &public_key).unwrap();
 
-        test_program(cs, script! {}).unwrap();
+        expect_program_failure(cs, script! {}).unwrap();
+    }
+
+    fn forged_signature_test_case(forge: impl FnOnce(&mut Vec<u8>, &mut WinternitzSignature)) {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 8, 4);
+        let public_key = secret_key.to_public_key();
+
+        let mut data = vec![0x10, 0x20, 0x30, 0x40];
+        let mut signature = secret_key.sign(&bytes_to_bits(&data));
+        forge(&mut data, &mut signature);
+
+        let cs = ConstraintSystem::new_ref();
+        let data_var = data
+            .iter()
+            .map(|&byte| U8Var::new_program_input(&cs, byte).unwrap())
+            .collect::<Vec<_>>();
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        signature_var.verify(&data_var, &public_key).unwrap();
+
+        expect_program_failure(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_winternitz_var_forged_message_element() {
+        forged_signature_test_case(|_, signature| signature.signature_messages[2][0] ^= 1);
+    }
+
+    #[test]
+    fn test_winternitz_var_forged_checksum_element() {
+        forged_signature_test_case(|_, signature| signature.signature_checksum[0][0] ^= 1);
+    }
+
+    fn chain_hash_test_case(chain_hash: ChainHash, forge: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let data: Vec<u8> = (0..16).map(|_| prng.gen()).collect();
+        let test_bits = bytes_to_bits(&data);
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let metadata = WinternitzMetadata::new("test", 8, 16).with_chain_hash(chain_hash);
+        let secret_key = winternitz.get_secret_key_from_metadata(metadata);
+        let public_key = secret_key.to_public_key();
+        assert!(public_key
+            .public_key
+            .iter()
+            .all(|elem| elem.len() == chain_hash.output_bytes()));
+        let encoded = public_key.to_script_pubkey_bytes();
+        let decoded = WinternitzPublicKey::from_script_pubkey_bytes(&encoded).unwrap();
+        assert_eq!(decoded.metadata, public_key.metadata);
+
+        let mut signature = secret_key.sign(&test_bits);
+        if forge {
+            signature.signature_messages[3][0] ^= 1;
+            assert!(public_key.verify(&test_bits, &signature).is_err());
+        } else {
+            public_key.verify(&test_bits, &signature).unwrap();
+        }
+
+        let cs = ConstraintSystem::new_ref();
+        let data_var = data
+            .iter()
+            .map(|&byte| U8Var::new_program_input(&cs, byte).unwrap())
+            .collect::<Vec<_>>();
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        signature_var.verify(&data_var, &public_key).unwrap();
+        cs
+    }
+
+    #[test]
+    fn test_winternitz_chain_hash() {
+        for chain_hash in [ChainHash::Sha256, ChainHash::Hash256, ChainHash::Hash160] {
+            let cs = chain_hash_test_case(chain_hash, false);
+            test_program(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_winternitz_chain_hash_forged() {
+        for chain_hash in [ChainHash::Sha256, ChainHash::Hash256, ChainHash::Hash160] {
+            let cs = chain_hash_test_case(chain_hash, true);
+            expect_program_failure(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_winternitz_chain_hash_mismatch() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        let data = vec![0x10, 0x20, 0x30, 0x40];
+        let metadata = WinternitzMetadata::new("test", 8, 4);
+        let public_key = winternitz
+            .get_secret_key_from_metadata(metadata.clone())
+            .to_public_key();
+        let signature = winternitz
+            .get_secret_key_from_metadata(metadata.with_chain_hash(ChainHash::Hash256))
+            .sign(&bytes_to_bits(&data));
+        assert!(public_key
+            .verify(&bytes_to_bits(&data), &signature)
+            .is_err());
+
+        let cs = ConstraintSystem::new_ref();
+        let data_var = data
+            .iter()
+            .map(|&byte| U8Var::new_program_input(&cs, byte).unwrap())
+            .collect::<Vec<_>>();
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        assert!(signature_var.verify(&data_var, &public_key).is_err());
+    }
+
+    #[test]
+    fn test_winternitz_chain_hash_default() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        let metadata = WinternitzMetadata::new("test", 4, 8);
+        assert_eq!(metadata.chain_hash, ChainHash::Sha256);
+        assert_eq!(*metadata.to_bytes().last().unwrap(), 0);
+
+        // the default chains are single SHA-256 steps, as for the keys created before
+        let secret_key = winternitz.get_secret_key("test", 4, 8);
+        let public_key = secret_key.to_public_key();
+        for (secret, public) in secret_key
+            .secret_key
+            .iter()
+            .zip(public_key.public_key.iter())
+        {
+            let mut cur = secret.clone();
+            for _ in 0..15 {
+                cur = Sha256Hash::hash(&cur);
+            }
+            assert_eq!(&cur, public);
+        }
+
+        let encoded = public_key.to_script_pubkey_bytes();
+        let decoded = WinternitzPublicKey::from_script_pubkey_bytes(&encoded).unwrap();
+        assert_eq!(decoded.metadata.chain_hash, ChainHash::Sha256);
+    }
+
+    fn membership_test_case(index: usize, corrupt: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        let keys = winternitz.derive_batch("cell", 5, 4, 8);
+        let set = WinternitzPublicKeySet::new(keys.clone());
+        assert_eq!(set.root(), winternitz.public_root(&keys));
+        assert!(set.membership_proof(5).is_err());
+
+        let proof = set.membership_proof(index).unwrap();
+        proof
+            .verify(&keys[index].succinct_public_key, &set.root())
+            .unwrap();
+
+        let mut succinct_public_key = keys[index].succinct_public_key.clone();
+        if corrupt {
+            succinct_public_key[0] ^= 1;
+        }
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+        let key_var = BytesVar::new_program_input(&cs, succinct_public_key).unwrap();
+        let direction_bits = proof
+            .direction_bits()
+            .iter()
+            .map(|&bit| U4Var::new_program_input(&cs, bit as u32).unwrap())
+            .collect::<Vec<_>>();
+        let proof_var = WinternitzMembershipProofVar::new_hint(&cs, &proof).unwrap();
+        let root_var = Blake3HashVar::new_constant(&cs, set.root()).unwrap();
+
+        verify_membership(&constant, &key_var, &proof_var, &direction_bits, &root_var).unwrap();
+        cs
+    }
+
+    #[test]
+    fn test_winternitz_public_key_set_membership() {
+        for index in [0, 3, 4] {
+            test_program_without_opcat(membership_test_case(index, false), script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_winternitz_public_key_set_wrong_key() {
+        expect_program_failure_without_opcat(membership_test_case(2, true), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_winternitz_sign_once() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        let test_bits = bytes_to_bits(&[0x12, 0x34, 0x56, 0x78]);
+        let secret_key = winternitz.get_secret_key("test", 8, 4);
+        let public_key = secret_key.to_public_key();
+        let expected = secret_key.sign(&test_bits);
+
+        let signature = secret_key.sign_once(&test_bits);
+        assert_eq!(signature, expected);
+        public_key.verify(&test_bits, &signature).unwrap();
+    }
+
+    fn aggregate_test_case(k: usize, flip: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        let keys = winternitz.derive_batch("value", k, 8, 4);
+        let agg = WinternitzPublicKey::aggregate(&keys).unwrap();
+        assert_eq!(agg.commitment().len(), 32);
+
+        let cs = ConstraintSystem::new_ref();
+        let mut signatures = vec![];
+        let mut messages = vec![];
+        for i in 0..k {
+            let mut data: Vec<u8> = (0..4).map(|_| prng.gen()).collect();
+            let secret_key = winternitz.get_secret_key(batch_key_name("value", i), 8, 4);
+            let signature = secret_key.sign_bytes(&data);
+
+            if flip && i == k / 2 {
+                data[2] ^= 1;
+            }
+
+            signatures.push(
+                WinternitzSignatureVar::from_signature(
+                    &cs,
+                    &signature,
+                    AllocationMode::ProgramInput,
+                )
+                .unwrap(),
+            );
+            messages.push(
+                data.iter()
+                    .map(|&byte| U8Var::new_program_input(&cs, byte).unwrap())
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        WinternitzSignatureVar::verify_batch(&signatures, &messages, &agg).unwrap();
+        assert!(
+            WinternitzSignatureVar::verify_batch(&signatures[1..], &messages[1..], &agg).is_err()
+        );
+        cs
+    }
+
+    #[test]
+    fn test_winternitz_aggregate() {
+        for k in [1, 3, 8] {
+            test_program(aggregate_test_case(k, false), script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_winternitz_aggregate_empty() {
+        assert!(WinternitzPublicKey::aggregate(&[]).is_err());
+    }
+
+    #[test]
+    fn test_winternitz_aggregate_flipped_digit() {
+        expect_program_failure(aggregate_test_case(3, true), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_winternitz_sign_constant_time() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        for (w, l) in [(1, 16), (4, 8), (8, 4), (10, 3)] {
+            let secret_key = winternitz.get_secret_key("test", w, l);
+            let public_key = secret_key.to_public_key();
+
+            let mut random_bits = vec![];
+            for _ in 0..w * l {
+                random_bits.push(prng.gen());
+            }
+
+            for test_bits in [vec![false; w * l], vec![true; w * l], random_bits] {
+                let signature = secret_key.sign_constant_time(&test_bits);
+                assert_eq!(signature, secret_key.sign(&test_bits));
+                public_key.verify(&test_bits, &signature).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_winternitz_var_advanced_chain() {
+        // anyone can hash a chain forward to sign a larger digit, which only the checksum catches
+        forged_signature_test_case(|data, signature| {
+            data[1] += 1;
+            signature.signature_messages[1] =
+                signature.metadata.hash(&signature.signature_messages[1]);
+        });
     }
 }
@@ -0,0 +1,84 @@
+use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::thread::LocalKey;
+
+/// The values cached for each constraint system, see `get_or_create`.
+///
+/// The constraint systems are held weakly, but the DSL gives no hook on their drop, so the
+/// stale entries are only pruned by the next call to `get_or_create` on the same registry. The
+/// registry therefore holds at most the entries that were live at that call and those added
+/// since, and a dropped constraint system whose cached value refers to it is freed at that call.
+pub(crate) type Registry<T> = RefCell<Vec<CachedEntry<T>>>;
+
+pub(crate) struct CachedEntry<T> {
+    cs: Weak<RefCell<ConstraintSystem>>,
+    /// The number of references to the constraint system held by the value itself.
+    internal_refs: usize,
+    value: Rc<T>,
+}
+
+impl<T> CachedEntry<T> {
+    /// An entry is stale once nothing but the cached value refers to the constraint system, so
+    /// that no one can ask for it again.
+    fn is_stale(&self) -> bool {
+        Rc::strong_count(&self.value) == 1 && self.cs.strong_count() <= self.internal_refs
+    }
+}
+
+/// Return the value cached in `registry` for the constraint system, and otherwise create it with
+/// `create` and cache it, so that all the callers share a single allocation.
+pub(crate) fn get_or_create<T>(
+    registry: &'static LocalKey<Registry<T>>,
+    cs: &ConstraintSystemRef,
+    create: impl FnOnce(&ConstraintSystemRef) -> T,
+) -> Rc<T> {
+    registry.with(|registry| {
+        let mut entries = registry.borrow_mut();
+        entries.retain(|entry| !entry.is_stale());
+
+        if let Some(entry) = entries
+            .iter()
+            .find(|entry| entry.cs.as_ptr() == Rc::as_ptr(&cs.0))
+        {
+            return entry.value.clone();
+        }
+
+        let refs_before = Rc::strong_count(&cs.0);
+        let value = Rc::new(create(cs));
+        entries.push(CachedEntry {
+            cs: Rc::downgrade(&cs.0),
+            internal_refs: Rc::strong_count(&cs.0) - refs_before,
+            value: value.clone(),
+        });
+        value
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::blake3::cache::{get_or_create, Registry};
+    use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+
+    thread_local! {
+        static TEST_REGISTRY: Registry<ConstraintSystemRef> = const { Registry::new(Vec::new()) };
+    }
+
+    #[test]
+    fn test_registry_prunes_dropped_constraint_systems() {
+        let cs = ConstraintSystem::new_ref();
+        let value = get_or_create(&TEST_REGISTRY, &cs, |cs| cs.clone());
+        assert!(std::rc::Rc::ptr_eq(
+            &value,
+            &get_or_create(&TEST_REGISTRY, &cs, |cs| cs.clone())
+        ));
+
+        // the cached value is the only reference left to the first constraint system
+        drop(value);
+        drop(cs);
+
+        let other_cs = ConstraintSystem::new_ref();
+        let _other_value = get_or_create(&TEST_REGISTRY, &other_cs, |cs| cs.clone());
+        TEST_REGISTRY.with(|registry| assert_eq!(registry.borrow().len(), 1));
+    }
+}
@@ -0,0 +1,238 @@
+//! Evaluate the Blake3 gadgets without building any script.
+//!
+//! The functions follow the same steps as `g`, `round`, and `hash`, over the little-endian
+//! nibbles that `U32Var` holds, so that their results are bit-compatible with the values of the
+//! variables. This is much faster than building the constraint system, for example to compute
+//! the expected digest of a candidate input before deciding to build the proof.
+
+use crate::compression::blake3::{CHUNK_END, CHUNK_START, IV, MAX_BLOCKS, ROOT, ROUNDS};
+
+/// The nibbles of a word, from the least significant one, as in `U32Var`.
+pub type U32Limbs = [u32; 8];
+
+pub fn to_limbs(v: u32) -> U32Limbs {
+    let mut limbs = [0u32; 8];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        *limb = (v >> (4 * i)) & 15;
+    }
+    limbs
+}
+
+pub fn from_limbs(limbs: &U32Limbs) -> u32 {
+    let mut v = 0;
+    for (i, limb) in limbs.iter().enumerate() {
+        v |= limb << (4 * i);
+    }
+    v
+}
+
+fn add_eval(addends: &[&U32Limbs]) -> U32Limbs {
+    let mut limbs = [0u32; 8];
+    let mut carry = 0;
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        let sum = addends.iter().map(|a| a[i]).sum::<u32>() + carry;
+        *limb = sum & 15;
+        carry = sum >> 4;
+    }
+    limbs
+}
+
+fn xor_eval(a: &U32Limbs, b: &U32Limbs) -> U32Limbs {
+    let mut limbs = [0u32; 8];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        *limb = a[i] ^ b[i];
+    }
+    limbs
+}
+
+fn rotate_right_nibbles(a: &U32Limbs, n: usize) -> U32Limbs {
+    let mut limbs = [0u32; 8];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        *limb = a[(i + n) % 8];
+    }
+    limbs
+}
+
+fn rotate_right_shift_7(a: &U32Limbs) -> U32Limbs {
+    let a = rotate_right_nibbles(a, 1);
+    let mut limbs = [0u32; 8];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        *limb = (a[i] >> 3) + ((a[(i + 1) % 8] << 1) & 15);
+    }
+    limbs
+}
+
+pub fn g_eval(
+    a_ref: &mut U32Limbs,
+    b_ref: &mut U32Limbs,
+    c_ref: &mut U32Limbs,
+    d_ref: &mut U32Limbs,
+    m_0: &U32Limbs,
+    m_1: &U32Limbs,
+) {
+    let mut a = *a_ref;
+    let mut b = *b_ref;
+    let mut c = *c_ref;
+    let mut d = *d_ref;
+
+    a = add_eval(&[&a, &b, m_0]);
+    d = rotate_right_nibbles(&xor_eval(&d, &a), 4);
+    c = add_eval(&[&c, &d]);
+    b = rotate_right_nibbles(&xor_eval(&b, &c), 3);
+    a = add_eval(&[&a, &b, m_1]);
+    d = rotate_right_nibbles(&xor_eval(&d, &a), 2);
+    c = add_eval(&[&c, &d]);
+    b = rotate_right_shift_7(&xor_eval(&b, &c));
+
+    *a_ref = a;
+    *b_ref = b;
+    *c_ref = c;
+    *d_ref = d;
+}
+
+pub fn round_eval(state_ref: &mut [U32Limbs; 16], msg: &mut [U32Limbs; 16]) {
+    let [ref mut s0, ref mut s1, ref mut s2, ref mut s3, ref mut s4, ref mut s5, ref mut s6, ref mut s7, ref mut s8, ref mut s9, ref mut s10, ref mut s11, ref mut s12, ref mut s13, ref mut s14, ref mut s15] =
+        *state_ref;
+
+    g_eval(s0, s4, s8, s12, &msg[0], &msg[1]);
+    g_eval(s1, s5, s9, s13, &msg[2], &msg[3]);
+    g_eval(s2, s6, s10, s14, &msg[4], &msg[5]);
+    g_eval(s3, s7, s11, s15, &msg[6], &msg[7]);
+
+    g_eval(s0, s5, s10, s15, &msg[8], &msg[9]);
+    g_eval(s1, s6, s11, s12, &msg[10], &msg[11]);
+    g_eval(s2, s7, s8, s13, &msg[12], &msg[13]);
+    g_eval(s3, s4, s9, s14, &msg[14], &msg[15]);
+
+    *msg = [
+        msg[2], msg[6], msg[3], msg[10], msg[7], msg[0], msg[4], msg[13], msg[1], msg[11], msg[12],
+        msg[5], msg[9], msg[14], msg[15], msg[8],
+    ];
+}
+
+/// Evaluate `compress_block`.
+pub fn compress_block_eval(
+    cv: &[U32Limbs; 8],
+    words: &[U32Limbs; 16],
+    counter: u64,
+    len: u32,
+    flags: u32,
+) -> [U32Limbs; 8] {
+    let mut messages = *words;
+
+    let mut states = [[0u32; 8]; 16];
+    states[0..8].copy_from_slice(cv);
+    for i in 0..4 {
+        states[8 + i] = to_limbs(IV[i]);
+    }
+    states[12] = to_limbs(counter as u32);
+    states[13] = to_limbs((counter >> 32) as u32);
+    states[14] = to_limbs(len);
+    states[15] = to_limbs(flags);
+
+    for _ in 0..ROUNDS {
+        round_eval(&mut states, &mut messages);
+    }
+
+    let mut new_chaining_values = [[0u32; 8]; 8];
+    for (i, cv) in new_chaining_values.iter_mut().enumerate() {
+        *cv = xor_eval(&states[i], &states[i + 8]);
+    }
+    new_chaining_values
+}
+
+/// Evaluate `hash` over a sequence of words.
+pub fn blake3_eval(msg: &[u32]) -> [u32; 8] {
+    let num_blocks = msg.len().div_ceil(16);
+    if num_blocks > MAX_BLOCKS {
+        panic!("Too many blocks passed to this Blake3 implementation.");
+    }
+
+    let mut chaining_values = IV.map(to_limbs);
+    for (i, chunk) in msg.chunks(16).enumerate() {
+        let mut words = [[0u32; 8]; 16];
+        for (word, &v) in words.iter_mut().zip(chunk.iter()) {
+            *word = to_limbs(v);
+        }
+
+        let mut d = 0;
+        if i == 0 {
+            d ^= CHUNK_START;
+        }
+        if i == num_blocks - 1 {
+            d ^= CHUNK_END;
+            d ^= ROOT;
+        }
+
+        chaining_values =
+            compress_block_eval(&chaining_values, &words, 0, (chunk.len() * 4) as u32, d);
+    }
+
+    chaining_values.map(|limbs| from_limbs(&limbs))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::blake3::eval::{blake3_eval, from_limbs, g_eval, to_limbs};
+    use crate::compression::blake3::native::g_reference;
+    use crate::compression::blake3::reference::blake3_reference;
+    use crate::compression::blake3::{hash, Blake3ConstantVar, Blake3HashVar};
+    use crate::limbs::u32::U32Var;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_g_eval() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let mut values: [u32; 6] = prng.gen();
+            let mut limbs = values.map(to_limbs);
+
+            let [a, b, c, d, m_0, m_1] = &mut limbs;
+            g_eval(a, b, c, d, m_0, m_1);
+            let [a, b, c, d, m_0, m_1] = &mut values;
+            g_reference(a, b, c, d, *m_0, *m_1);
+
+            assert_eq!(limbs.map(|l| from_limbs(&l)), values);
+        }
+    }
+
+    #[test]
+    fn test_blake3_eval() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for num_words in [1, 16, 17, 40] {
+            let messages = (0..num_words).map(|_| prng.gen()).collect::<Vec<u32>>();
+
+            let expected = blake3_reference(&messages);
+            assert_eq!(blake3_eval(&messages), expected);
+
+            let cs = ConstraintSystem::new_ref();
+            let messages_var = messages
+                .iter()
+                .map(|&v| U32Var::new_program_input(&cs, v).unwrap())
+                .collect::<Vec<_>>();
+            let constant = Blake3ConstantVar::new(&cs);
+            let computed_hash = hash(&constant, messages_var.as_slice());
+
+            let computed = computed_hash
+                .hash
+                .iter()
+                .map(|word| word.value().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(computed, expected);
+
+            let expected_var = Blake3HashVar {
+                hash: expected.map(|v| U32Var::new_constant(&cs, v).unwrap()),
+            };
+            computed_hash.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+}
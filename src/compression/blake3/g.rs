@@ -1,5 +1,7 @@
 use crate::compression::blake3::lookup_table::LookupTableVar;
 use crate::limbs::u32::U32Var;
+use crate::limbs::TryAdd;
+use anyhow::Result;
 
 pub fn g(
     table: &LookupTableVar,
@@ -10,32 +12,46 @@ pub fn g(
     m_0: &U32Var,
     m_1: &U32Var,
 ) {
+    try_g(table, a_ref, b_ref, c_ref, d_ref, m_0, m_1).unwrap()
+}
+
+pub fn try_g(
+    table: &LookupTableVar,
+    a_ref: &mut U32Var,
+    b_ref: &mut U32Var,
+    c_ref: &mut U32Var,
+    d_ref: &mut U32Var,
+    m_0: &U32Var,
+    m_1: &U32Var,
+) -> Result<()> {
     let mut a = a_ref.clone();
     let mut b = b_ref.clone();
     let mut c = c_ref.clone();
     let mut d = d_ref.clone();
 
-    a = &a + (table, &b, m_0);
-    d = (&d ^ (table, &a)).rotate_right_shift_16();
-    c = &c + (table, &d);
-    b = (&b ^ (table, &c)).rotate_right_shift_12();
-    a = &a + (table, &b, m_1);
-    d = (&d ^ (table, &a)).rotate_right_shift_8();
-    c = &c + (table, &d);
-    b = (&b ^ (table, &c)).rotate_right_shift_7(table);
+    a = a.try_add((table, &b, m_0))?;
+    d = d.try_xor(table, &a)?.rotate_right_shift_16();
+    c = c.try_add((table, &d))?;
+    b = b.try_xor(table, &c)?.rotate_right_shift_12();
+    a = a.try_add((table, &b, m_1))?;
+    d = d.try_xor(table, &a)?.rotate_right_shift_8();
+    c = c.try_add((table, &d))?;
+    b = b.try_xor(table, &c)?.try_rotate_right_shift_7(table)?;
 
     *a_ref = a;
     *b_ref = b;
     *c_ref = c;
     *d_ref = d;
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use crate::compression::blake3::g::g;
     use crate::compression::blake3::lookup_table::LookupTableVar;
-    use crate::compression::blake3::reference::g_reference;
+    use crate::compression::blake3::native::g_reference;
     use crate::limbs::u32::U32Var;
+    use crate::test_utils::u32s_to_nibbles;
     use bitcoin_circle_stark::treepp::*;
     use bitcoin_script_dsl::bvar::{AllocVar, BVar};
     use bitcoin_script_dsl::constraint_system::ConstraintSystem;
@@ -86,14 +102,7 @@ mod test {
             cs.set_program_output(&c_var).unwrap();
             cs.set_program_output(&d_var).unwrap();
 
-            let mut values = vec![];
-            for v in [a, b, c, d].iter() {
-                let mut v = *v;
-                for _ in 0..8 {
-                    values.push(v & 15);
-                    v >>= 4;
-                }
-            }
+            let values = u32s_to_nibbles(&[a, b, c, d]);
 
             test_program_without_opcat(
                 cs,
@@ -0,0 +1,428 @@
+use crate::compression::blake3::{
+    parent_with_flags, tree_key_and_flags, try_compress_block, Blake3ConstantVar, Blake3HashVar,
+    ToU4LimbVar, CHUNK_END, CHUNK_START, MAX_BLOCKS, PARENT, ROOT,
+};
+use crate::limbs::u32::U32Var;
+use crate::limbs::u4::U4Var;
+use anyhow::{Error, Result};
+use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
+use serde::{Deserialize, Serialize};
+
+/// The number of limbs in a block of 64 bytes.
+const BLOCK_LIMBS: usize = 128;
+
+/// A streaming Blake3 hasher in script, which follows the spec for inputs of any length.
+///
+/// A block is compressed as soon as more limbs follow it, as the last block is compressed with
+/// different flags. The chaining values of the completed chunks are merged into the tree as
+/// soon as possible, as in the `blake3` crate. The hash is keyed if the constant has a key.
+pub struct Blake3Hasher<'a> {
+    pub constant: &'a Blake3ConstantVar,
+    /// The chaining value within the current chunk.
+    pub chunk_cv: Blake3HashVar,
+    /// The number of blocks of the current chunk that are compressed.
+    pub blocks_in_chunk: usize,
+    /// The limbs that are not compressed yet, at most one block.
+    pub buffer: Vec<U4Var>,
+    /// The chaining values of the subtrees that are not merged yet.
+    pub cv_stack: Vec<Blake3HashVar>,
+    pub chunk_counter: u64,
+}
+
+/// The state of a `Blake3Hasher` as plain values, so that a hash too large for one transaction
+/// can be split across constraint systems at a commitment to this state.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Blake3HasherState {
+    pub chunk_cv: [u32; 8],
+    pub blocks_in_chunk: usize,
+    /// The buffered limbs, one nibble per element.
+    pub buffer: Vec<u8>,
+    pub cv_stack: Vec<[u32; 8]>,
+    pub chunk_counter: u64,
+}
+
+impl<'a> Blake3Hasher<'a> {
+    pub fn new(constant: &'a Blake3ConstantVar) -> Self {
+        Self {
+            constant,
+            chunk_cv: tree_key_and_flags(constant).0.clone(),
+            blocks_in_chunk: 0,
+            buffer: vec![],
+            cv_stack: vec![],
+            chunk_counter: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: impl ToU4LimbVar) {
+        self.try_update(data).unwrap()
+    }
+
+    pub fn try_update(&mut self, data: impl ToU4LimbVar) -> Result<()> {
+        self.buffer.extend(data.to_u4_limbs());
+
+        while self.buffer.len() > BLOCK_LIMBS {
+            let block = self.buffer.drain(..BLOCK_LIMBS).collect::<Vec<_>>();
+
+            let mut flags = 0;
+            if self.blocks_in_chunk == 0 {
+                flags ^= CHUNK_START;
+            }
+            if self.blocks_in_chunk == MAX_BLOCKS - 1 {
+                flags ^= CHUNK_END;
+            }
+            self.chunk_cv = self.compress(&block, flags)?;
+            self.blocks_in_chunk += 1;
+
+            if self.blocks_in_chunk == MAX_BLOCKS {
+                self.push_chunk_cv()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finalize(self) -> Blake3HashVar {
+        self.try_finalize().unwrap()
+    }
+
+    pub fn try_finalize(mut self) -> Result<Blake3HashVar> {
+        assert_eq!(
+            self.buffer.len() % 2,
+            0,
+            "The number of u4 limbs should be even (byte aligned)"
+        );
+
+        let mut flags = CHUNK_END;
+        if self.blocks_in_chunk == 0 {
+            flags ^= CHUNK_START;
+        }
+        if self.chunk_counter == 0 {
+            flags ^= ROOT;
+        }
+        let block = std::mem::take(&mut self.buffer);
+        let mut cv = self.compress(&block, flags)?;
+
+        while let Some(left) = self.cv_stack.pop() {
+            let flags = if self.cv_stack.is_empty() {
+                PARENT ^ ROOT
+            } else {
+                PARENT
+            };
+            cv = parent_with_flags(self.constant, &left, &cv, flags)?;
+        }
+        Ok(cv)
+    }
+
+    /// Return the values of the state, to be committed before the computation is split.
+    pub fn export_state(&self) -> Result<Blake3HasherState> {
+        Ok(Blake3HasherState {
+            chunk_cv: hash_words(&self.chunk_cv)?,
+            blocks_in_chunk: self.blocks_in_chunk,
+            buffer: self
+                .buffer
+                .iter()
+                .map(|limb| Ok(limb.value()? as u8))
+                .collect::<Result<Vec<_>>>()?,
+            cv_stack: self
+                .cv_stack
+                .iter()
+                .map(hash_words)
+                .collect::<Result<Vec<_>>>()?,
+            chunk_counter: self.chunk_counter,
+        })
+    }
+
+    /// Restore a hasher from its state, with the chaining values and the buffered limbs
+    /// allocated in the given mode. Program inputs and hints are range-checked.
+    pub fn import_state(
+        constant: &'a Blake3ConstantVar,
+        state: &Blake3HasherState,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        if state.blocks_in_chunk >= MAX_BLOCKS || state.buffer.len() > BLOCK_LIMBS {
+            return Err(Error::msg("The state of the Blake3 hasher is malformed."));
+        }
+        // the stack holds one subtree for each bit set in the number of completed chunks
+        if state.cv_stack.len() != state.chunk_counter.count_ones() as usize {
+            return Err(Error::msg(format!(
+                "The state of the Blake3 hasher has {} subtrees after {} chunks, but {} are expected.",
+                state.cv_stack.len(),
+                state.chunk_counter,
+                state.chunk_counter.count_ones()
+            )));
+        }
+
+        let cs = &constant.cs;
+
+        let alloc_hash = |words: &[u32; 8]| -> Result<Blake3HashVar> {
+            let mut hash = vec![];
+            for &word in words.iter() {
+                hash.push(U32Var::new_variable(cs, word, mode)?);
+            }
+            Ok(Blake3HashVar {
+                hash: hash.try_into().unwrap(),
+            })
+        };
+
+        let mut buffer = vec![];
+        for &limb in state.buffer.iter() {
+            buffer.push(U4Var::new_variable(cs, limb as u32, mode)?);
+        }
+
+        Ok(Self {
+            constant,
+            chunk_cv: alloc_hash(&state.chunk_cv)?,
+            blocks_in_chunk: state.blocks_in_chunk,
+            buffer,
+            cv_stack: state
+                .cv_stack
+                .iter()
+                .map(alloc_hash)
+                .collect::<Result<Vec<_>>>()?,
+            chunk_counter: state.chunk_counter,
+        })
+    }
+
+    /// Compress a block of at most 64 bytes into the chaining value of the current chunk.
+    fn compress(&self, block: &[U4Var], flags: u32) -> Result<Blake3HashVar> {
+        let mut limbs = block.to_vec();
+        limbs.resize(BLOCK_LIMBS, self.constant.zero_u32.limbs()[0].clone());
+
+        let mut words = vec![];
+        for chunk in limbs.chunks_exact(8) {
+            words.push(U32Var::from_limbs(chunk.to_vec().try_into().unwrap())?);
+        }
+
+        let (_, key_flags) = tree_key_and_flags(self.constant);
+        Ok(Blake3HashVar {
+            hash: try_compress_block(
+                self.constant,
+                &self.chunk_cv,
+                &words.try_into().unwrap(),
+                self.chunk_counter,
+                (block.len() / 2) as u32,
+                flags ^ key_flags,
+            )?,
+        })
+    }
+
+    /// Merge the chaining value of the completed chunk with the subtrees that it completes, and
+    /// start the next chunk.
+    fn push_chunk_cv(&mut self) -> Result<()> {
+        let mut cv = std::mem::replace(
+            &mut self.chunk_cv,
+            tree_key_and_flags(self.constant).0.clone(),
+        );
+        self.blocks_in_chunk = 0;
+        self.chunk_counter += 1;
+
+        let mut total_chunks = self.chunk_counter;
+        while total_chunks & 1 == 0 {
+            cv = parent_with_flags(self.constant, &self.cv_stack.pop().unwrap(), &cv, PARENT)?;
+            total_chunks >>= 1;
+        }
+        self.cv_stack.push(cv);
+        Ok(())
+    }
+}
+
+fn hash_words(hash: &Blake3HashVar) -> Result<[u32; 8]> {
+    let mut words = [0u32; 8];
+    for (word, word_var) in words.iter_mut().zip(hash.hash.iter()) {
+        *word = word_var.value()?;
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::blake3::hasher::{Blake3Hasher, Blake3HasherState};
+    use crate::compression::blake3::{Blake3ConstantVar, Blake3HashVar};
+    use crate::limbs::u32::U32Var;
+    use crate::test_utils::u32s_to_nibbles;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::builtins::u8::U8Var;
+    use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn hasher_test_case(input: &[u8], update_size: usize) -> [u8; 32] {
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let input_var = input
+            .iter()
+            .map(|&byte| U8Var::new_program_input(&cs, byte).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut hasher = Blake3Hasher::new(&constant);
+        for chunk in input_var.chunks(update_size) {
+            hasher.update(chunk);
+        }
+        let digest = hasher.finalize();
+
+        let expected = *blake3::hash(input).as_bytes();
+        digest
+            .equalverify(&Blake3HashVar::new_constant(&cs, expected).unwrap())
+            .unwrap();
+        test_program_without_opcat(cs, script! {}).unwrap();
+
+        digest.value().unwrap()
+    }
+
+    #[test]
+    fn test_blake3_hasher_update_sizes() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        // two full chunks and a partial one, so that the tree has a parent below the root
+        let mut input = vec![0u8; 2048 + 100];
+        prng.fill(input.as_mut_slice());
+
+        let expected = *blake3::hash(&input).as_bytes();
+        for update_size in [1, 77, 1024, input.len()] {
+            assert_eq!(hasher_test_case(&input, update_size), expected);
+        }
+    }
+
+    #[test]
+    fn test_blake3_hasher_short_inputs() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for len in [0, 1, 64, 65, 1024] {
+            let mut input = vec![0u8; len];
+            prng.fill(input.as_mut_slice());
+
+            assert_eq!(
+                hasher_test_case(&input, 7),
+                *blake3::hash(&input).as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_blake3_hasher_keyed() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut input = vec![0u8; 2048 + 100];
+        prng.fill(input.as_mut_slice());
+        let mut key = [0u8; 32];
+        prng.fill(&mut key);
+
+        let cs = ConstraintSystem::new_ref();
+        let mut key_words = [0u32; 8];
+        for (word, chunk) in key_words.iter_mut().zip(key.chunks_exact(4)) {
+            *word = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+        let constant = Blake3ConstantVar::new_keyed(&cs, key_words);
+
+        let mut hasher = Blake3Hasher::new(&constant);
+        for chunk in input.chunks(100) {
+            let chunk_var = chunk
+                .iter()
+                .map(|&v| U8Var::new_program_input(&cs, v).unwrap())
+                .collect::<Vec<_>>();
+            hasher.update(chunk_var.as_slice());
+        }
+        let digest = hasher.finalize();
+
+        let expected = *blake3::keyed_hash(&key, &input).as_bytes();
+        assert_eq!(digest.value().unwrap(), expected);
+        digest
+            .equalverify(&Blake3HashVar::new_constant(&cs, expected).unwrap())
+            .unwrap();
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_hasher_export_import_state() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let words: [u32; 64] = std::array::from_fn(|_| prng.gen());
+        let expected = *blake3::hash(
+            &words
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect::<Vec<_>>(),
+        )
+        .as_bytes();
+
+        // the four blocks in one go
+        let one_go = {
+            let cs = ConstraintSystem::new_ref();
+            let constant = Blake3ConstantVar::new(&cs);
+
+            let words_var = words
+                .iter()
+                .map(|&word| U32Var::new_program_input(&cs, word).unwrap())
+                .collect::<Vec<_>>();
+            let mut hasher = Blake3Hasher::new(&constant);
+            hasher.update(words_var.as_slice());
+            let digest = hasher.finalize();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+            digest.value().unwrap()
+        };
+        assert_eq!(one_go, expected);
+
+        // the first two blocks, with the exported state committed as the program output
+        let state = {
+            let cs = ConstraintSystem::new_ref();
+            let constant = Blake3ConstantVar::new(&cs);
+
+            let words_var = words[..32]
+                .iter()
+                .map(|&word| U32Var::new_program_input(&cs, word).unwrap())
+                .collect::<Vec<_>>();
+            let mut hasher = Blake3Hasher::new(&constant);
+            hasher.update(words_var.as_slice());
+            let state = hasher.export_state().unwrap();
+            assert_eq!(state.blocks_in_chunk, 1);
+
+            hasher.chunk_cv.set_program_output().unwrap();
+            for limb in hasher.buffer.iter() {
+                cs.set_program_output(limb).unwrap();
+            }
+            let mut output = u32s_to_nibbles(&state.chunk_cv);
+            output.extend(state.buffer.iter().map(|&limb| limb as u32));
+            test_program_without_opcat(cs, script! { { output } }).unwrap();
+
+            state
+        };
+
+        let serialized = serde_json::to_string(&state).unwrap();
+        let state: Blake3HasherState = serde_json::from_str(&serialized).unwrap();
+
+        // the last two blocks, continuing from the imported state
+        let segmented = {
+            let cs = ConstraintSystem::new_ref();
+            let constant = Blake3ConstantVar::new(&cs);
+
+            let mut hasher =
+                Blake3Hasher::import_state(&constant, &state, AllocationMode::ProgramInput)
+                    .unwrap();
+            let words_var = words[32..]
+                .iter()
+                .map(|&word| U32Var::new_program_input(&cs, word).unwrap())
+                .collect::<Vec<_>>();
+            hasher.update(words_var.as_slice());
+            let digest = hasher.finalize();
+
+            digest
+                .equalverify(&Blake3HashVar::new_constant(&cs, expected).unwrap())
+                .unwrap();
+            test_program_without_opcat(cs, script! {}).unwrap();
+            digest.value().unwrap()
+        };
+        assert_eq!(segmented, one_go);
+
+        let mut malformed = state.clone();
+        malformed.cv_stack.push(malformed.chunk_cv);
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+        let err = Blake3Hasher::import_state(&constant, &malformed, AllocationMode::ProgramInput)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("subtrees"), "{}", err);
+    }
+}
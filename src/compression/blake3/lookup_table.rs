@@ -1,49 +1,83 @@
-use anyhow::Result;
+use crate::compression::blake3::cache::{get_or_create, Registry};
+use crate::watchdog::{track_pinned, WatchdogExt};
+use anyhow::{Error, Result};
 use bitcoin_circle_stark::treepp::*;
 use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
 use bitcoin_script_dsl::constraint_system::{ConstraintSystemRef, Element};
+use bitcoin_script_dsl::options::Options;
+use bitcoin_script_dsl::stack::Stack;
+use std::rc::Rc;
 
+thread_local! {
+    static LOOKUP_TABLES: Registry<LookupTableVar> = const { Registry::new(Vec::new()) };
+}
+
+/// The lookup tables used by the U4 gadgets. Each table is only allocated if it is requested
+/// through `LookupTableBuilder`, so that a gadget only pays for the tables that it uses.
+///
+/// The gadgets take the whole `LookupTableVar` and look up the tables that they need when they
+/// are called. The fallible forms, such as `try_xor` and `TryAdd`, return an error naming the
+/// builder method if a table is missing, whereas the operators panic with that error.
+///
+/// The tables stay on the main stack. Moving them to the altstack would not help deep
+/// circuits, since the limit of 1000 elements counts both stacks and OP_PICK can reach any
+/// depth, while pulling a table back for each lookup would cost an opcode per entry.
 #[derive(Debug, Clone)]
 pub struct LookupTableVar {
-    pub xor_table_var: XorTableVar,
-    pub row_table: RowTable,
-    pub shr3table_var: Shr3TableVar,
-    pub shl1table_var: Shl1TableVar,
-    pub quotient_table_var: QuotientTableVar,
-    pub remainder_table_var: RemainderTableVar,
+    pub cs: ConstraintSystemRef,
+    pub xor_table_var: Option<XorTableVar>,
+    pub row_table: Option<RowTable>,
+    pub shr3table_var: Option<Shr3TableVar>,
+    pub shl1table_var: Option<Shl1TableVar>,
+    pub shr1table_var: Option<Shr1TableVar>,
+    pub shr2table_var: Option<Shr2TableVar>,
+    pub shl2table_var: Option<Shl2TableVar>,
+    pub shl3table_var: Option<Shl3TableVar>,
+    pub quotient_table_var: Option<QuotientTableVar>,
+    pub remainder_table_var: Option<RemainderTableVar>,
 }
 
 impl BVar for LookupTableVar {
     type Value = ();
 
     fn cs(&self) -> ConstraintSystemRef {
-        self.xor_table_var
-            .cs()
-            .and(&self.row_table.cs())
-            .and(&self.shr3table_var.cs())
-            .and(&self.shl1table_var.cs())
-            .and(&self.quotient_table_var.cs())
-            .and(&self.remainder_table_var.cs())
+        self.cs.clone()
     }
 
     fn variables(&self) -> Vec<usize> {
-        self.xor_table_var
-            .variables()
-            .iter()
-            .chain(self.row_table.variables.iter())
-            .chain(self.shr3table_var.variables.iter())
-            .chain(self.shl1table_var.variables.iter())
-            .chain(self.quotient_table_var.variables.iter())
-            .chain(self.remainder_table_var.variables.iter())
-            .copied()
-            .collect()
+        let mut variables = vec![];
+        if let Some(table) = &self.xor_table_var {
+            variables.extend(table.variables.iter());
+        }
+        for table in [
+            self.row_table.as_ref().map(|t| &t.variables),
+            self.shr3table_var.as_ref().map(|t| &t.variables),
+            self.shl1table_var.as_ref().map(|t| &t.variables),
+            self.shr1table_var.as_ref().map(|t| &t.variables),
+            self.shr2table_var.as_ref().map(|t| &t.variables),
+            self.shl2table_var.as_ref().map(|t| &t.variables),
+            self.shl3table_var.as_ref().map(|t| &t.variables),
+            self.quotient_table_var.as_ref().map(|t| &t.variables),
+            self.remainder_table_var.as_ref().map(|t| &t.variables),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            variables.extend(table.iter());
+        }
+        variables
     }
 
+    /// The length when all the tables are allocated.
     fn length() -> usize {
         XorTableVar::length()
             + RowTable::length()
             + Shr3TableVar::length()
             + Shl1TableVar::length()
+            + Shr1TableVar::length()
+            + Shr2TableVar::length()
+            + Shl2TableVar::length()
+            + Shl3TableVar::length()
             + QuotientTableVar::length()
             + RemainderTableVar::length()
     }
@@ -54,26 +88,240 @@ impl BVar for LookupTableVar {
 }
 
 impl AllocVar for LookupTableVar {
+    /// Allocate all the tables.
     fn new_variable(
         cs: &ConstraintSystemRef,
-        data: <Self as BVar>::Value,
+        _: <Self as BVar>::Value,
         mode: AllocationMode,
     ) -> Result<Self> {
-        let shr3table_var = Shr3TableVar::new_variable(cs, data, mode)?;
-        let shl1table_var = Shl1TableVar::new_variable(cs, data, mode)?;
-        let xor_table_var = XorTableVar::new_variable(cs, data, mode)?;
-        let row_table = RowTable::new_variable(cs, data, mode)?;
-        let quotient_table_var = QuotientTableVar::new_variable(cs, data, mode)?;
-        let remainder_table_var = RemainderTableVar::new_variable(cs, data, mode)?;
+        assert_eq!(mode, AllocationMode::Constant);
+        Self::builder().with_all().build(cs)
+    }
+}
 
-        Ok(Self {
+/// The largest sum in the default quotient and remainder tables, which covers three limbs and
+/// a carry.
+pub const DEFAULT_MAX_SUM: u32 = 47;
+
+/// Select the tables to allocate for a `LookupTableVar`.
+#[derive(Debug, Clone, Copy)]
+pub struct LookupTableBuilder {
+    xor: bool,
+    hinted_xor: bool,
+    add: bool,
+    max_sum: u32,
+    rotate7: bool,
+    shift: bool,
+}
+
+impl Default for LookupTableBuilder {
+    fn default() -> Self {
+        Self {
+            xor: false,
+            hinted_xor: false,
+            add: false,
+            max_sum: DEFAULT_MAX_SUM,
+            rotate7: false,
+            shift: false,
+        }
+    }
+}
+
+impl LookupTableBuilder {
+    /// The XOR table and its row table, used by XOR, AND, and OR.
+    pub fn with_xor(mut self) -> Self {
+        self.xor = true;
+        self
+    }
+
+    /// The XOR table as in `with_xor`, but allocated as hints that the script checks entry by
+    /// entry, for a table supplied by the prover.
+    pub fn with_hinted_xor(mut self) -> Self {
+        self.xor = true;
+        self.hinted_xor = true;
+        self
+    }
+
+    /// The quotient and remainder tables, used by addition and subtraction.
+    pub fn with_add(mut self) -> Self {
+        self.add = true;
+        self
+    }
+
+    /// The quotient and remainder tables covering sums up to `max_sum`, which allows adding
+    /// more limbs at once, e.g., 111 for seven limbs and a carry.
+    pub fn with_max_sum(mut self, max_sum: u32) -> Self {
+        assert!(max_sum >= DEFAULT_MAX_SUM);
+        self.add = true;
+        self.max_sum = max_sum;
+        self
+    }
+
+    /// The shr3 and shl1 tables, which are all that the rotation by 7 in Blake3 needs.
+    pub fn with_rotate7(mut self) -> Self {
+        self.rotate7 = true;
+        self
+    }
+
+    /// All the shift tables, used by rotations by any number of bits.
+    pub fn with_shift(mut self) -> Self {
+        self.shift = true;
+        self
+    }
+
+    pub fn with_all(self) -> Self {
+        self.with_xor().with_add().with_shift()
+    }
+
+    pub fn build(self, cs: &ConstraintSystemRef) -> Result<LookupTableVar> {
+        let rotate7 = self.rotate7 || self.shift;
+
+        let shr3table_var = rotate7
+            .then(|| Shr3TableVar::new_constant(cs, ()))
+            .transpose()?;
+        let shl1table_var = rotate7
+            .then(|| Shl1TableVar::new_constant(cs, ()))
+            .transpose()?;
+        let shr1table_var = self
+            .shift
+            .then(|| Shr1TableVar::new_constant(cs, ()))
+            .transpose()?;
+        let shr2table_var = self
+            .shift
+            .then(|| Shr2TableVar::new_constant(cs, ()))
+            .transpose()?;
+        let shl2table_var = self
+            .shift
+            .then(|| Shl2TableVar::new_constant(cs, ()))
+            .transpose()?;
+        let shl3table_var = self
+            .shift
+            .then(|| Shl3TableVar::new_constant(cs, ()))
+            .transpose()?;
+        let xor_table_var = self
+            .xor
+            .then(|| {
+                if self.hinted_xor {
+                    XorTableVar::new_hint(cs, ())
+                } else {
+                    XorTableVar::new_constant(cs, ())
+                }
+            })
+            .transpose()?;
+        let row_table = self
+            .xor
+            .then(|| RowTable::new_constant(cs, ()))
+            .transpose()?;
+        let quotient_table_var = self
+            .add
+            .then(|| QuotientTableVar::new_with_max_sum(cs, self.max_sum))
+            .transpose()?;
+        let remainder_table_var = self
+            .add
+            .then(|| RemainderTableVar::new_with_max_sum(cs, self.max_sum))
+            .transpose()?;
+
+        let res = LookupTableVar {
+            cs: cs.clone(),
             xor_table_var,
             row_table,
             shr3table_var,
             shl1table_var,
+            shr1table_var,
+            shr2table_var,
+            shl2table_var,
+            shl3table_var,
             quotient_table_var,
             remainder_table_var,
-        })
+        };
+        track_pinned(cs, res.variables().len());
+        if self.hinted_xor {
+            res.xor_table_var.as_ref().unwrap().verify()?;
+        }
+        Ok(res)
+    }
+}
+
+impl LookupTableVar {
+    pub fn builder() -> LookupTableBuilder {
+        LookupTableBuilder::default()
+    }
+
+    /// Return the table with all the tables allocated for the constraint system, which is only
+    /// allocated by the first call so that gadgets built separately share it.
+    pub fn get_or_create(cs: &ConstraintSystemRef) -> Rc<LookupTableVar> {
+        get_or_create(&LOOKUP_TABLES, cs, |cs| Self::new_constant(cs, ()).unwrap())
+    }
+
+    /// The variable of the entry for 0 in the XOR table, which the scripts use as a reference.
+    pub fn xor_table_ref(&self) -> Result<u32> {
+        table_ref(&self.xor_table_var, "XOR", "with_xor")
+    }
+
+    pub fn row_table_ref(&self) -> Result<u32> {
+        table_ref(&self.row_table, "row", "with_xor")
+    }
+
+    pub fn quotient_table_ref(&self) -> Result<u32> {
+        table_ref(&self.quotient_table_var, "quotient", "with_add")
+    }
+
+    pub fn remainder_table_ref(&self) -> Result<u32> {
+        table_ref(&self.remainder_table_var, "remainder", "with_add")
+    }
+
+    /// The reference of the table that shifts right by `bits` in 1..=3.
+    pub fn shr_table_ref(&self, bits: usize) -> Result<u32> {
+        match bits {
+            1 => table_ref(&self.shr1table_var, "shr1", "with_shift"),
+            2 => table_ref(&self.shr2table_var, "shr2", "with_shift"),
+            3 => table_ref(&self.shr3table_var, "shr3", "with_rotate7"),
+            _ => Err(Error::msg("The shift should be in 1..=3.")),
+        }
+    }
+
+    /// The reference of the table that shifts left by `bits` in 1..=3.
+    pub fn shl_table_ref(&self, bits: usize) -> Result<u32> {
+        match bits {
+            1 => table_ref(&self.shl1table_var, "shl1", "with_rotate7"),
+            2 => table_ref(&self.shl2table_var, "shl2", "with_shift"),
+            3 => table_ref(&self.shl3table_var, "shl3", "with_shift"),
+            _ => Err(Error::msg("The shift should be in 1..=3.")),
+        }
+    }
+
+    /// The largest sum that the quotient and remainder tables cover.
+    pub fn max_sum(&self) -> Result<u32> {
+        match &self.quotient_table_var {
+            Some(table) => Ok(table.variables.len() as u32 - 1),
+            None => Err(Error::msg(
+                "The quotient table is not allocated, which requires LookupTableBuilder::with_add.",
+            )),
+        }
+    }
+
+    /// The number of limbs that can be added with an incoming carry in a single lookup, where
+    /// the carry is at most `max_sum / 16`.
+    pub fn max_addends(&self) -> Result<usize> {
+        let max_sum = self.max_sum()?;
+        Ok(((max_sum - max_sum / 16) / 15) as usize)
+    }
+}
+
+/// The depth of the entry for 0 of the table whose reference is under `key` in `options`,
+/// before the inputs of the script are consumed.
+pub(crate) fn table_depth(stack: &mut Stack, options: &Options, key: &str) -> Result<usize> {
+    let entry_zero = options.get_u32(key)?;
+    stack.get_relative_position(entry_zero as usize)
+}
+
+fn table_ref<T: BVar>(table: &Option<T>, name: &str, builder_method: &str) -> Result<u32> {
+    match table {
+        Some(table) => Ok(*table.variables().last().unwrap() as u32),
+        None => Err(Error::msg(format!(
+            "The {} table is not allocated, which requires LookupTableBuilder::{}.",
+            name, builder_method
+        ))),
     }
 }
 
@@ -114,16 +362,28 @@ impl AllocVar for XorTableVar {
     }
 
     fn new_constant(cs: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
-        let mut values = vec![];
-        for i in (0..16).rev() {
-            for j in (0..16).rev() {
-                values.push(i ^ j);
-            }
-        }
+        Self::alloc_entries(cs, AllocationMode::Constant)
+    }
 
+    fn new_program_input(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn new_function_output(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    /// Allocate the table as hints, which must be checked by `verify` before use.
+    fn new_hint(cs: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        Self::alloc_entries(cs, AllocationMode::Hint)
+    }
+}
+
+impl XorTableVar {
+    fn alloc_entries(cs: &ConstraintSystemRef, mode: AllocationMode) -> Result<Self> {
         let mut variables = vec![];
-        for &v in values.iter() {
-            variables.push(cs.alloc(Element::Num(v), AllocationMode::Constant)?);
+        for v in xor_table_entries() {
+            variables.push(cs.alloc(Element::Num(v), mode)?);
         }
 
         Ok(Self {
@@ -132,17 +392,38 @@ impl AllocVar for XorTableVar {
         })
     }
 
-    fn new_program_input(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
-        unimplemented!()
+    /// Check in script that every entry is `i ^ j`, for a table that does not come from the
+    /// script itself, such as one allocated by `new_hint`. The entries are picked, so the table
+    /// stays on the stack for the lookups.
+    pub fn verify(&self) -> Result<()> {
+        self.cs.insert_gadget_complex(
+            "verify_xor_table",
+            verify_xor_table,
+            [],
+            &Options::new().with_u32("xor_table_ref", *self.variables.last().unwrap() as u32),
+        )
     }
+}
 
-    fn new_function_output(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
-        unimplemented!()
+fn xor_table_entries() -> Vec<i32> {
+    let mut values = vec![];
+    for i in (0..16).rev() {
+        for j in (0..16).rev() {
+            values.push(i ^ j);
+        }
     }
+    values
+}
 
-    fn new_hint(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
-        unimplemented!()
-    }
+fn verify_xor_table(stack: &mut Stack, options: &Options) -> Result<Script> {
+    let k_xor = table_depth(stack, options, "xor_table_ref")?;
+
+    // the entry for `i ^ j` is `16 * i + j` elements below the entry for `0 ^ 0`
+    Ok(script! {
+        for (m, v) in xor_table_entries().into_iter().rev().enumerate() {
+            { k_xor + m } OP_PICK { v } OP_EQUALVERIFY
+        }
+    })
 }
 
 #[derive(Clone, Debug)]
@@ -335,12 +616,12 @@ impl AllocVar for Shl1TableVar {
 }
 
 #[derive(Clone, Debug)]
-pub struct QuotientTableVar {
+pub struct Shr1TableVar {
     pub variables: Vec<usize>,
     pub cs: ConstraintSystemRef,
 }
 
-impl BVar for QuotientTableVar {
+impl BVar for Shr1TableVar {
     type Value = ();
 
     fn cs(&self) -> ConstraintSystemRef {
@@ -352,7 +633,7 @@ impl BVar for QuotientTableVar {
     }
 
     fn length() -> usize {
-        48
+        16
     }
 
     fn value(&self) -> Result<Self::Value> {
@@ -360,7 +641,7 @@ impl BVar for QuotientTableVar {
     }
 }
 
-impl AllocVar for QuotientTableVar {
+impl AllocVar for Shr1TableVar {
     fn new_variable(
         cs: &ConstraintSystemRef,
         _: <Self as BVar>::Value,
@@ -372,9 +653,11 @@ impl AllocVar for QuotientTableVar {
 
     fn new_constant(cs: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
         let mut variables = vec![];
-        cs.insert_script(create_quotient_table, [])?;
-        for i in (0..48).rev() {
-            variables.push(cs.alloc(Element::Num(i / 16), AllocationMode::FunctionOutput)?);
+        for i in (0..16).rev() {
+            variables.push(cs.alloc(
+                Element::Num(((i as u32) >> 1) as i32),
+                AllocationMode::Constant,
+            )?);
         }
 
         Ok(Self {
@@ -396,45 +679,77 @@ impl AllocVar for QuotientTableVar {
     }
 }
 
-fn create_quotient_table() -> Script {
-    script! {
-        // 47 - 32 == 2, 16 numbers
-        // 31 - 16 == 1, 16 numbers
-        // 15 - 0 == 0, 16 numbers
+#[derive(Clone, Debug)]
+pub struct Shr2TableVar {
+    pub variables: Vec<usize>,
+    pub cs: ConstraintSystemRef,
+}
 
-        OP_PUSHNUM_2
-        OP_DUP
-        OP_2DUP
-        OP_3DUP
-        OP_3DUP
-        OP_3DUP
-        OP_3DUP
+impl BVar for Shr2TableVar {
+    type Value = ();
 
-        OP_PUSHNUM_1
-        OP_DUP
-        OP_2DUP
-        OP_3DUP
-        OP_3DUP
-        OP_3DUP
-        OP_3DUP
+    fn cs(&self) -> ConstraintSystemRef {
+        self.cs.clone()
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        self.variables.clone()
+    }
+
+    fn length() -> usize {
+        16
+    }
 
-        OP_PUSHBYTES_0
-        OP_DUP
-        OP_2DUP
-        OP_3DUP
-        OP_3DUP
-        OP_3DUP
-        OP_3DUP
+    fn value(&self) -> Result<Self::Value> {
+        Ok(())
+    }
+}
+
+impl AllocVar for Shr2TableVar {
+    fn new_variable(
+        cs: &ConstraintSystemRef,
+        _: <Self as BVar>::Value,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        assert_eq!(mode, AllocationMode::Constant);
+        Self::new_constant(cs, ())
+    }
+
+    fn new_constant(cs: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        let mut variables = vec![];
+        for i in (0..16).rev() {
+            variables.push(cs.alloc(
+                Element::Num(((i as u32) >> 2) as i32),
+                AllocationMode::Constant,
+            )?);
+        }
+
+        Ok(Self {
+            variables,
+            cs: cs.clone(),
+        })
+    }
+
+    fn new_program_input(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn new_function_output(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn new_hint(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct RemainderTableVar {
+pub struct Shl2TableVar {
     pub variables: Vec<usize>,
     pub cs: ConstraintSystemRef,
 }
 
-impl BVar for RemainderTableVar {
+impl BVar for Shl2TableVar {
     type Value = ();
 
     fn cs(&self) -> ConstraintSystemRef {
@@ -446,7 +761,7 @@ impl BVar for RemainderTableVar {
     }
 
     fn length() -> usize {
-        48
+        16
     }
 
     fn value(&self) -> Result<Self::Value> {
@@ -454,7 +769,7 @@ impl BVar for RemainderTableVar {
     }
 }
 
-impl AllocVar for RemainderTableVar {
+impl AllocVar for Shl2TableVar {
     fn new_variable(
         cs: &ConstraintSystemRef,
         _: <Self as BVar>::Value,
@@ -466,7 +781,244 @@ impl AllocVar for RemainderTableVar {
 
     fn new_constant(cs: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
         let mut variables = vec![];
-        for i in (0..48).rev() {
+        for i in (0..16).rev() {
+            variables.push(cs.alloc(
+                Element::Num(((i as u32) << 2) as i32 & 15),
+                AllocationMode::Constant,
+            )?);
+        }
+
+        Ok(Self {
+            variables,
+            cs: cs.clone(),
+        })
+    }
+
+    fn new_program_input(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn new_function_output(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn new_hint(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Shl3TableVar {
+    pub variables: Vec<usize>,
+    pub cs: ConstraintSystemRef,
+}
+
+impl BVar for Shl3TableVar {
+    type Value = ();
+
+    fn cs(&self) -> ConstraintSystemRef {
+        self.cs.clone()
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        self.variables.clone()
+    }
+
+    fn length() -> usize {
+        16
+    }
+
+    fn value(&self) -> Result<Self::Value> {
+        Ok(())
+    }
+}
+
+impl AllocVar for Shl3TableVar {
+    fn new_variable(
+        cs: &ConstraintSystemRef,
+        _: <Self as BVar>::Value,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        assert_eq!(mode, AllocationMode::Constant);
+        Self::new_constant(cs, ())
+    }
+
+    fn new_constant(cs: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        let mut variables = vec![];
+        for i in (0..16).rev() {
+            variables.push(cs.alloc(
+                Element::Num(((i as u32) << 3) as i32 & 15),
+                AllocationMode::Constant,
+            )?);
+        }
+
+        Ok(Self {
+            variables,
+            cs: cs.clone(),
+        })
+    }
+
+    fn new_program_input(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn new_function_output(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn new_hint(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct QuotientTableVar {
+    pub variables: Vec<usize>,
+    pub cs: ConstraintSystemRef,
+}
+
+impl BVar for QuotientTableVar {
+    type Value = ();
+
+    fn cs(&self) -> ConstraintSystemRef {
+        self.cs.clone()
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        self.variables.clone()
+    }
+
+    /// The length of the default table.
+    fn length() -> usize {
+        DEFAULT_MAX_SUM as usize + 1
+    }
+
+    fn value(&self) -> Result<Self::Value> {
+        Ok(())
+    }
+}
+
+impl AllocVar for QuotientTableVar {
+    fn new_variable(
+        cs: &ConstraintSystemRef,
+        _: <Self as BVar>::Value,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        assert_eq!(mode, AllocationMode::Constant);
+        Self::new_constant(cs, ())
+    }
+
+    fn new_constant(cs: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        Self::new_with_max_sum(cs, DEFAULT_MAX_SUM)
+    }
+
+    fn new_program_input(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn new_function_output(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn new_hint(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        unimplemented!()
+    }
+}
+
+impl QuotientTableVar {
+    /// Allocate the table of `i / 16` for `i` in 0..=max_sum.
+    pub fn new_with_max_sum(cs: &ConstraintSystemRef, max_sum: u32) -> Result<Self> {
+        let mut variables = vec![];
+        cs.insert_gadget_complex(
+            "create_quotient_table",
+            create_quotient_table,
+            [],
+            &Options::new().with_u32("max_sum", max_sum),
+        )?;
+        for i in (0..=max_sum as i32).rev() {
+            variables.push(cs.alloc(Element::Num(i / 16), AllocationMode::FunctionOutput)?);
+        }
+
+        Ok(Self {
+            variables,
+            cs: cs.clone(),
+        })
+    }
+}
+
+fn create_quotient_table(_: &mut Stack, options: &Options) -> Result<Script> {
+    let max_sum = options.get_u32("max_sum")?;
+
+    // push each quotient from the highest one and duplicate it once per entry, using the
+    // largest OP_DUP variant that the copies on the stack allow
+    let mut counts = vec![];
+    for quotient in (0..=max_sum / 16).rev() {
+        counts.push((quotient, (max_sum - 16 * quotient + 1).min(16)));
+    }
+
+    Ok(script! {
+        for (quotient, count) in counts {
+            { quotient }
+            for dup in dup_sequence(count) {
+                if dup == 3 {
+                    OP_3DUP
+                } else if dup == 2 {
+                    OP_2DUP
+                } else {
+                    OP_DUP
+                }
+            }
+        }
+    })
+}
+
+/// The sizes of the OP_DUP, OP_2DUP, and OP_3DUP that grow one element into `count` copies.
+fn dup_sequence(count: u32) -> Vec<u32> {
+    let mut res = vec![];
+    let mut copies = 1;
+    while copies < count {
+        let dup = (1..=3)
+            .rev()
+            .find(|&dup| copies >= dup && count - copies >= dup)
+            .unwrap();
+        res.push(dup);
+        copies += dup;
+    }
+    res
+}
+
+#[derive(Clone, Debug)]
+pub struct RemainderTableVar {
+    pub variables: Vec<usize>,
+    pub cs: ConstraintSystemRef,
+}
+
+impl BVar for RemainderTableVar {
+    type Value = ();
+
+    fn cs(&self) -> ConstraintSystemRef {
+        self.cs.clone()
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        self.variables.clone()
+    }
+
+    /// The length of the default table.
+    fn length() -> usize {
+        DEFAULT_MAX_SUM as usize + 1
+    }
+
+    fn value(&self) -> Result<Self::Value> {
+        Ok(())
+    }
+}
+
+impl RemainderTableVar {
+    /// Allocate the table of `i % 16` for `i` in 0..=max_sum.
+    pub fn new_with_max_sum(cs: &ConstraintSystemRef, max_sum: u32) -> Result<Self> {
+        let mut variables = vec![];
+        for i in (0..=max_sum as i32).rev() {
             variables.push(cs.alloc(Element::Num(i % 16), AllocationMode::Constant)?);
         }
 
@@ -475,6 +1027,21 @@ impl AllocVar for RemainderTableVar {
             cs: cs.clone(),
         })
     }
+}
+
+impl AllocVar for RemainderTableVar {
+    fn new_variable(
+        cs: &ConstraintSystemRef,
+        _: <Self as BVar>::Value,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        assert_eq!(mode, AllocationMode::Constant);
+        Self::new_constant(cs, ())
+    }
+
+    fn new_constant(cs: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
+        Self::new_with_max_sum(cs, DEFAULT_MAX_SUM)
+    }
 
     fn new_program_input(_: &ConstraintSystemRef, _: <Self as BVar>::Value) -> Result<Self> {
         unimplemented!()
@@ -491,11 +1058,22 @@ impl AllocVar for RemainderTableVar {
 
 #[cfg(test)]
 mod test {
-    use crate::compression::blake3::lookup_table::LookupTableVar;
+    use crate::compression::blake3::lookup_table::{
+        LookupTableVar, QuotientTableVar, RemainderTableVar, RowTable, Shl1TableVar, Shl2TableVar,
+        Shl3TableVar, Shr1TableVar, Shr2TableVar, Shr3TableVar, XorTableVar,
+    };
+    use crate::compression::blake3::Blake3ConstantVar;
+    use crate::limbs::u32::U32Var;
+    use crate::limbs::u4::U4Var;
+    use crate::limbs::TryAdd;
+    use crate::test_utils::expect_program_failure_without_opcat;
     use bitcoin_circle_stark::treepp::*;
-    use bitcoin_script_dsl::bvar::AllocVar;
-    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
+    use bitcoin_script_dsl::constraint_system::{ConstraintSystem, Element};
     use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use std::rc::Rc;
 
     #[test]
     fn test_table() {
@@ -503,4 +1081,202 @@ mod test {
         let _ = LookupTableVar::new_constant(&cs, ()).unwrap();
         test_program_without_opcat(cs, script! {}).unwrap();
     }
+
+    #[test]
+    fn test_table_builder_add_only() {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder().with_add().build(&cs).unwrap();
+
+        let a_var = U32Var::new_program_input(&cs, 0x89abcdef).unwrap();
+        let b_var = U32Var::new_program_input(&cs, 0x76543211).unwrap();
+        let sum_var = &a_var + (&table, &b_var);
+        sum_var
+            .equalverify(&U32Var::new_constant(&cs, 0).unwrap())
+            .unwrap();
+
+        let x_var = U4Var::new_program_input(&cs, 3).unwrap();
+        let err = x_var.try_xor(&table, &x_var).unwrap_err();
+        assert!(err.to_string().contains("with_xor"));
+        assert!(table.shr_table_ref(1).is_err());
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_table_builder_footprint() {
+        let cs = ConstraintSystem::new_ref();
+
+        let full = LookupTableVar::new_constant(&cs, ()).unwrap();
+        let add_only = LookupTableVar::builder().with_add().build(&cs).unwrap();
+        let empty = LookupTableVar::builder().build(&cs).unwrap();
+        let blake3 = Blake3ConstantVar::new(&cs).table;
+
+        assert_eq!(full.variables().len(), LookupTableVar::length());
+        assert_eq!(add_only.variables().len(), 96);
+        assert!(empty.variables().is_empty());
+        assert!(blake3.variables().len() < full.variables().len());
+        assert!(blake3.shl_table_ref(1).is_ok() && blake3.shl_table_ref(2).is_err());
+    }
+
+    #[test]
+    fn test_table_builder_xor_only() {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder().with_xor().build(&cs).unwrap();
+        assert_eq!(table.variables().len(), 256 + 16);
+
+        let a_var = U32Var::new_program_input(&cs, 0x12345678).unwrap();
+        let b_var = U32Var::new_program_input(&cs, 0x9abcdef0).unwrap();
+        let xor_var = &a_var ^ (&table, &b_var);
+        xor_var
+            .equalverify(&U32Var::new_constant(&cs, 0x12345678 ^ 0x9abcdef0).unwrap())
+            .unwrap();
+
+        assert!(table.quotient_table_ref().is_err());
+        assert!(table.max_sum().is_err());
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_table_builder_missing_add_tables() {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder().with_xor().build(&cs).unwrap();
+
+        let a_var = U4Var::new_program_input(&cs, 3).unwrap();
+        let err = (&a_var).try_add((&table, &a_var)).err().unwrap();
+        assert!(
+            err.to_string().contains("LookupTableBuilder::with_add"),
+            "{}",
+            err
+        );
+
+        let a_var = U32Var::new_program_input(&cs, 3).unwrap();
+        let err = (&a_var).try_add((&table, &a_var)).err().unwrap();
+        assert!(
+            err.to_string().contains("LookupTableBuilder::with_add"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "LookupTableBuilder::with_add")]
+    fn test_table_builder_missing_add_tables_operator() {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder().with_xor().build(&cs).unwrap();
+
+        let a_var = U4Var::new_program_input(&cs, 3).unwrap();
+        let _ = &a_var + (&table, &a_var);
+    }
+
+    #[test]
+    fn test_table_reversed_order() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let cs = ConstraintSystem::new_ref();
+
+        // the fields are evaluated in order, so the tables are allocated in the reverse order
+        // of `LookupTableBuilder::build`
+        let table = LookupTableVar {
+            remainder_table_var: Some(RemainderTableVar::new_constant(&cs, ()).unwrap()),
+            quotient_table_var: Some(QuotientTableVar::new_constant(&cs, ()).unwrap()),
+            row_table: Some(RowTable::new_constant(&cs, ()).unwrap()),
+            xor_table_var: Some(XorTableVar::new_constant(&cs, ()).unwrap()),
+            shl3table_var: Some(Shl3TableVar::new_constant(&cs, ()).unwrap()),
+            shl2table_var: Some(Shl2TableVar::new_constant(&cs, ()).unwrap()),
+            shr2table_var: Some(Shr2TableVar::new_constant(&cs, ()).unwrap()),
+            shr1table_var: Some(Shr1TableVar::new_constant(&cs, ()).unwrap()),
+            shl1table_var: Some(Shl1TableVar::new_constant(&cs, ()).unwrap()),
+            shr3table_var: Some(Shr3TableVar::new_constant(&cs, ()).unwrap()),
+            cs: cs.clone(),
+        };
+
+        let a: u32 = prng.gen();
+        let b: u32 = prng.gen();
+        let c: u32 = prng.gen();
+        let a_var = U32Var::new_program_input(&cs, a).unwrap();
+        let b_var = U32Var::new_program_input(&cs, b).unwrap();
+        let c_var = U32Var::new_program_input(&cs, c).unwrap();
+
+        let check = |var: U32Var, expected: u32| {
+            var.equalverify(&U32Var::new_constant(&cs, expected).unwrap())
+                .unwrap();
+        };
+        check(&a_var ^ (&table, &b_var), a ^ b);
+        check(&a_var & (&table, &b_var), a & b);
+        check(&a_var | (&table, &b_var), a | b);
+        check(
+            &a_var + (&table, &b_var, &c_var),
+            a.wrapping_add(b).wrapping_add(c),
+        );
+        check(&a_var - (&table, &b_var), a.wrapping_sub(b));
+        for n in 1..32 {
+            check(a_var.clone().rotate_right(n, &table), a.rotate_right(n));
+        }
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_xor_table_verify() {
+        let cs = ConstraintSystem::new_ref();
+        let table = XorTableVar::new_hint(&cs, ()).unwrap();
+        table.verify().unwrap();
+        test_program_without_opcat(cs, script! {}).unwrap();
+
+        // the hinted table stays on the stack after the check and serves the lookups
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder()
+            .with_hinted_xor()
+            .build(&cs)
+            .unwrap();
+        let a_var = U4Var::new_program_input(&cs, 3).unwrap();
+        let b_var = U4Var::new_program_input(&cs, 5).unwrap();
+        let res_var = a_var.try_xor(&table, &b_var).unwrap();
+        res_var
+            .equalverify(&U4Var::new_constant(&cs, 3 ^ 5).unwrap())
+            .unwrap();
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_lookup_table_get_or_create() {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::get_or_create(&cs);
+        let table_again = LookupTableVar::get_or_create(&cs);
+        assert!(Rc::ptr_eq(&table, &table_again));
+        assert_eq!(table.variables(), table_again.variables());
+
+        let other_cs = ConstraintSystem::new_ref();
+        let other_table = LookupTableVar::get_or_create(&other_cs);
+        assert!(!Rc::ptr_eq(&table, &other_table));
+
+        let a_var = U4Var::new_program_input(&cs, 3).unwrap();
+        let b_var = U4Var::new_program_input(&cs, 5).unwrap();
+        let res_var = &a_var ^ (table.as_ref(), &b_var);
+        let res_again_var = &a_var ^ (table_again.as_ref(), &b_var);
+        res_var.equalverify(&res_again_var).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_xor_table_verify_malicious() {
+        let cs = ConstraintSystem::new_ref();
+
+        let mut variables = vec![];
+        for i in (0..16).rev() {
+            for j in (0..16).rev() {
+                // a table where 3 ^ 5 is claimed to be 7
+                let v = if i == 3 && j == 5 { 7 } else { i ^ j };
+                variables.push(cs.alloc(Element::Num(v), AllocationMode::Hint).unwrap());
+            }
+        }
+        let table = XorTableVar {
+            variables,
+            cs: cs.clone(),
+        };
+        table.verify().unwrap();
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+    }
 }
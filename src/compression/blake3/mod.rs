@@ -1,34 +1,80 @@
+use crate::cost::Cost;
+use crate::limbs::bytes::BytesVar;
+use crate::limbs::u16::U16Var;
 use crate::limbs::u32::{U32CompactVar, U32Var};
 use crate::limbs::u4::U4Var;
-use bitcoin_script_dsl::bvar::AllocVar;
+use crate::limbs::u8::U8VarLimbs;
+use anyhow::{Error, Result};
+use bitcoin_circle_stark::treepp::*;
+use bitcoin_script_dsl::builtins::i32::I32Var;
+use bitcoin_script_dsl::builtins::u8::U8Var;
+use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
 use bitcoin_script_dsl::constraint_system::ConstraintSystemRef;
+use cache::{get_or_create, Registry};
 use lookup_table::LookupTableVar;
-use round::round;
-use std::cmp::min;
+use round::try_round;
+use std::cell::OnceCell;
 use std::ops::AddAssign;
+use std::rc::Rc;
 
+pub(crate) mod cache;
+pub mod eval;
 pub mod g;
+pub mod hasher;
 pub mod lookup_table;
+pub(crate) mod native;
 #[cfg(test)]
 pub(crate) mod reference;
 pub mod round;
+pub mod transcript;
 
 pub const IV: [u32; 8] = [
     0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
 ];
 
+pub const CHUNK_START: u32 = 1;
+pub const CHUNK_END: u32 = 2;
+pub const PARENT: u32 = 4;
+pub const ROOT: u32 = 8;
+pub const KEYED_HASH: u32 = 16;
+
+/// The number of rounds in a Blake3 compression.
+pub const ROUNDS: usize = 7;
+
+thread_local! {
+    static BLAKE3_CONSTANTS: Registry<Blake3ConstantVar> = const { Registry::new(Vec::new()) };
+    static COMPRESSION_COST: OnceCell<Cost> = const { OnceCell::new() };
+}
+
 pub struct Blake3ConstantVar {
     pub cs: ConstraintSystemRef,
     pub table: LookupTableVar,
     pub zero_u32: U32Var,
     pub iv: Blake3HashVar,
+    /// The key for the keyed hash mode, if any.
+    pub key: Option<Blake3HashVar>,
+    /// The number of rounds in each compression, which is `ROUNDS` unless reduced by
+    /// `with_rounds`.
+    pub(crate) rounds: usize,
 }
 
 impl Blake3ConstantVar {
+    /// Allocate the constants with a table of their own, which only has the XOR, add, and
+    /// rotation by 7 tables that Blake3 needs.
     pub fn new(cs: &ConstraintSystemRef) -> Blake3ConstantVar {
+        let table = LookupTableVar::builder()
+            .with_xor()
+            .with_add()
+            .with_rotate7()
+            .build(cs)
+            .unwrap();
+        Self::with_table(cs, table)
+    }
+
+    fn with_table(cs: &ConstraintSystemRef, table: LookupTableVar) -> Blake3ConstantVar {
         Blake3ConstantVar {
             cs: cs.clone(),
-            table: LookupTableVar::new_constant(cs, ()).unwrap(),
+            table,
             zero_u32: U32Var::new_constant(cs, 0).unwrap(),
             iv: Blake3HashVar {
                 hash: [
@@ -42,7 +88,78 @@ impl Blake3ConstantVar {
                     U32Var::new_constant(cs, IV[7]).unwrap(),
                 ],
             },
+            key: None,
+            rounds: ROUNDS,
+        }
+    }
+
+    /// Create the constants for a Blake3 with `rounds` rounds in each compression, which is
+    /// insecure below `ROUNDS` and only meant for tests and benchmarks.
+    #[cfg(feature = "insecure-reduced-rounds")]
+    pub fn with_rounds(cs: &ConstraintSystemRef, rounds: usize) -> Blake3ConstantVar {
+        assert!((1..=ROUNDS).contains(&rounds));
+        let mut constant = Self::new(cs);
+        constant.rounds = rounds;
+        constant
+    }
+
+    /// Return the constants for the constraint system, which are only allocated by the first
+    /// call, so that hashes computed at separate call sites share the lookup table and the IV.
+    /// The table is the one from `LookupTableVar::get_or_create`, which has all the tables and
+    /// is shared with the other gadgets.
+    pub fn get_or_create(cs: &ConstraintSystemRef) -> Rc<Blake3ConstantVar> {
+        get_or_create(&BLAKE3_CONSTANTS, cs, |cs| {
+            Self::with_table(cs, LookupTableVar::get_or_create(cs).as_ref().clone())
+        })
+    }
+
+    /// Estimate the cost of `hash` over `num_bytes` bytes, as one compression per block of 64
+    /// bytes, without the constants and the inputs, which callers may share or already have.
+    pub fn cost_of_hash(num_bytes: usize) -> Result<Cost> {
+        let num_blocks = num_bytes.div_ceil(64).max(1);
+        if num_blocks > MAX_BLOCKS {
+            return Err(Error::msg(format!(
+                "The input of {} bytes is longer than the {} blocks that this Blake3 implementation hashes.",
+                num_bytes, MAX_BLOCKS
+            )));
+        }
+        Ok(Self::cost_of_compression()? * num_blocks)
+    }
+
+    /// Measure the cost of a single compression, with the chaining value and the message as
+    /// program inputs. The compression is only compiled by the first call on each thread.
+    pub fn cost_of_compression() -> Result<Cost> {
+        if let Some(cost) = COMPRESSION_COST.with(|cost| cost.get().copied()) {
+            return Ok(cost);
         }
+        let cost = Cost::measure_op(
+            |cs| {
+                let constant = Blake3ConstantVar::new(cs);
+                let cv = Blake3HashVar::new_program_input(cs, [0u8; 32])?;
+                let mut words = vec![];
+                for _ in 0..16 {
+                    words.push(U32Var::new_program_input(cs, 0)?);
+                }
+                let words: [U32Var; 16] = words.try_into().unwrap();
+                Ok((constant, cv, words))
+            },
+            |(constant, cv, words)| {
+                try_compress_block(constant, cv, words, 0, 64, CHUNK_START | CHUNK_END | ROOT)?;
+                Ok(())
+            },
+        )?;
+        COMPRESSION_COST.with(|cached| {
+            cached.get_or_init(|| cost);
+        });
+        Ok(cost)
+    }
+
+    pub fn new_keyed(cs: &ConstraintSystemRef, key: [u32; 8]) -> Blake3ConstantVar {
+        let mut constant = Self::new(cs);
+        constant.key = Some(Blake3HashVar {
+            hash: key.map(|v| U32Var::new_constant(cs, v).unwrap()),
+        });
+        constant
     }
 }
 
@@ -51,87 +168,522 @@ pub struct Blake3HashVar {
     pub hash: [U32Var; 8],
 }
 
+impl Blake3HashVar {
+    pub fn equalverify(&self, other: &Blake3HashVar) -> Result<()> {
+        for (a, b) in self.hash.iter().zip(other.hash.iter()) {
+            a.equalverify(b)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_program_output(&self) -> Result<()> {
+        let cs = self.hash[0].cs();
+        for word in self.hash.iter() {
+            cs.set_program_output(word)?;
+        }
+        Ok(())
+    }
+
+    pub fn truncate(&self, n_words: usize) -> Vec<U32Var> {
+        assert!(n_words <= 8);
+        self.hash[0..n_words].to_vec()
+    }
+
+    /// The 32 bytes of the digest in the byte order of `value`, which are the digits that
+    /// `WinternitzSignatureVar::verify` takes for a key with w = 8.
+    pub fn to_u8_vars(&self) -> Result<Vec<U8Var>> {
+        self.to_u4_limbs()
+            .chunks_exact(2)
+            .map(|pair| U8Var::from_u4_pair(&pair[1], &pair[0]))
+            .collect()
+    }
+}
+
+/// The value is the digest in the byte order of the `blake3` crate, where each word is read in
+/// little-endian.
+impl BVar for Blake3HashVar {
+    type Value = [u8; 32];
+
+    fn cs(&self) -> ConstraintSystemRef {
+        let mut cs = self.hash[0].cs();
+        for word in self.hash.iter().skip(1) {
+            cs = cs.and(&word.cs());
+        }
+        cs
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        let mut variables = vec![];
+        for word in self.hash.iter() {
+            variables.extend(word.variables());
+        }
+        variables
+    }
+
+    fn length() -> usize {
+        64
+    }
+
+    fn value(&self) -> Result<Self::Value> {
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.hash.iter()) {
+            chunk.copy_from_slice(&word.value()?.to_le_bytes());
+        }
+        Ok(digest)
+    }
+}
+
+impl AllocVar for Blake3HashVar {
+    fn new_variable(
+        cs: &ConstraintSystemRef,
+        data: <Self as BVar>::Value,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        let mut hash = vec![];
+        for chunk in data.chunks_exact(4) {
+            let word = u32::from_le_bytes(chunk.try_into().unwrap());
+            hash.push(U32Var::new_variable(cs, word, mode)?);
+        }
+        Ok(Self {
+            hash: hash.try_into().unwrap(),
+        })
+    }
+}
+
+/// A Blake3 hash that only keeps the first few words, e.g., five words for a 160-bit commitment.
+#[derive(Clone)]
+pub struct Blake3TruncatedHashVar {
+    pub hash: Vec<U32Var>,
+}
+
+impl Blake3TruncatedHashVar {
+    pub fn new(hash: &Blake3HashVar, n_words: usize) -> Self {
+        Self {
+            hash: hash.truncate(n_words),
+        }
+    }
+
+    pub fn equalverify(&self, other: &Blake3TruncatedHashVar) -> Result<()> {
+        assert_eq!(self.hash.len(), other.hash.len());
+        for (a, b) in self.hash.iter().zip(other.hash.iter()) {
+            a.equalverify(b)?;
+        }
+        Ok(())
+    }
+}
+
+impl ToU4LimbVar for Blake3TruncatedHashVar {
+    fn to_u4_limbs(&self) -> Vec<U4Var> {
+        self.hash.as_slice().to_u4_limbs()
+    }
+}
+
+/// The number of blocks in a single chunk, which is the most that this implementation hashes.
+pub const MAX_BLOCKS: usize = 16;
+
 pub fn hash<T: ToU4LimbVar>(constant: &Blake3ConstantVar, v: T) -> Blake3HashVar {
-    let cs = constant.cs.clone();
+    hash_with_key_and_flags(constant, v, &constant.iv, 0).unwrap()
+}
+
+/// Compute the hash as `hash` does, but return an error instead of panicking if the input is
+/// not byte aligned or is longer than `MAX_BLOCKS` blocks. An odd nibble can be padded
+/// explicitly with `PaddedU4`.
+pub fn hash_checked<T: ToU4LimbVar>(constant: &Blake3ConstantVar, v: T) -> Result<Blake3HashVar> {
+    let u4_limbs = v.to_u4_limbs();
+    check_u4_limbs(&u4_limbs)?;
+    hash_with_key_and_flags(constant, u4_limbs.as_slice(), &constant.iv, 0)
+}
+
+/// Hash a byte string, which gives the same digest as the Blake3 hash of the bytes.
+pub fn hash_bytes(constant: &Blake3ConstantVar, bytes: &BytesVar) -> Result<Blake3HashVar> {
+    hash_checked(constant, bytes.bytes.as_slice())
+}
+
+pub(crate) fn check_u4_limbs(u4_limbs: &[U4Var]) -> Result<()> {
+    if u4_limbs.len() % 2 != 0 {
+        return Err(Error::msg(format!(
+            "The number of u4 limbs should be even (byte aligned), but it is {}.",
+            u4_limbs.len()
+        )));
+    }
+    if u4_limbs.len().div_ceil(512 / 4) > MAX_BLOCKS {
+        return Err(Error::msg(format!(
+            "The input of {} bytes is longer than the {} blocks that this Blake3 implementation hashes.",
+            u4_limbs.len() / 2,
+            MAX_BLOCKS
+        )));
+    }
+    Ok(())
+}
+
+/// Compute the hash of the limbs produced by `iter`, which are consumed one block at a time
+/// instead of being collected first.
+pub fn hash_iter(constant: &Blake3ConstantVar, iter: impl Iterator<Item = U4Var>) -> Blake3HashVar {
+    hash_iter_with_key_and_flags(constant, iter, &constant.iv, 0, 0, true).unwrap()
+}
+
+/// Compute the chaining value of the chunk of at most 1024 bytes at position `counter` of a
+/// longer input, which is not the root of the Blake3 tree. Combine the chaining values with
+/// `blake3_parent` and `blake3_parent_root` to hash inputs of more than one chunk. The chunk is
+/// keyed if `constant` has a key.
+pub fn hash_chunk<T: ToU4LimbVar>(
+    constant: &Blake3ConstantVar,
+    v: T,
+    counter: u64,
+) -> Blake3HashVar {
+    let (key, flags) = tree_key_and_flags(constant);
+    hash_iter_with_key_and_flags(
+        constant,
+        v.to_u4_limbs().into_iter(),
+        key,
+        flags,
+        counter,
+        false,
+    )
+    .unwrap()
+}
+
+/// The key and the extra flags of the chunks and parents of a multi-chunk hash, which use the
+/// key of `constant` in the keyed hash mode if it has one.
+fn tree_key_and_flags(constant: &Blake3ConstantVar) -> (&Blake3HashVar, u32) {
+    match constant.key.as_ref() {
+        Some(key) => (key, KEYED_HASH),
+        None => (&constant.iv, 0),
+    }
+}
+
+/// Return the hash of `v` if `cond` is 1 and `fallback` if `cond` is 0, where any other value
+/// of `cond` fails the script. The hash is computed in both cases.
+pub fn maybe_hash<T: ToU4LimbVar>(
+    constant: &Blake3ConstantVar,
+    cond: &I32Var,
+    v: T,
+    fallback: &Blake3HashVar,
+) -> Blake3HashVar {
+    let hashed = hash(constant, v);
+
+    // `U32Var::select` checks in script that the condition is 0 or 1, so it can be read as a
+    // U4Var
+    let cond = U4Var {
+        variable: cond.variables()[0],
+        value: cond.value().unwrap() as u32,
+        mode: AllocationMode::FunctionOutput,
+        cs: cond.cs(),
+    };
+
+    let mut words = vec![];
+    for (a, b) in hashed.hash.iter().zip(fallback.hash.iter()) {
+        words.push(U32Var::select(&cond, a, b));
+    }
+    Blake3HashVar {
+        hash: words.try_into().unwrap(),
+    }
+}
+
+/// Verify that the two hashes differ in at least one word, which fails the script if they are
+/// equal. This is the dual of `equalverify`.
+pub fn prove_not_equal(a: &Blake3HashVar, b: &Blake3HashVar) -> Result<()> {
+    let mut flags = vec![];
+    for (l, r) in a.hash.iter().zip(b.hash.iter()) {
+        flags.push(l.is_equal(r));
+    }
+
+    let cs = flags
+        .iter()
+        .skip(1)
+        .fold(flags[0].cs(), |cs, flag| cs.and(&flag.cs()));
+    cs.insert_script(
+        blake3_hash_not_equal,
+        flags.iter().map(|flag| flag.variable),
+    )
+}
+
+fn blake3_hash_not_equal() -> Script {
+    script! {
+        OP_NOT
+        for _ in 1..8 {
+            OP_SWAP OP_NOT OP_BOOLOR
+        }
+        OP_VERIFY
+    }
+}
+
+/// Compute the keyed hash with the key in `constant`, which must be created by
+/// `Blake3ConstantVar::new_keyed`.
+pub fn hash_keyed<T: ToU4LimbVar>(constant: &Blake3ConstantVar, v: T) -> Blake3HashVar {
+    let key = constant
+        .key
+        .as_ref()
+        .expect("The keyed hash requires a Blake3ConstantVar with a key.");
+    hash_with_key_and_flags(constant, v, key, KEYED_HASH).unwrap()
+}
+
+fn hash_with_key_and_flags<T: ToU4LimbVar>(
+    constant: &Blake3ConstantVar,
+    v: T,
+    key: &Blake3HashVar,
+    flags: u32,
+) -> Result<Blake3HashVar> {
+    hash_iter_with_key_and_flags(constant, v.to_u4_limbs().into_iter(), key, flags, 0, true)
+}
 
-    let mut u4_limbs = v.to_u4_limbs();
-    assert_eq!(
-        u4_limbs.len() % 2,
-        0,
-        "The number of u4 limbs should be even (byte aligned)"
-    );
+fn hash_iter_with_key_and_flags(
+    constant: &Blake3ConstantVar,
+    iter: impl Iterator<Item = U4Var>,
+    key: &Blake3HashVar,
+    flags: u32,
+    counter: u64,
+    root: bool,
+) -> Result<Blake3HashVar> {
+    let mut u4_limbs = iter.peekable();
 
     let mut num_block = 0;
-    let mut chaining_values = constant.iv.clone();
+    let mut chaining_values = key.clone();
 
-    while u4_limbs.len() > 0 {
-        if num_block > 16 {
-            panic!("Too many blocks passed to this Blake3 implementation.");
+    while u4_limbs.peek().is_some() {
+        if num_block >= MAX_BLOCKS {
+            return Err(Error::msg(
+                "Too many blocks passed to this Blake3 implementation.",
+            ));
         }
 
-        let mut messages_u4 = vec![];
-        let l = min(512 / 4, u4_limbs.len());
-        for _ in 0..l {
-            messages_u4.push(u4_limbs.remove(0));
+        let mut messages_u4 = u4_limbs.by_ref().take(512 / 4).collect::<Vec<_>>();
+        let l = messages_u4.len();
+        if l % 2 != 0 {
+            return Err(Error::msg(
+                "The number of u4 limbs should be even (byte aligned).",
+            ));
         }
         for _ in l..512 / 4 {
-            messages_u4.push(constant.zero_u32.limbs[0].clone());
+            messages_u4.push(constant.zero_u32.limbs()[0].clone());
         }
 
         let mut messages_u32 = vec![];
         for i in 0..16 {
-            messages_u32.push(U32Var {
-                limbs: messages_u4[(i * 8 + 0)..(i * 8 + 8)]
+            messages_u32.push(U32Var::from_limbs(
+                messages_u4[(i * 8)..(i * 8 + 8)]
                     .to_vec()
                     .try_into()
                     .unwrap(),
-            })
+            )?)
         }
-        let mut messages_u32: [U32Var; 16] = messages_u32.try_into().unwrap();
+        let messages_u32: [U32Var; 16] = messages_u32.try_into().unwrap();
 
-        let mut states_u32 = chaining_values.hash.to_vec();
-        states_u32.extend_from_slice(&constant.iv.hash[0..4]);
-        states_u32.push(constant.zero_u32.clone());
-        states_u32.push(constant.zero_u32.clone());
-        states_u32.push(U32Var::new_constant(&cs, (l / 2) as u32).unwrap());
-
-        let mut d = 0;
+        let mut d = flags;
         if num_block == 0 {
-            d ^= 1;
-        }
-        if u4_limbs.is_empty() {
-            d ^= 2;
-            d ^= 8;
-        }
-        states_u32.push(U32Var::new_constant(&cs, d).unwrap());
-
-        let mut states_u32: [U32Var; 16] = states_u32.try_into().unwrap();
-        for _ in 0..7 {
-            round(&constant.table, &mut states_u32, &mut messages_u32);
+            d ^= CHUNK_START;
         }
-
-        let mut new_chaining_values = vec![];
-        for i in 0..8 {
-            new_chaining_values.push(&states_u32[i] ^ (&constant.table, &states_u32[i + 8]));
+        if u4_limbs.peek().is_none() {
+            d ^= CHUNK_END;
+            if root {
+                d ^= ROOT;
+            }
         }
 
         chaining_values = Blake3HashVar {
-            hash: new_chaining_values.try_into().unwrap(),
+            hash: try_compress_block(
+                constant,
+                &chaining_values,
+                &messages_u32,
+                counter,
+                (l / 2) as u32,
+                d,
+            )?,
         };
         num_block += 1;
     }
 
-    chaining_values
+    Ok(chaining_values)
+}
+
+/// Compute the hash with the chaining values before the last block supplied as hints, so that
+/// only the compression of the last block is computed in script.
+///
+/// The script does not check the hinted chaining values against the earlier blocks, which are
+/// not used at all. The result therefore only binds the last block and the last hinted
+/// chaining value, and the caller must trust the prover, or check the earlier blocks by other
+/// means, for the rest of the input. `hash_hints` computes the honest hints.
+pub fn hash_with_hints<T: ToU4LimbVar>(
+    constant: &Blake3ConstantVar,
+    input: T,
+    hinted_chaining_values: &[[u32; 8]],
+) -> Result<Blake3HashVar> {
+    let u4_limbs = input.to_u4_limbs();
+    if u4_limbs.is_empty() || u4_limbs.len() % 2 != 0 {
+        return Err(Error::msg(
+            "The input should be non-empty and byte aligned.",
+        ));
+    }
+
+    let num_blocks = u4_limbs.len().div_ceil(512 / 4);
+    if num_blocks > MAX_BLOCKS {
+        return Err(Error::msg(
+            "Too many blocks passed to this Blake3 implementation.",
+        ));
+    }
+    if hinted_chaining_values.len() != num_blocks - 1 {
+        return Err(Error::msg(format!(
+            "{} blocks require {} hinted chaining values, but {} are given.",
+            num_blocks,
+            num_blocks - 1,
+            hinted_chaining_values.len()
+        )));
+    }
+
+    let cv = match hinted_chaining_values.last() {
+        Some(cv) => {
+            let mut hash = vec![];
+            for &word in cv.iter() {
+                hash.push(U32Var::new_hint(&constant.cs, word)?);
+            }
+            Blake3HashVar {
+                hash: hash.try_into().unwrap(),
+            }
+        }
+        None => constant.iv.clone(),
+    };
+
+    let mut messages_u4 = u4_limbs[(num_blocks - 1) * (512 / 4)..].to_vec();
+    let l = messages_u4.len();
+    messages_u4.resize(512 / 4, constant.zero_u32.limbs()[0].clone());
+
+    let mut messages_u32 = vec![];
+    for i in 0..16 {
+        messages_u32.push(U32Var::from_limbs(
+            messages_u4[(i * 8)..(i * 8 + 8)]
+                .to_vec()
+                .try_into()
+                .unwrap(),
+        )?)
+    }
+
+    let mut d = CHUNK_END ^ ROOT;
+    if num_blocks == 1 {
+        d ^= CHUNK_START;
+    }
+
+    Ok(Blake3HashVar {
+        hash: try_compress_block(
+            constant,
+            &cv,
+            &messages_u32.try_into().unwrap(),
+            0,
+            (l / 2) as u32,
+            d,
+        )?,
+    })
+}
+
+/// Compute the chaining values after each block but the last, as the hints for
+/// `hash_with_hints`.
+pub fn hash_hints(msg: &[u32]) -> Vec<[u32; 8]> {
+    let mut chaining_values = IV;
+    let mut res = vec![];
+
+    let num_blocks = msg.len().div_ceil(16);
+    for (i, chunk) in msg
+        .chunks(16)
+        .take(num_blocks.saturating_sub(1))
+        .enumerate()
+    {
+        let flags = if i == 0 { CHUNK_START } else { 0 };
+        chaining_values =
+            native::compress_reference(&chaining_values, &chunk.try_into().unwrap(), 0, 64, flags);
+        res.push(chaining_values);
+    }
+    res
+}
+
+/// Apply the Blake3 compression function to a block of 16 message words, and return the new
+/// chaining values.
+pub fn compress_block(
+    constant: &Blake3ConstantVar,
+    cv: &Blake3HashVar,
+    words: &[U32Var; 16],
+    counter: u64,
+    len: u32,
+    flags: u32,
+) -> [U32Var; 8] {
+    try_compress_block(constant, cv, words, counter, len, flags).unwrap()
+}
+
+pub fn try_compress_block(
+    constant: &Blake3ConstantVar,
+    cv: &Blake3HashVar,
+    words: &[U32Var; 16],
+    counter: u64,
+    len: u32,
+    flags: u32,
+) -> Result<[U32Var; 8]> {
+    let cs = constant.cs.clone();
+
+    let mut messages_u32 = words.clone();
+
+    let mut states_u32 = cv.hash.to_vec();
+    states_u32.extend_from_slice(&constant.iv.hash[0..4]);
+    states_u32.push(U32Var::new_constant(&cs, counter as u32)?);
+    states_u32.push(U32Var::new_constant(&cs, (counter >> 32) as u32)?);
+    states_u32.push(U32Var::new_constant(&cs, len)?);
+    states_u32.push(U32Var::new_constant(&cs, flags)?);
+
+    let mut states_u32: [U32Var; 16] = states_u32.try_into().unwrap();
+    for _ in 0..constant.rounds {
+        try_round(&constant.table, &mut states_u32, &mut messages_u32)?;
+    }
+
+    let mut new_chaining_values = vec![];
+    for i in 0..8 {
+        new_chaining_values.push(states_u32[i].try_xor(&constant.table, &states_u32[i + 8])?);
+    }
+    Ok(new_chaining_values.try_into().unwrap())
+}
+
+/// Compress two child chaining values into the chaining value of their parent node in the
+/// Blake3 tree, for a parent that is not the root. The parent is keyed if `constant` has a key.
+pub fn blake3_parent(
+    constant: &Blake3ConstantVar,
+    left: &Blake3HashVar,
+    right: &Blake3HashVar,
+) -> Blake3HashVar {
+    parent_with_flags(constant, left, right, PARENT).unwrap()
+}
+
+/// Compress two child chaining values into the root of the Blake3 tree, which is the digest of
+/// the whole input.
+pub fn blake3_parent_root(
+    constant: &Blake3ConstantVar,
+    left: &Blake3HashVar,
+    right: &Blake3HashVar,
+) -> Blake3HashVar {
+    parent_with_flags(constant, left, right, PARENT ^ ROOT).unwrap()
+}
+
+fn parent_with_flags(
+    constant: &Blake3ConstantVar,
+    left: &Blake3HashVar,
+    right: &Blake3HashVar,
+    flags: u32,
+) -> Result<Blake3HashVar> {
+    let mut words = left.hash.to_vec();
+    words.extend_from_slice(&right.hash);
+
+    let (key, key_flags) = tree_key_and_flags(constant);
+    Ok(Blake3HashVar {
+        hash: try_compress_block(
+            constant,
+            key,
+            &words.try_into().unwrap(),
+            0,
+            64,
+            flags ^ key_flags,
+        )?,
+    })
 }
 
+/// Combine two digests as the root parent node over them.
 impl AddAssign<(&Blake3ConstantVar, &Blake3HashVar)> for Blake3HashVar {
     fn add_assign(&mut self, rhs: (&Blake3ConstantVar, &Blake3HashVar)) {
-        let constant = rhs.0;
-        let rhs = rhs.1;
-
-        let mut limbs = self.hash.to_vec();
-        limbs.extend(rhs.hash.to_vec());
-        *self = hash(&constant, limbs.as_slice())
+        *self = blake3_parent_root(rhs.0, self, rhs.1)
     }
 }
 
@@ -146,11 +698,50 @@ impl ToU4LimbVar for U4Var {
 }
 
 impl ToU4LimbVar for U32Var {
+    fn to_u4_limbs(&self) -> Vec<U4Var> {
+        self.limbs().to_vec()
+    }
+}
+
+impl ToU4LimbVar for U16Var {
     fn to_u4_limbs(&self) -> Vec<U4Var> {
         self.limbs.to_vec()
     }
 }
 
+/// A byte is split into its two nibbles, low nibble first, so that a sequence of bytes is hashed
+/// as the same bytes.
+impl ToU4LimbVar for U8Var {
+    fn to_u4_limbs(&self) -> Vec<U4Var> {
+        let (hi, lo) = self.to_u4_pair().unwrap();
+        vec![lo, hi]
+    }
+}
+
+/// The bytes in order, each as its low nibble and then its high nibble, which is how Blake3
+/// reads a byte string.
+impl ToU4LimbVar for BytesVar {
+    fn to_u4_limbs(&self) -> Vec<U4Var> {
+        self.bytes.as_slice().to_u4_limbs()
+    }
+}
+
+impl ToU4LimbVar for Blake3HashVar {
+    fn to_u4_limbs(&self) -> Vec<U4Var> {
+        self.hash.as_slice().to_u4_limbs()
+    }
+}
+
+/// A single nibble padded with a zero high nibble into a byte, for callers that want to hash an
+/// odd nibble instead of having it rejected.
+pub struct PaddedU4(pub U4Var);
+
+impl ToU4LimbVar for PaddedU4 {
+    fn to_u4_limbs(&self) -> Vec<U4Var> {
+        vec![self.0.clone(), U4Var::new_constant(&self.0.cs, 0).unwrap()]
+    }
+}
+
 impl<T: ToU4LimbVar> ToU4LimbVar for &[T] {
     fn to_u4_limbs(&self) -> Vec<U4Var> {
         let mut result = vec![];
@@ -166,6 +757,33 @@ pub struct Blake3CompactHashVar {
     pub hash: [U32CompactVar; 8],
 }
 
+impl Blake3CompactHashVar {
+    pub fn equalverify(&self, other: &Blake3CompactHashVar) -> Result<()> {
+        for (a, b) in self.hash.iter().zip(other.hash.iter()) {
+            a.equalverify(b)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_program_output(&self) -> Result<()> {
+        let cs = self.hash[0].cs();
+        for word in self.hash.iter() {
+            cs.set_program_output(word)?;
+        }
+        Ok(())
+    }
+
+    /// Decompose the hash into digits of `w` bits for a Winternitz signature, in the byte
+    /// order of the digest and the lowest digit of each byte first.
+    pub fn to_digits(&self, w: usize) -> Result<Vec<U8Var>> {
+        let mut digits = vec![];
+        for word in self.hash.iter() {
+            digits.extend(word.to_digits(w)?);
+        }
+        Ok(digits)
+    }
+}
+
 impl From<&Blake3HashVar> for Blake3CompactHashVar {
     fn from(value: &Blake3HashVar) -> Self {
         Self {
@@ -202,15 +820,37 @@ impl From<&Blake3CompactHashVar> for Blake3HashVar {
 
 #[cfg(test)]
 mod test {
-    use crate::compression::blake3::reference::blake3_reference;
-    use crate::compression::blake3::{hash, Blake3ConstantVar};
-    use crate::limbs::u32::U32Var;
+    use crate::commitment::merkle::blake3_digest_to_words;
+    use crate::commitment::winternitz::{
+        blake3_words_to_bits, bytes_to_bits, Winternitz, WinternitzSignatureVar,
+    };
+    use crate::compression::blake3::lookup_table::LookupTableVar;
+    use crate::compression::blake3::native::compress_reference;
+    use crate::compression::blake3::reference::{
+        blake3_reference, blake3_reference_keyed, chunk_reference,
+    };
+    use crate::compression::blake3::{
+        blake3_parent, blake3_parent_root, compress_block, hash, hash_bytes, hash_checked,
+        hash_chunk, hash_hints, hash_iter, hash_keyed, hash_with_hints, maybe_hash,
+        prove_not_equal, Blake3CompactHashVar, Blake3ConstantVar, Blake3HashVar,
+        Blake3TruncatedHashVar, PaddedU4, ToU4LimbVar, CHUNK_START, IV, MAX_BLOCKS, ROUNDS,
+    };
+    use crate::cost::Cost;
+    use crate::limbs::bytes::BytesVar;
+    use crate::limbs::u16::U16Var;
+    use crate::limbs::u32::{U32CompactVar, U32Var};
+    use crate::limbs::u4::U4Var;
+    use crate::test_utils::{expect_program_failure_without_opcat, u32s_to_nibbles};
     use bitcoin_circle_stark::treepp::*;
-    use bitcoin_script_dsl::bvar::{AllocVar, BVar};
-    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
-    use bitcoin_script_dsl::test_program_without_opcat;
+    use bitcoin_script_dsl::builtins::i32::I32Var;
+    use bitcoin_script_dsl::builtins::u8::U8Var;
+    use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
+    use bitcoin_script_dsl::compiler::Compiler;
+    use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+    use bitcoin_script_dsl::{test_program, test_program_without_opcat};
     use rand::{Rng, SeedableRng};
     use rand_chacha::ChaCha20Rng;
+    use std::rc::Rc;
 
     #[test]
     fn test_blake3() {
@@ -233,20 +873,13 @@ mod test {
         let mut messages = messages.clone();
         let expected = blake3_reference(&mut messages);
 
-        for i in 0..8 {
-            let var = U32Var::new_constant(&cs, expected[i]).unwrap();
-            computed_hash.hash[i].equalverify(&var).unwrap();
-            cs.set_program_output(&computed_hash.hash[i]).unwrap();
-        }
+        let expected_var = Blake3HashVar {
+            hash: expected.map(|v| U32Var::new_constant(&cs, v).unwrap()),
+        };
+        computed_hash.equalverify(&expected_var).unwrap();
+        computed_hash.set_program_output().unwrap();
 
-        let mut values = vec![];
-        for i in 0..8 {
-            let mut v = expected[i];
-            for _ in 0..8 {
-                values.push(v & 15);
-                v >>= 4;
-            }
-        }
+        let values = u32s_to_nibbles(&expected);
 
         test_program_without_opcat(
             cs,
@@ -256,4 +889,826 @@ mod test {
         )
         .unwrap();
     }
+
+    #[test]
+    fn test_blake3_hash_iter() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut messages = Vec::<u32>::with_capacity(40);
+        for _ in 0..40 {
+            messages.push(prng.gen());
+        }
+
+        let cs = ConstraintSystem::new_ref();
+
+        let mut messages_u32 = vec![];
+        for &v in messages.iter() {
+            messages_u32.push(U32Var::new_program_input(&cs, v).unwrap());
+        }
+
+        let constant = Blake3ConstantVar::new(&cs);
+        let expected_hash = hash(&constant, messages_u32.as_slice());
+        let computed_hash = hash_iter(
+            &constant,
+            messages_u32.iter().flat_map(|v| v.limbs().iter().cloned()),
+        );
+
+        for (a, b) in computed_hash.hash.iter().zip(expected_hash.hash.iter()) {
+            assert_eq!(a.value().unwrap(), b.value().unwrap());
+        }
+        computed_hash.equalverify(&expected_hash).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_compress_block() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let cv: [u32; 8] = prng.gen();
+        let block: [u32; 16] = prng.gen();
+        let counter = (1u64 << 32) + 5;
+        let len = 64;
+        let flags = CHUNK_START;
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let cv_var = Blake3HashVar {
+            hash: cv.map(|v| U32Var::new_program_input(&cs, v).unwrap()),
+        };
+        let block_var = block.map(|v| U32Var::new_program_input(&cs, v).unwrap());
+
+        let computed = compress_block(&constant, &cv_var, &block_var, counter, len, flags);
+
+        let expected = compress_reference(&cv, &block, counter, len, flags);
+        let expected_var = Blake3HashVar {
+            hash: expected.map(|v| U32Var::new_constant(&cs, v).unwrap()),
+        };
+        Blake3HashVar { hash: computed }
+            .equalverify(&expected_var)
+            .unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_maybe_hash() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let messages: [u32; 8] = prng.gen();
+        let fallback: [u32; 8] = prng.gen();
+
+        for cond in [0, 1] {
+            let cs = ConstraintSystem::new_ref();
+            let constant = Blake3ConstantVar::new(&cs);
+
+            let cond_var = I32Var::new_program_input(&cs, cond).unwrap();
+            let messages_var = messages.map(|v| U32Var::new_program_input(&cs, v).unwrap());
+            let fallback_var = Blake3HashVar {
+                hash: fallback.map(|v| U32Var::new_program_input(&cs, v).unwrap()),
+            };
+
+            let res = maybe_hash(&constant, &cond_var, messages_var.as_slice(), &fallback_var);
+
+            let expected = if cond == 1 {
+                blake3_reference(&messages)
+            } else {
+                fallback
+            };
+            let expected_var = Blake3HashVar {
+                hash: expected.map(|v| U32Var::new_constant(&cs, v).unwrap()),
+            };
+            res.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    fn prove_not_equal_test_case(diff: Option<(usize, u32)>) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let a: [u32; 8] = prng.gen();
+        let mut b = a;
+        if let Some((i, mask)) = diff {
+            b[i] ^= mask;
+        }
+
+        let cs = ConstraintSystem::new_ref();
+        let a_var = Blake3HashVar {
+            hash: a.map(|v| U32Var::new_program_input(&cs, v).unwrap()),
+        };
+        let b_var = Blake3HashVar {
+            hash: b.map(|v| U32Var::new_program_input(&cs, v).unwrap()),
+        };
+        prove_not_equal(&a_var, &b_var).unwrap();
+
+        cs
+    }
+
+    #[test]
+    fn test_blake3_prove_not_equal() {
+        for (i, mask) in [(0, 1), (3, 0x80000000), (7, 0x10), (5, 0xffffffff)] {
+            test_program_without_opcat(prove_not_equal_test_case(Some((i, mask))), script! {})
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_blake3_prove_not_equal_equal_hashes() {
+        expect_program_failure_without_opcat(prove_not_equal_test_case(None), script! {}).unwrap();
+    }
+
+    fn hash_with_hints_test_case(corrupt: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut messages = Vec::<u32>::with_capacity(40);
+        for _ in 0..40 {
+            messages.push(prng.gen());
+        }
+
+        let mut hints = hash_hints(&messages);
+        assert_eq!(hints.len(), 2);
+        if corrupt {
+            hints[1][3] ^= 1;
+        }
+
+        let cs = ConstraintSystem::new_ref();
+
+        let mut messages_u32 = vec![];
+        for &v in messages.iter() {
+            messages_u32.push(U32Var::new_program_input(&cs, v).unwrap());
+        }
+
+        let constant = Blake3ConstantVar::new(&cs);
+        let computed_hash = hash_with_hints(&constant, messages_u32.as_slice(), &hints).unwrap();
+
+        let expected = blake3_reference(&messages);
+        let expected_var = Blake3HashVar {
+            hash: expected.map(|v| U32Var::new_constant(&cs, v).unwrap()),
+        };
+        computed_hash.equalverify(&expected_var).unwrap();
+
+        cs
+    }
+
+    #[test]
+    fn test_blake3_hash_with_hints() {
+        test_program_without_opcat(hash_with_hints_test_case(false), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_hash_with_hints_wrong_hint() {
+        expect_program_failure_without_opcat(hash_with_hints_test_case(true), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_hash_wrong_digest() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut message = vec![0u8; 100];
+        prng.fill(message.as_mut_slice());
+        let mut digest = *::blake3::hash(&message).as_bytes();
+        digest[17] ^= 0x40;
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let mut message_words = vec![];
+        for chunk in message.chunks_exact(4) {
+            let word = u32::from_le_bytes(chunk.try_into().unwrap());
+            message_words.push(U32Var::new_program_input(&cs, word).unwrap());
+        }
+        let computed = hash(&constant, message_words.as_slice());
+
+        let claimed_var = Blake3HashVar::new_program_input(&cs, digest).unwrap();
+        computed.equalverify(&claimed_var).unwrap();
+
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_hash_var_from_digest() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut message = vec![0u8; 100];
+        prng.fill(message.as_mut_slice());
+        let digest = *::blake3::hash(&message).as_bytes();
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let mut message_words = vec![];
+        for chunk in message.chunks_exact(4) {
+            let word = u32::from_le_bytes(chunk.try_into().unwrap());
+            message_words.push(U32Var::new_program_input(&cs, word).unwrap());
+        }
+        let computed = hash(&constant, message_words.as_slice());
+        assert_eq!(computed.value().unwrap(), digest);
+
+        let expected_var = Blake3HashVar::new_constant(&cs, digest).unwrap();
+        assert_eq!(expected_var.value().unwrap(), digest);
+        computed.equalverify(&expected_var).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_constant_get_or_create() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let a: [u32; 8] = prng.gen();
+        let b: [u32; 8] = prng.gen();
+
+        let build = |shared: bool| {
+            let cs = ConstraintSystem::new_ref();
+            for message in [a, b] {
+                let constant = if shared {
+                    Blake3ConstantVar::get_or_create(&cs)
+                } else {
+                    Rc::new(Blake3ConstantVar::new(&cs))
+                };
+                let message_var = message.map(|v| U32Var::new_program_input(&cs, v).unwrap());
+                let hash_var = hash(&constant, message_var.as_slice());
+                let expected_var = Blake3HashVar {
+                    hash: blake3_reference(&message).map(|v| U32Var::new_constant(&cs, v).unwrap()),
+                };
+                hash_var.equalverify(&expected_var).unwrap();
+            }
+            cs
+        };
+
+        test_program_without_opcat(build(true), script! {}).unwrap();
+
+        // the second hash reuses the table, so the constants are not allocated twice, although
+        // the shared table also has the shift tables
+        let table_len = Blake3ConstantVar::new(&ConstraintSystem::new_ref())
+            .table
+            .variables()
+            .len();
+        let shared_table_len = LookupTableVar::length();
+        let shared_len = Compiler::compile(build(true)).unwrap().script.len();
+        let separate_len = Compiler::compile(build(false)).unwrap().script.len();
+        assert!(shared_len + 2 * table_len <= separate_len + shared_table_len);
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::get_or_create(&cs);
+        assert!(Rc::ptr_eq(
+            &constant,
+            &Blake3ConstantVar::get_or_create(&cs)
+        ));
+        assert_eq!(
+            constant.table.variables(),
+            LookupTableVar::get_or_create(&cs).variables()
+        );
+
+        let other_cs = ConstraintSystem::new_ref();
+        let other_constant = Blake3ConstantVar::get_or_create(&other_cs);
+        assert!(!Rc::ptr_eq(&constant, &other_constant));
+    }
+
+    #[test]
+    fn test_blake3_cost_of_hash() {
+        let compression = Blake3ConstantVar::cost_of_compression().unwrap();
+        assert!(compression.script_len > 0);
+        assert_eq!(Blake3ConstantVar::cost_of_hash(0).unwrap(), compression);
+        assert_eq!(Blake3ConstantVar::cost_of_hash(64).unwrap(), compression);
+        assert_eq!(
+            Blake3ConstantVar::cost_of_hash(65).unwrap(),
+            compression * 2
+        );
+        assert!(Blake3ConstantVar::cost_of_hash(64 * MAX_BLOCKS + 1).is_err());
+
+        // the estimate is close to the measured cost of hashing three full blocks
+        let num_words = 48;
+        let measured = Cost::measure_op(
+            |cs| {
+                let constant = Blake3ConstantVar::new(cs);
+                let mut words = vec![];
+                for i in 0..num_words {
+                    words.push(U32Var::new_program_input(cs, i)?);
+                }
+                Ok((constant, words))
+            },
+            |(constant, words)| {
+                let _ = hash(constant, words.as_slice());
+                Ok(())
+            },
+        )
+        .unwrap();
+        let estimated = Blake3ConstantVar::cost_of_hash(num_words as usize * 4).unwrap();
+        assert!(measured.script_len.abs_diff(estimated.script_len) * 10 < estimated.script_len);
+    }
+
+    #[test]
+    fn test_blake3_default_rounds() {
+        let cs = ConstraintSystem::new_ref();
+        assert_eq!(Blake3ConstantVar::new(&cs).rounds, ROUNDS);
+        assert_eq!(Blake3ConstantVar::new_keyed(&cs, IV).rounds, ROUNDS);
+
+        let message = [0x01234567u32; 20];
+        let message_bytes = message
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blake3_digest_to_words(::blake3::hash(&message_bytes).as_bytes()),
+            blake3_reference(&message)
+        );
+    }
+
+    #[cfg(feature = "insecure-reduced-rounds")]
+    #[test]
+    fn test_blake3_reduced_rounds() {
+        use crate::compression::blake3::reference::blake3_reference_with_rounds;
+
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut messages = Vec::<u32>::with_capacity(20);
+        for _ in 0..20 {
+            messages.push(prng.gen());
+        }
+
+        for rounds in [1, 2] {
+            let expected = blake3_reference_with_rounds(&messages, rounds);
+            assert_ne!(expected, blake3_reference(&messages));
+
+            let cs = ConstraintSystem::new_ref();
+            let constant = Blake3ConstantVar::with_rounds(&cs, rounds);
+            let messages_var = messages
+                .iter()
+                .map(|&v| U32Var::new_program_input(&cs, v).unwrap())
+                .collect::<Vec<_>>();
+            let hash_var = hash(&constant, messages_var.as_slice());
+
+            let expected_var = Blake3HashVar {
+                hash: expected.map(|v| U32Var::new_constant(&cs, v).unwrap()),
+            };
+            hash_var.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_blake3_hash_bytes() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut one_block = vec![0u8; 64];
+        prng.fill(one_block.as_mut_slice());
+
+        for message in [b"hello world".to_vec(), one_block] {
+            let digest = *::blake3::hash(&message).as_bytes();
+
+            let cs = ConstraintSystem::new_ref();
+            let constant = Blake3ConstantVar::new(&cs);
+
+            let bytes_var = BytesVar::new_program_input(&cs, message.clone()).unwrap();
+            assert_eq!(bytes_var.len(), message.len());
+            assert_eq!(bytes_var.value().unwrap(), message);
+
+            let computed = hash_bytes(&constant, &bytes_var).unwrap();
+            assert_eq!(computed.value().unwrap(), digest);
+
+            let expected_var = Blake3HashVar::new_constant(&cs, digest).unwrap();
+            computed.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_blake3_hash_then_sign() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut messages = Vec::<u32>::with_capacity(20);
+        for _ in 0..20 {
+            messages.push(prng.gen());
+        }
+        let words = blake3_reference(&messages);
+
+        let message_bytes = messages
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blake3_words_to_bits(&words),
+            bytes_to_bits(::blake3::hash(&message_bytes).as_bytes())
+        );
+
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("digest", 8, 32);
+        let public_key = secret_key.to_public_key();
+        let signature = secret_key.sign(&blake3_words_to_bits(&words));
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let message_vars = messages
+            .iter()
+            .map(|&word| U32Var::new_program_input(&cs, word).unwrap())
+            .collect::<Vec<_>>();
+        let hash_var = hash(&constant, message_vars.as_slice());
+
+        let bytes = hash_var.to_u8_vars().unwrap();
+        assert_eq!(bytes.len(), 32);
+
+        let signature_var =
+            WinternitzSignatureVar::from_signature(&cs, &signature, AllocationMode::ProgramInput)
+                .unwrap();
+        signature_var.verify(&bytes, &public_key).unwrap();
+
+        test_program(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_bytes_and_u16() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut message = vec![0u8; 64];
+        prng.fill(message.as_mut_slice());
+        let digest = *::blake3::hash(&message).as_bytes();
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let bytes = message
+            .iter()
+            .map(|&b| U8Var::new_program_input(&cs, b).unwrap())
+            .collect::<Vec<_>>();
+        let halves = message
+            .chunks_exact(2)
+            .map(|chunk| {
+                U16Var::new_program_input(&cs, u16::from_le_bytes(chunk.try_into().unwrap()))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let expected_var = Blake3HashVar::new_constant(&cs, digest).unwrap();
+        hash(&constant, bytes.as_slice())
+            .equalverify(&expected_var)
+            .unwrap();
+        hash(&constant, halves.as_slice())
+            .equalverify(&expected_var)
+            .unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_hash_checked() {
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let nibble = U4Var::new_program_input(&cs, 9).unwrap();
+        let err = hash_checked(&constant, nibble.clone()).unwrap_err();
+        assert!(err.to_string().contains("byte aligned"));
+
+        let too_long = (0..MAX_BLOCKS * 16 + 1)
+            .map(|_| U32Var::new_program_input(&cs, 0).unwrap())
+            .collect::<Vec<_>>();
+        let err = hash_checked(&constant, too_long.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("blocks"));
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+        let nibble = U4Var::new_program_input(&cs, 9).unwrap();
+        let computed = hash_checked(&constant, PaddedU4(nibble)).unwrap();
+        let expected_var =
+            Blake3HashVar::new_constant(&cs, *::blake3::hash(&[9u8]).as_bytes()).unwrap();
+        computed.equalverify(&expected_var).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_keyed() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for num_words in [8, 16, 20] {
+            let mut messages = Vec::<u32>::with_capacity(num_words);
+            for _ in 0..num_words {
+                messages.push(prng.gen());
+            }
+            let mut key = [0u32; 8];
+            for v in key.iter_mut() {
+                *v = prng.gen();
+            }
+
+            let expected = blake3_reference_keyed(&messages, &key);
+
+            let mut key_bytes = [0u8; 32];
+            for (chunk, v) in key_bytes.chunks_exact_mut(4).zip(key.iter()) {
+                chunk.copy_from_slice(&v.to_le_bytes());
+            }
+            let mut message_bytes = vec![];
+            for v in messages.iter() {
+                message_bytes.extend_from_slice(&v.to_le_bytes());
+            }
+            let official = ::blake3::keyed_hash(&key_bytes, &message_bytes);
+            for (chunk, v) in official.as_bytes().chunks_exact(4).zip(expected.iter()) {
+                assert_eq!(u32::from_le_bytes(chunk.try_into().unwrap()), *v);
+            }
+
+            let cs = ConstraintSystem::new_ref();
+
+            let mut messages_u32 = vec![];
+            for &v in messages.iter() {
+                messages_u32.push(U32Var::new_program_input(&cs, v).unwrap());
+            }
+
+            let constant = Blake3ConstantVar::new_keyed(&cs, key);
+            let computed_hash = hash_keyed(&constant, messages_u32.as_slice());
+
+            let expected_var = Blake3HashVar {
+                hash: expected.map(|v| U32Var::new_constant(&cs, v).unwrap()),
+            };
+            computed_hash.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_blake3_full_blocks() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for num_words in [16, 32, 48] {
+            let mut messages = Vec::<u32>::with_capacity(num_words);
+            for _ in 0..num_words {
+                messages.push(prng.gen());
+            }
+            let expected = blake3_reference(&messages);
+
+            let cs = ConstraintSystem::new_ref();
+
+            let mut messages_u32 = vec![];
+            for &v in messages.iter() {
+                messages_u32.push(U32Var::new_program_input(&cs, v).unwrap());
+            }
+
+            let constant = Blake3ConstantVar::new(&cs);
+            let computed_hash = hash(&constant, messages_u32.as_slice());
+
+            let expected_var = Blake3HashVar {
+                hash: expected.map(|v| U32Var::new_constant(&cs, v).unwrap()),
+            };
+            computed_hash.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_blake3_hash_of_hash() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut messages = Vec::<u32>::with_capacity(16);
+        for _ in 0..16 {
+            messages.push(prng.gen());
+        }
+        let expected = blake3_reference(&blake3_reference(&messages));
+
+        let cs = ConstraintSystem::new_ref();
+
+        let mut messages_u32 = vec![];
+        for &v in messages.iter() {
+            messages_u32.push(U32Var::new_program_input(&cs, v).unwrap());
+        }
+
+        let constant = Blake3ConstantVar::new(&cs);
+        let first_hash = hash(&constant, messages_u32.as_slice());
+        assert_eq!(first_hash.to_u4_limbs().len(), 64);
+        let second_hash = hash(&constant, first_hash);
+
+        let expected_var = Blake3HashVar {
+            hash: expected.map(|v| U32Var::new_constant(&cs, v).unwrap()),
+        };
+        second_hash.equalverify(&expected_var).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_truncate() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut messages = Vec::<u32>::with_capacity(16);
+        for _ in 0..16 {
+            messages.push(prng.gen());
+        }
+        let expected = blake3_reference(&messages);
+
+        let cs = ConstraintSystem::new_ref();
+
+        let mut messages_u32 = vec![];
+        for &v in messages.iter() {
+            messages_u32.push(U32Var::new_program_input(&cs, v).unwrap());
+        }
+
+        let constant = Blake3ConstantVar::new(&cs);
+        let computed_hash = hash(&constant, messages_u32.as_slice());
+
+        let truncated = Blake3TruncatedHashVar::new(&computed_hash, 5);
+        assert_eq!(truncated.hash.len(), 5);
+        assert_eq!(truncated.to_u4_limbs().len(), 40);
+
+        let expected_var = Blake3TruncatedHashVar {
+            hash: expected[0..5]
+                .iter()
+                .map(|&v| U32Var::new_constant(&cs, v).unwrap())
+                .collect(),
+        };
+        truncated.equalverify(&expected_var).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_compact_equalverify() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let cs = ConstraintSystem::new_ref();
+
+        let mut words = vec![];
+        for _ in 0..8 {
+            words.push(U32Var::new_program_input(&cs, prng.gen()).unwrap());
+        }
+        let hash_var = Blake3HashVar {
+            hash: words.try_into().unwrap(),
+        };
+
+        let compact_var = Blake3CompactHashVar::from(&hash_var);
+        let recovered_var = Blake3HashVar::from(&compact_var);
+        let compact_recovered_var = Blake3CompactHashVar::from(&recovered_var);
+
+        hash_var.equalverify(&recovered_var).unwrap();
+        compact_var.equalverify(&compact_recovered_var).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_compact_to_digits() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let winternitz = Winternitz::keygen(&mut prng);
+
+        for w in [4, 8] {
+            let mut digest: [u8; 32] = prng.gen();
+            // the negative zero in the compact representation
+            digest[4..8].copy_from_slice(&0x80000000u32.to_le_bytes());
+
+            let secret_key = winternitz.get_secret_key("digest", w, 256 / w);
+            let public_key = secret_key.to_public_key();
+            let signature = secret_key.sign(&bytes_to_bits(&digest));
+
+            let cs = ConstraintSystem::new_ref();
+            let compact_var = Blake3CompactHashVar {
+                hash: blake3_digest_to_words(&digest)
+                    .map(|word| U32CompactVar::new_program_input(&cs, word).unwrap()),
+            };
+            let digits = compact_var.to_digits(w).unwrap();
+            assert_eq!(digits.len(), 256 / w);
+
+            let signature_var = WinternitzSignatureVar::from_signature(
+                &cs,
+                &signature,
+                AllocationMode::ProgramInput,
+            )
+            .unwrap();
+            signature_var.verify(&digits, &public_key).unwrap();
+
+            test_program(cs, script! {}).unwrap();
+        }
+    }
+
+    fn bytes_to_words(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_blake3_parent_merkle_root() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        // four chunks, so that the tree has two parents below the root
+        let mut input = vec![0u8; 4096];
+        prng.fill(input.as_mut_slice());
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let chunk_cvs = input
+            .chunks_exact(1024)
+            .enumerate()
+            .map(|(i, chunk)| Blake3HashVar {
+                hash: chunk_reference(&bytes_to_words(chunk), &IV, i as u64, 0, false)
+                    .map(|v| U32Var::new_program_input(&cs, v).unwrap()),
+            })
+            .collect::<Vec<_>>();
+
+        let left = blake3_parent(&constant, &chunk_cvs[0], &chunk_cvs[1]);
+        let right = blake3_parent(&constant, &chunk_cvs[2], &chunk_cvs[3]);
+        let root = blake3_parent_root(&constant, &left, &right);
+
+        let expected = *blake3::hash(&input).as_bytes();
+        assert_eq!(root.value().unwrap(), expected);
+
+        let mut combined = left.clone();
+        combined += (&constant, &right);
+        assert_eq!(combined.value().unwrap(), expected);
+
+        root.equalverify(&Blake3HashVar::new_constant(&cs, expected).unwrap())
+            .unwrap();
+        combined
+            .equalverify(&Blake3HashVar::new_constant(&cs, expected).unwrap())
+            .unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_keyed_multi_chunk() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        // three chunks, so that the tree has a parent below the root
+        let mut input = vec![0u8; 2048 + 300];
+        prng.fill(input.as_mut_slice());
+        let words = bytes_to_words(&input);
+
+        let key: [u32; 8] = prng.gen();
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new_keyed(&cs, key);
+
+        let mut chunk_cvs = vec![];
+        for (i, chunk) in words.chunks(256).enumerate() {
+            let chunk_var = chunk
+                .iter()
+                .map(|&v| U32Var::new_program_input(&cs, v).unwrap())
+                .collect::<Vec<_>>();
+            chunk_cvs.push(hash_chunk(&constant, chunk_var.as_slice(), i as u64));
+        }
+        let left = blake3_parent(&constant, &chunk_cvs[0], &chunk_cvs[1]);
+        let root = blake3_parent_root(&constant, &left, &chunk_cvs[2]);
+
+        let expected = blake3_reference_keyed(&words, &key);
+        let mut key_bytes = [0u8; 32];
+        for (chunk, word) in key_bytes.chunks_exact_mut(4).zip(key.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        assert_eq!(
+            expected,
+            blake3_digest_to_words(blake3::keyed_hash(&key_bytes, &input).as_bytes())
+        );
+
+        let mut expected_bytes = [0u8; 32];
+        for (chunk, word) in expected_bytes.chunks_exact_mut(4).zip(expected.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        assert_eq!(root.value().unwrap(), expected_bytes);
+        root.equalverify(&Blake3HashVar::new_constant(&cs, expected_bytes).unwrap())
+            .unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_blake3_reference_multi_chunk() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for num_bytes in [1024, 1028, 2048, 3000, 4096, 5120, 9216] {
+            let mut input = vec![0u8; num_bytes];
+            prng.fill(input.as_mut_slice());
+
+            let expected = blake3_digest_to_words(blake3::hash(&input).as_bytes());
+            assert_eq!(blake3_reference(&bytes_to_words(&input)), expected);
+        }
+    }
+
+    #[test]
+    fn test_blake3_hash_chunk_streaming() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        // a full chunk followed by a partial one
+        let mut input = vec![0u8; 1024 + 200];
+        prng.fill(input.as_mut_slice());
+        let words = bytes_to_words(&input);
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let mut chunk_cvs = vec![];
+        for (i, chunk) in words.chunks(256).enumerate() {
+            let chunk_var = chunk
+                .iter()
+                .map(|&v| U32Var::new_program_input(&cs, v).unwrap())
+                .collect::<Vec<_>>();
+            chunk_cvs.push(hash_chunk(&constant, chunk_var.as_slice(), i as u64));
+        }
+        let root = blake3_parent_root(&constant, &chunk_cvs[0], &chunk_cvs[1]);
+
+        let expected = *blake3::hash(&input).as_bytes();
+        assert_eq!(root.value().unwrap(), expected);
+        root.equalverify(&Blake3HashVar::new_constant(&cs, expected).unwrap())
+            .unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
 }
@@ -0,0 +1,114 @@
+//! The Blake3 compression computed on plain words, for the hints and the nodes that are
+//! computed outside of the script.
+
+use crate::compression::blake3::{IV, PARENT, ROUNDS};
+use std::ops::BitXor;
+
+pub(crate) fn g_reference(
+    a_ref: &mut u32,
+    b_ref: &mut u32,
+    c_ref: &mut u32,
+    d_ref: &mut u32,
+    m_0: u32,
+    m_1: u32,
+) {
+    let mut a = a_ref.clone();
+    let mut b = b_ref.clone();
+    let mut c = c_ref.clone();
+    let mut d = d_ref.clone();
+
+    a = a.wrapping_add(b).wrapping_add(m_0);
+    d = d.bitxor(&a).rotate_right(16);
+    c = c.wrapping_add(d);
+    b = b.bitxor(&c).rotate_right(12);
+    a = a.wrapping_add(b).wrapping_add(m_1);
+    d = d.bitxor(&a).rotate_right(8);
+    c = c.wrapping_add(d);
+    b = b.bitxor(&c).rotate_right(7);
+
+    *a_ref = a;
+    *b_ref = b;
+    *c_ref = c;
+    *d_ref = d;
+}
+
+pub(crate) fn round_reference(state_ref: &mut [u32; 16], msg: &mut [u32; 16]) {
+    let [ref mut s0, ref mut s1, ref mut s2, ref mut s3, ref mut s4, ref mut s5, ref mut s6, ref mut s7, ref mut s8, ref mut s9, ref mut s10, ref mut s11, ref mut s12, ref mut s13, ref mut s14, ref mut s15] =
+        *state_ref;
+
+    g_reference(s0, s4, s8, s12, msg[0], msg[1]);
+    g_reference(s1, s5, s9, s13, msg[2], msg[3]);
+    g_reference(s2, s6, s10, s14, msg[4], msg[5]);
+    g_reference(s3, s7, s11, s15, msg[6], msg[7]);
+
+    g_reference(s0, s5, s10, s15, msg[8], msg[9]);
+    g_reference(s1, s6, s11, s12, msg[10], msg[11]);
+    g_reference(s2, s7, s8, s13, msg[12], msg[13]);
+    g_reference(s3, s4, s9, s14, msg[14], msg[15]);
+
+    *msg = [
+        msg[2], msg[6], msg[3], msg[10], msg[7], msg[0], msg[4], msg[13], msg[1], msg[11], msg[12],
+        msg[5], msg[9], msg[14], msg[15], msg[8],
+    ];
+}
+
+/// Compute the chaining value of the parent node over two children.
+pub(crate) fn parent_reference(
+    left: &[u32; 8],
+    right: &[u32; 8],
+    key: &[u32; 8],
+    flags: u32,
+) -> [u32; 8] {
+    parent_reference_with_rounds(left, right, key, flags, ROUNDS)
+}
+
+pub(crate) fn parent_reference_with_rounds(
+    left: &[u32; 8],
+    right: &[u32; 8],
+    key: &[u32; 8],
+    flags: u32,
+    rounds: usize,
+) -> [u32; 8] {
+    let mut block = [0u32; 16];
+    block[..8].copy_from_slice(left);
+    block[8..].copy_from_slice(right);
+    compress_reference_with_rounds(key, &block, 0, 64, flags ^ PARENT, rounds)
+}
+
+pub(crate) fn compress_reference(
+    cv: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    len: u32,
+    flags: u32,
+) -> [u32; 8] {
+    compress_reference_with_rounds(cv, block, counter, len, flags, ROUNDS)
+}
+
+pub(crate) fn compress_reference_with_rounds(
+    cv: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    len: u32,
+    flags: u32,
+    rounds: usize,
+) -> [u32; 8] {
+    let mut state = [0u32; 16];
+    state[0..8].copy_from_slice(cv);
+    state[8..12].copy_from_slice(&IV[0..4]);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+    state[14] = len;
+    state[15] = flags;
+
+    let mut msg = block.clone();
+    for _ in 0..rounds {
+        round_reference(&mut state, &mut msg);
+    }
+
+    let mut res = [0u32; 8];
+    for i in 0..8 {
+        res[i] = state[i] ^ state[i + 8];
+    }
+    res
+}
@@ -1,85 +1,123 @@
-use crate::compression::blake3::IV;
-use std::ops::BitXor;
+use crate::compression::blake3::native::{
+    compress_reference_with_rounds, parent_reference_with_rounds,
+};
+use crate::compression::blake3::{CHUNK_END, CHUNK_START, IV, KEYED_HASH, ROOT, ROUNDS};
 
-pub(crate) fn g_reference(
-    a_ref: &mut u32,
-    b_ref: &mut u32,
-    c_ref: &mut u32,
-    d_ref: &mut u32,
-    m_0: u32,
-    m_1: u32,
-) {
-    let mut a = a_ref.clone();
-    let mut b = b_ref.clone();
-    let mut c = c_ref.clone();
-    let mut d = d_ref.clone();
+pub fn blake3_reference(msg: &[u32]) -> [u32; 8] {
+    blake3_reference_with_key_and_flags(msg, &IV, 0, ROUNDS)
+}
 
-    a = a.wrapping_add(b).wrapping_add(m_0);
-    d = d.bitxor(&a).rotate_right(16);
-    c = c.wrapping_add(d);
-    b = b.bitxor(&c).rotate_right(12);
-    a = a.wrapping_add(b).wrapping_add(m_1);
-    d = d.bitxor(&a).rotate_right(8);
-    c = c.wrapping_add(d);
-    b = b.bitxor(&c).rotate_right(7);
+pub fn blake3_reference_keyed(msg: &[u32], key: &[u32; 8]) -> [u32; 8] {
+    blake3_reference_with_key_and_flags(msg, key, KEYED_HASH, ROUNDS)
+}
 
-    *a_ref = a;
-    *b_ref = b;
-    *c_ref = c;
-    *d_ref = d;
+/// Hash the words with `rounds` rounds in each compression, which matches the hash with the
+/// constants from `Blake3ConstantVar::with_rounds`.
+#[cfg(feature = "insecure-reduced-rounds")]
+pub fn blake3_reference_with_rounds(msg: &[u32], rounds: usize) -> [u32; 8] {
+    blake3_reference_with_key_and_flags(msg, &IV, 0, rounds)
 }
 
-pub fn round_reference(state_ref: &mut [u32; 16], msg: &mut [u32; 16]) {
-    let [ref mut s0, ref mut s1, ref mut s2, ref mut s3, ref mut s4, ref mut s5, ref mut s6, ref mut s7, ref mut s8, ref mut s9, ref mut s10, ref mut s11, ref mut s12, ref mut s13, ref mut s14, ref mut s15] =
-        *state_ref;
+/// The number of words in a chunk of 1024 bytes.
+const CHUNK_WORDS: usize = 256;
+
+/// Hash the words as in the Blake3 spec, where an input of more than one chunk is hashed as a
+/// tree of chunks with the chunk counter and the parent nodes.
+fn blake3_reference_with_key_and_flags(
+    msg: &[u32],
+    key: &[u32; 8],
+    flags: u32,
+    rounds: usize,
+) -> [u32; 8] {
+    if msg.len() <= CHUNK_WORDS {
+        return chunk_reference_with_rounds(msg, key, 0, flags, true, rounds);
+    }
 
-    g_reference(s0, s4, s8, s12, msg[0], msg[1]);
-    g_reference(s1, s5, s9, s13, msg[2], msg[3]);
-    g_reference(s2, s6, s10, s14, msg[4], msg[5]);
-    g_reference(s3, s7, s11, s15, msg[6], msg[7]);
+    let chunks = msg.chunks(CHUNK_WORDS).collect::<Vec<_>>();
+    let (left, right) = split_subtree(&chunks, 0, key, flags, rounds);
+    parent_reference_with_rounds(&left, &right, key, flags ^ ROOT, rounds)
+}
 
-    g_reference(s0, s5, s10, s15, msg[8], msg[9]);
-    g_reference(s1, s6, s11, s12, msg[10], msg[11]);
-    g_reference(s2, s7, s8, s13, msg[12], msg[13]);
-    g_reference(s3, s4, s9, s14, msg[14], msg[15]);
+/// Compute the chaining values of the two children of the subtree over `chunks`, where the left
+/// child covers the largest power-of-two number of chunks that leaves the right child nonempty.
+fn split_subtree(
+    chunks: &[&[u32]],
+    counter: u64,
+    key: &[u32; 8],
+    flags: u32,
+    rounds: usize,
+) -> ([u32; 8], [u32; 8]) {
+    let num_left = 1 << (usize::BITS - 1 - (chunks.len() - 1).leading_zeros());
 
-    *msg = [
-        msg[2], msg[6], msg[3], msg[10], msg[7], msg[0], msg[4], msg[13], msg[1], msg[11], msg[12],
-        msg[5], msg[9], msg[14], msg[15], msg[8],
-    ];
+    let left = subtree_reference(&chunks[..num_left], counter, key, flags, rounds);
+    let right = subtree_reference(
+        &chunks[num_left..],
+        counter + num_left as u64,
+        key,
+        flags,
+        rounds,
+    );
+    (left, right)
 }
 
-pub fn blake3_reference(msg: &[u32]) -> [u32; 8] {
-    let mut chaining_values = IV.clone();
+fn subtree_reference(
+    chunks: &[&[u32]],
+    counter: u64,
+    key: &[u32; 8],
+    flags: u32,
+    rounds: usize,
+) -> [u32; 8] {
+    if chunks.len() == 1 {
+        return chunk_reference_with_rounds(chunks[0], key, counter, flags, false, rounds);
+    }
+    let (left, right) = split_subtree(chunks, counter, key, flags, rounds);
+    parent_reference_with_rounds(&left, &right, key, flags, rounds)
+}
 
-    for (i, chunk) in msg.chunks(16).enumerate() {
-        let mut state = [0u32; 16];
-        state[0..8].copy_from_slice(&chaining_values);
-        state[8..12].copy_from_slice(&IV[0..4]);
-        state[12] = 0;
-        state[13] = 0;
-        state[14] = (chunk.len() * 4) as u32;
+/// Compute the chaining value of the chunk at position `counter`, which is also the digest if
+/// the chunk is the root.
+pub fn chunk_reference(
+    msg: &[u32],
+    key: &[u32; 8],
+    counter: u64,
+    flags: u32,
+    root: bool,
+) -> [u32; 8] {
+    chunk_reference_with_rounds(msg, key, counter, flags, root, ROUNDS)
+}
+
+fn chunk_reference_with_rounds(
+    msg: &[u32],
+    key: &[u32; 8],
+    counter: u64,
+    flags: u32,
+    root: bool,
+    rounds: usize,
+) -> [u32; 8] {
+    let mut chaining_values = key.clone();
 
-        let mut d = 0;
+    for (i, chunk) in msg.chunks(16).enumerate() {
+        let mut d = flags;
         if i == 0 {
-            d ^= 1;
+            d ^= CHUNK_START;
         }
         if i == (msg.len() + 15) / 16 - 1 {
-            d ^= 2;
-            d ^= 8;
-        }
-        state[15] = d;
-
-        let mut chunk = chunk.to_vec();
-        chunk.resize(16, 0);
-        let mut msg: [u32; 16] = chunk.try_into().unwrap();
-        for _ in 0..7 {
-            round_reference(&mut state, &mut msg);
+            d ^= CHUNK_END;
+            if root {
+                d ^= ROOT;
+            }
         }
 
-        for i in 0..8 {
-            chaining_values[i] = state[i] ^ state[i + 8];
-        }
+        let mut block = chunk.to_vec();
+        block.resize(16, 0);
+        chaining_values = compress_reference_with_rounds(
+            &chaining_values,
+            &block.try_into().unwrap(),
+            counter,
+            (chunk.len() * 4) as u32,
+            d,
+            rounds,
+        );
     }
 
     chaining_values
@@ -1,20 +1,29 @@
-use crate::compression::blake3::g::g;
+use crate::compression::blake3::g::try_g;
 use crate::compression::blake3::lookup_table::LookupTableVar;
 use crate::limbs::u32::U32Var;
+use anyhow::Result;
 
 pub fn round(table: &LookupTableVar, state_ref: &mut [U32Var; 16], msg: &mut [U32Var; 16]) {
+    try_round(table, state_ref, msg).unwrap()
+}
+
+pub fn try_round(
+    table: &LookupTableVar,
+    state_ref: &mut [U32Var; 16],
+    msg: &mut [U32Var; 16],
+) -> Result<()> {
     let [ref mut s0, ref mut s1, ref mut s2, ref mut s3, ref mut s4, ref mut s5, ref mut s6, ref mut s7, ref mut s8, ref mut s9, ref mut s10, ref mut s11, ref mut s12, ref mut s13, ref mut s14, ref mut s15] =
         *state_ref;
 
-    g(table, s0, s4, s8, s12, &msg[0], &msg[1]);
-    g(table, s1, s5, s9, s13, &msg[2], &msg[3]);
-    g(table, s2, s6, s10, s14, &msg[4], &msg[5]);
-    g(table, s3, s7, s11, s15, &msg[6], &msg[7]);
+    try_g(table, s0, s4, s8, s12, &msg[0], &msg[1])?;
+    try_g(table, s1, s5, s9, s13, &msg[2], &msg[3])?;
+    try_g(table, s2, s6, s10, s14, &msg[4], &msg[5])?;
+    try_g(table, s3, s7, s11, s15, &msg[6], &msg[7])?;
 
-    g(table, s0, s5, s10, s15, &msg[8], &msg[9]);
-    g(table, s1, s6, s11, s12, &msg[10], &msg[11]);
-    g(table, s2, s7, s8, s13, &msg[12], &msg[13]);
-    g(table, s3, s4, s9, s14, &msg[14], &msg[15]);
+    try_g(table, s0, s5, s10, s15, &msg[8], &msg[9])?;
+    try_g(table, s1, s6, s11, s12, &msg[10], &msg[11])?;
+    try_g(table, s2, s7, s8, s13, &msg[12], &msg[13])?;
+    try_g(table, s3, s4, s9, s14, &msg[14], &msg[15])?;
 
     *msg = [
         msg[2].clone(),
@@ -34,14 +43,16 @@ pub fn round(table: &LookupTableVar, state_ref: &mut [U32Var; 16], msg: &mut [U3
         msg[15].clone(),
         msg[8].clone(),
     ];
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use crate::compression::blake3::lookup_table::LookupTableVar;
-    use crate::compression::blake3::reference::round_reference;
+    use crate::compression::blake3::native::round_reference;
     use crate::compression::blake3::round::round;
     use crate::limbs::u32::U32Var;
+    use crate::test_utils::u32s_to_nibbles;
     use bitcoin_circle_stark::treepp::*;
     use bitcoin_script_dsl::bvar::{AllocVar, BVar};
     use bitcoin_script_dsl::constraint_system::ConstraintSystem;
@@ -86,14 +97,7 @@ mod test {
             cs.set_program_output(&state_var[i]).unwrap();
         }
 
-        let mut values = vec![];
-        for i in 0..16 {
-            let mut v = state[i];
-            for _ in 0..8 {
-                values.push(v & 15);
-                v >>= 4;
-            }
-        }
+        let values = u32s_to_nibbles(&state);
 
         test_program_without_opcat(
             cs,
@@ -0,0 +1,180 @@
+use crate::compression::blake3::{
+    check_u4_limbs, hash, Blake3ConstantVar, Blake3HashVar, ToU4LimbVar,
+};
+use crate::limbs::u32::U32Var;
+use crate::limbs::u4::U4Var;
+use anyhow::Result;
+use bitcoin_script_dsl::bvar::AllocVar;
+
+/// A transcript that hashes structured data in script.
+///
+/// The absorbed items are buffered as limbs, and `squeeze` hashes the buffer and chains the
+/// digest into the state with `AddAssign`, as the root parent node over the two. A tag is
+/// absorbed together with its length, so the boundary between a tag and the data that follows
+/// is unambiguous. Each squeeze hashes at most 1024 bytes.
+pub struct TranscriptVar<'a> {
+    pub constant: &'a Blake3ConstantVar,
+    pub state: Blake3HashVar,
+    pub buffer: Vec<U4Var>,
+}
+
+impl<'a> TranscriptVar<'a> {
+    pub fn new(constant: &'a Blake3ConstantVar) -> Self {
+        Self {
+            constant,
+            state: constant.iv.clone(),
+            buffer: vec![],
+        }
+    }
+
+    /// Absorb the length of the tag as a little-endian u32 and then the bytes of the tag, all as
+    /// constants.
+    pub fn absorb_tag(&mut self, tag: &str) {
+        let cs = &self.constant.cs;
+
+        let mut bytes = (tag.len() as u32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(tag.as_bytes());
+        for byte in bytes {
+            // the lower nibble comes first, as in `U32Var`
+            self.buffer
+                .push(U4Var::new_constant(cs, (byte & 15) as u32).unwrap());
+            self.buffer
+                .push(U4Var::new_constant(cs, (byte >> 4) as u32).unwrap());
+        }
+    }
+
+    pub fn absorb_u32(&mut self, v: &U32Var) {
+        self.buffer.extend(v.to_u4_limbs());
+    }
+
+    pub fn absorb_hash(&mut self, h: &Blake3HashVar) {
+        self.buffer.extend(h.to_u4_limbs());
+    }
+
+    /// Absorb any limbs, which returns an error if they are not byte aligned or if the buffer
+    /// would exceed what a single squeeze hashes. Wrap an odd nibble with `PaddedU4` to absorb
+    /// it as a byte.
+    pub fn absorb(&mut self, v: &impl ToU4LimbVar) -> Result<()> {
+        let limbs = v.to_u4_limbs();
+        check_u4_limbs(&limbs)?;
+
+        let mut buffer = self.buffer.clone();
+        buffer.extend(limbs);
+        check_u4_limbs(&buffer)?;
+
+        self.buffer = buffer;
+        Ok(())
+    }
+
+    /// Hash the absorbed items, chain the digest into the state, and return the new state.
+    pub fn squeeze(&mut self) -> Blake3HashVar {
+        let digest = hash(self.constant, self.buffer.as_slice());
+        self.buffer.clear();
+
+        self.state += (self.constant, &digest);
+        self.state.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commitment::merkle::blake3_digest_to_words;
+    use crate::compression::blake3::native::parent_reference;
+    use crate::compression::blake3::transcript::TranscriptVar;
+    use crate::compression::blake3::{Blake3ConstantVar, Blake3HashVar, PaddedU4, IV, ROOT};
+    use crate::limbs::u32::U32Var;
+    use crate::limbs::u4::U4Var;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    /// The root parent node over the state and the digest, computed outside of the script.
+    fn chain(state: &[u32; 8], digest: &[u8; 32]) -> [u32; 8] {
+        parent_reference(state, &blake3_digest_to_words(digest), &IV, ROOT)
+    }
+
+    fn words_to_bytes(words: &[u32; 8]) -> [u8; 32] {
+        let mut res = [0u8; 32];
+        for (chunk, word) in res.chunks_exact_mut(4).zip(words.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        res
+    }
+
+    fn transcript_test_case(tag: &str, payload: &[u32], h: [u8; 32]) -> ([u8; 32], [u8; 32]) {
+        let mut bytes = (tag.len() as u32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(tag.as_bytes());
+        for v in payload.iter() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.extend_from_slice(&h);
+
+        let first = chain(&IV, blake3::hash(&bytes).as_bytes());
+        let second = chain(&first, blake3::hash(&[]).as_bytes());
+        let expected = (words_to_bytes(&first), words_to_bytes(&second));
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let mut transcript = TranscriptVar::new(&constant);
+        transcript.absorb_tag(tag);
+        for &v in payload.iter() {
+            transcript.absorb_u32(&U32Var::new_program_input(&cs, v).unwrap());
+        }
+        transcript.absorb_hash(&Blake3HashVar::new_program_input(&cs, h).unwrap());
+
+        let first = transcript.squeeze();
+        let second = transcript.squeeze();
+        assert_eq!((first.value().unwrap(), second.value().unwrap()), expected);
+
+        first
+            .equalverify(&Blake3HashVar::new_constant(&cs, expected.0).unwrap())
+            .unwrap();
+        second
+            .equalverify(&Blake3HashVar::new_constant(&cs, expected.1).unwrap())
+            .unwrap();
+        test_program_without_opcat(cs, script! {}).unwrap();
+
+        expected
+    }
+
+    #[test]
+    fn test_transcript_absorb() {
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+        let mut transcript = TranscriptVar::new(&constant);
+
+        let nibble = U4Var::new_program_input(&cs, 5).unwrap();
+        assert!(transcript.absorb(&nibble).is_err());
+        assert!(transcript.buffer.is_empty());
+
+        transcript.absorb(&PaddedU4(nibble)).unwrap();
+        assert_eq!(transcript.buffer.len(), 2);
+
+        let words = (0..256)
+            .map(|_| U32Var::new_program_input(&cs, 0).unwrap())
+            .collect::<Vec<_>>();
+        assert!(transcript.absorb(&words.as_slice()).is_err());
+        assert_eq!(transcript.buffer.len(), 2);
+    }
+
+    #[test]
+    fn test_transcript() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let payload: [u32; 4] = prng.gen();
+        let h: [u8; 32] = prng.gen();
+
+        let (a_first, a_second) = transcript_test_case("memory/read", &payload, h);
+        let (b_first, b_second) = transcript_test_case("memory/read", &payload, h);
+        let (c_first, _) = transcript_test_case("memory/write", &payload, h);
+
+        assert_eq!(a_first, b_first);
+        assert_eq!(a_second, b_second);
+        assert_ne!(a_first, a_second);
+        assert_ne!(a_first, c_first);
+    }
+}
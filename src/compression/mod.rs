@@ -1 +1,2 @@
 pub mod blake3;
+pub mod sha256;
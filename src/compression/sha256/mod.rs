@@ -0,0 +1,133 @@
+use crate::compression::blake3::lookup_table::LookupTableVar;
+use crate::limbs::u32::U32Var;
+use crate::limbs::TryAdd;
+use anyhow::Result;
+use bitcoin_script_dsl::bvar::AllocVar;
+use bitcoin_script_dsl::constraint_system::ConstraintSystemRef;
+use round::{try_message_schedule, try_round};
+
+pub mod round;
+
+pub const IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+pub const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+pub struct Sha256ConstantVar {
+    pub cs: ConstraintSystemRef,
+    pub table: LookupTableVar,
+}
+
+impl Sha256ConstantVar {
+    /// Allocate the XOR, add, and shift tables, as SHA-256 rotates and shifts by arbitrary
+    /// numbers of bits.
+    pub fn new(cs: &ConstraintSystemRef) -> Self {
+        Self {
+            cs: cs.clone(),
+            table: LookupTableVar::builder()
+                .with_xor()
+                .with_add()
+                .with_shift()
+                .build(cs)
+                .unwrap(),
+        }
+    }
+
+    pub fn iv(&self) -> [U32Var; 8] {
+        IV.map(|v| U32Var::new_constant(&self.cs, v).unwrap())
+    }
+}
+
+/// Compress one block of 16 big-endian words into the state, as in FIPS 180-4.
+pub fn sha256_compress(
+    constant: &Sha256ConstantVar,
+    state: &[U32Var; 8],
+    block: &[U32Var; 16],
+) -> [U32Var; 8] {
+    try_sha256_compress(constant, state, block).unwrap()
+}
+
+pub fn try_sha256_compress(
+    constant: &Sha256ConstantVar,
+    state: &[U32Var; 8],
+    block: &[U32Var; 16],
+) -> Result<[U32Var; 8]> {
+    let table = &constant.table;
+
+    let w = try_message_schedule(table, block)?;
+
+    let mut working = state.clone();
+    for (k, w) in K.iter().zip(w.iter()) {
+        let k = U32Var::new_constant(&constant.cs, *k)?;
+        try_round(table, &mut working, &k, w)?;
+    }
+
+    let mut res = vec![];
+    for (s, v) in state.iter().zip(working.iter()) {
+        res.push(s.try_add((table, v))?);
+    }
+    Ok(res.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::sha256::{sha256_compress, Sha256ConstantVar};
+    use crate::limbs::u32::U32Var;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_sha256_compress() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..2 {
+            let mut message = [0u8; 32];
+            prng.fill(&mut message);
+
+            // a single padded block: the message, 0x80, zeros, and the length in bits
+            let mut block = message.to_vec();
+            block.push(0x80);
+            block.resize(56, 0);
+            block.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+
+            let expected = Sha256::digest(message);
+
+            let cs = ConstraintSystem::new_ref();
+            let constant = Sha256ConstantVar::new(&cs);
+
+            let block_vars = block
+                .chunks_exact(4)
+                .map(|chunk| {
+                    let word = u32::from_be_bytes(chunk.try_into().unwrap());
+                    U32Var::new_program_input(&cs, word).unwrap()
+                })
+                .collect::<Vec<_>>();
+            let state = sha256_compress(&constant, &constant.iv(), &block_vars.try_into().unwrap());
+
+            for (word_var, chunk) in state.iter().zip(expected.chunks_exact(4)) {
+                let word = u32::from_be_bytes(chunk.try_into().unwrap());
+                assert_eq!(word_var.value().unwrap(), word);
+                word_var
+                    .equalverify(&U32Var::new_constant(&cs, word).unwrap())
+                    .unwrap();
+            }
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::compression::blake3::lookup_table::LookupTableVar;
+use crate::limbs::u32::U32Var;
+use crate::limbs::TryAdd;
+use anyhow::Result;
+
+/// Expand the block into the 64 words of the message schedule.
+pub fn message_schedule(table: &LookupTableVar, block: &[U32Var; 16]) -> Vec<U32Var> {
+    try_message_schedule(table, block).unwrap()
+}
+
+pub fn try_message_schedule(table: &LookupTableVar, block: &[U32Var; 16]) -> Result<Vec<U32Var>> {
+    let mut w = block.to_vec();
+    for t in 16..64 {
+        let s0 = small_sigma0(table, &w[t - 15])?;
+        let s1 = small_sigma1(table, &w[t - 2])?;
+        let next = U32Var::try_sum(table, &[&s1, &w[t - 7], &s0, &w[t - 16]])?;
+        w.push(next);
+    }
+    Ok(w)
+}
+
+pub fn round(table: &LookupTableVar, state: &mut [U32Var; 8], k: &U32Var, w: &U32Var) {
+    try_round(table, state, k, w).unwrap()
+}
+
+pub fn try_round(
+    table: &LookupTableVar,
+    state: &mut [U32Var; 8],
+    k: &U32Var,
+    w: &U32Var,
+) -> Result<()> {
+    let [a, b, c, d, e, f, g, h] = state.clone();
+
+    let s1 = big_sigma1(table, &e)?;
+    let ch = ch(table, &e, &f, &g)?;
+    let t1 = U32Var::try_sum(table, &[&h, &s1, &ch, k, w])?;
+
+    let s0 = big_sigma0(table, &a)?;
+    let maj = maj(table, &a, &b, &c)?;
+    let t2 = s0.try_add((table, &maj))?;
+
+    *state = [
+        t1.try_add((table, &t2))?,
+        a,
+        b,
+        c,
+        d.try_add((table, &t1))?,
+        e,
+        f,
+        g,
+    ];
+    Ok(())
+}
+
+fn xor3(table: &LookupTableVar, x: &U32Var, y: &U32Var, z: &U32Var) -> Result<U32Var> {
+    x.try_xor(table, y)?.try_xor(table, z)
+}
+
+fn ch(table: &LookupTableVar, e: &U32Var, f: &U32Var, g: &U32Var) -> Result<U32Var> {
+    e.try_and(table, f)?
+        .try_xor(table, &e.try_not()?.try_and(table, g)?)
+}
+
+/// Compute the majority as (a & (b ^ c)) ^ (b & c), which saves an AND over the definition.
+fn maj(table: &LookupTableVar, a: &U32Var, b: &U32Var, c: &U32Var) -> Result<U32Var> {
+    a.try_and(table, &b.try_xor(table, c)?)?
+        .try_xor(table, &b.try_and(table, c)?)
+}
+
+fn big_sigma0(table: &LookupTableVar, a: &U32Var) -> Result<U32Var> {
+    xor3(
+        table,
+        &a.clone().try_rotate_right(2, table)?,
+        &a.clone().try_rotate_right(13, table)?,
+        &a.clone().try_rotate_right(22, table)?,
+    )
+}
+
+fn big_sigma1(table: &LookupTableVar, e: &U32Var) -> Result<U32Var> {
+    xor3(
+        table,
+        &e.clone().try_rotate_right(6, table)?,
+        &e.clone().try_rotate_right(11, table)?,
+        &e.clone().try_rotate_right(25, table)?,
+    )
+}
+
+fn small_sigma0(table: &LookupTableVar, x: &U32Var) -> Result<U32Var> {
+    xor3(
+        table,
+        &x.clone().try_rotate_right(7, table)?,
+        &x.clone().try_rotate_right(18, table)?,
+        &x.clone().try_shr(3, table)?,
+    )
+}
+
+fn small_sigma1(table: &LookupTableVar, x: &U32Var) -> Result<U32Var> {
+    xor3(
+        table,
+        &x.clone().try_rotate_right(17, table)?,
+        &x.clone().try_rotate_right(19, table)?,
+        &x.clone().try_shr(10, table)?,
+    )
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use bitcoin_script_dsl::compiler::Compiler;
+use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+use std::ops::{Add, AddAssign, Mul};
+
+/// The cost of a program or of an operation, as the length in bytes of its compiled script.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cost {
+    pub script_len: usize,
+}
+
+impl Cost {
+    /// Build a program on a fresh constraint system with `build` and measure its script.
+    pub fn measure(build: impl FnOnce(&ConstraintSystemRef) -> Result<()>) -> Result<Cost> {
+        let cs = ConstraintSystem::new_ref();
+        build(&cs)?;
+        Ok(Cost {
+            script_len: estimated_script_len(&cs)?,
+        })
+    }
+
+    /// Measure the scripts inserted by `op` alone, without the allocation of its inputs by
+    /// `alloc`, which is measured separately and subtracted.
+    pub fn measure_op<T>(
+        alloc: impl Fn(&ConstraintSystemRef) -> Result<T>,
+        op: impl FnOnce(&T) -> Result<()>,
+    ) -> Result<Cost> {
+        let inputs = Self::measure(|cs| alloc(cs).map(|_| ()))?;
+        let total = Self::measure(|cs| op(&alloc(cs)?))?;
+        Ok(Cost {
+            script_len: total.script_len.saturating_sub(inputs.script_len),
+        })
+    }
+}
+
+impl Add for Cost {
+    type Output = Cost;
+
+    fn add(self, rhs: Cost) -> Cost {
+        Cost {
+            script_len: self.script_len + rhs.script_len,
+        }
+    }
+}
+
+impl AddAssign for Cost {
+    fn add_assign(&mut self, rhs: Cost) {
+        self.script_len += rhs.script_len;
+    }
+}
+
+impl Mul<usize> for Cost {
+    type Output = Cost;
+
+    fn mul(self, rhs: usize) -> Cost {
+        Cost {
+            script_len: self.script_len * rhs,
+        }
+    }
+}
+
+/// The length in bytes of the script that the constraint system compiles to, which includes the
+/// scripts of all the operations inserted so far and the pushes of the constants.
+pub fn estimated_script_len(cs: &ConstraintSystemRef) -> Result<usize> {
+    Ok(Compiler::compile(cs.clone())?.script.len())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::blake3::lookup_table::LookupTableVar;
+    use crate::cost::Cost;
+    use crate::limbs::u32::U32Var;
+    use bitcoin_script_dsl::bvar::AllocVar;
+    use bitcoin_script_dsl::constraint_system::ConstraintSystemRef;
+
+    #[test]
+    fn test_cost_of_u32_ops() {
+        let alloc = |cs: &ConstraintSystemRef| -> anyhow::Result<_> {
+            Ok((
+                LookupTableVar::new_constant(cs, ())?,
+                U32Var::new_program_input(cs, 0x12345678)?,
+                U32Var::new_program_input(cs, 0x9abcdef0)?,
+            ))
+        };
+
+        let add = Cost::measure_op(alloc, |(table, a, b)| {
+            let _ = a + (table, b);
+            Ok(())
+        })
+        .unwrap();
+        let xor = Cost::measure_op(alloc, |(table, a, b)| {
+            let _ = a ^ (table, b);
+            Ok(())
+        })
+        .unwrap();
+        let two_adds = Cost::measure_op(alloc, |(table, a, b)| {
+            let sum = a + (table, b);
+            let _ = &sum + (table, b);
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(add.script_len > 0);
+        assert!(xor.script_len > 0);
+        assert!(two_adds > add);
+        assert_eq!(add + add, add * 2);
+    }
+}
@@ -0,0 +1,462 @@
+use crate::commitment::winternitz::{WinternitzMetadata, WinternitzSecretKey, WinternitzSignature};
+use anyhow::{Error, Result};
+use redb::{Database, ReadableTable, TableDefinition, TableError, WriteTransaction};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+const KEYSTORE_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("keystore");
+
+/// The prefix of the records of the Winternitz keys that have signed, followed by the domain
+/// separator from which the key is derived.
+const WINTERNITZ_USED_PREFIX: &str = "winternitz-used/";
+
+pub enum Keystore {
+    HashMap(HashMap<String, Vec<u8>>),
+    REDB {
+        database: Database,
+        /// The pending writes, which are committed on `flush()`.
+        transaction: Option<WriteTransaction>,
+    },
+    /// A sled database, which needs no transaction to be held between writes.
+    #[cfg(feature = "sled")]
+    Sled(sled::Db),
+}
+
+impl Keystore {
+    pub fn new_hashmap() -> Self {
+        Keystore::HashMap(HashMap::new())
+    }
+
+    pub fn new_redb(database: Database) -> Self {
+        Keystore::REDB {
+            database,
+            transaction: None,
+        }
+    }
+
+    #[cfg(feature = "sled")]
+    pub fn new_sled(db: sled::Db) -> Self {
+        Keystore::Sled(db)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Keystore::HashMap(map) => Ok(map.get(key).cloned()),
+            Keystore::REDB {
+                database,
+                transaction,
+            } => {
+                if let Some(transaction) = transaction {
+                    let table = transaction.open_table(KEYSTORE_TABLE)?;
+                    let res = table.get(key)?.map(|v| v.value().to_vec());
+                    Ok(res)
+                } else {
+                    let transaction = database.begin_read()?;
+                    let table = match transaction.open_table(KEYSTORE_TABLE) {
+                        Ok(table) => table,
+                        Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+                        Err(e) => return Err(e.into()),
+                    };
+                    let res = table.get(key)?.map(|v| v.value().to_vec());
+                    Ok(res)
+                }
+            }
+            #[cfg(feature = "sled")]
+            Keystore::Sled(db) => Ok(db.get(key)?.map(|v| v.to_vec())),
+        }
+    }
+
+    pub fn put(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        match self {
+            Keystore::HashMap(map) => {
+                map.insert(key.to_string(), value.to_vec());
+            }
+            Keystore::REDB { .. } => {
+                let transaction = self.redb_write_transaction()?;
+                let mut table = transaction.open_table(KEYSTORE_TABLE)?;
+                table.insert(key, value)?;
+            }
+            #[cfg(feature = "sled")]
+            Keystore::Sled(db) => {
+                db.insert(key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `put`, with the signature of `HashMap::insert`.
+    pub fn insert(&mut self, key: &str, value: Vec<u8>) -> Result<()> {
+        self.put(key, &value)
+    }
+
+    pub fn delete(&mut self, key: &str) -> Result<()> {
+        match self {
+            Keystore::HashMap(map) => {
+                map.remove(key);
+            }
+            Keystore::REDB { .. } => {
+                let transaction = self.redb_write_transaction()?;
+                let mut table = transaction.open_table(KEYSTORE_TABLE)?;
+                table.remove(key)?;
+            }
+            #[cfg(feature = "sled")]
+            Keystore::Sled(db) => {
+                db.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return all the entries whose keys start with `prefix`, sorted by key.
+    pub fn iter_prefix(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut res = vec![];
+        match self {
+            Keystore::HashMap(map) => {
+                for (k, v) in map.iter() {
+                    if k.starts_with(prefix) {
+                        res.push((k.clone(), v.clone()));
+                    }
+                }
+                res.sort();
+            }
+            Keystore::REDB {
+                database,
+                transaction,
+            } => {
+                if let Some(transaction) = transaction {
+                    let table = transaction.open_table(KEYSTORE_TABLE)?;
+                    for entry in table.range::<&str>(prefix..)? {
+                        let (k, v) = entry?;
+                        if !k.value().starts_with(prefix) {
+                            break;
+                        }
+                        res.push((k.value().to_string(), v.value().to_vec()));
+                    }
+                } else {
+                    let transaction = database.begin_read()?;
+                    let table = match transaction.open_table(KEYSTORE_TABLE) {
+                        Ok(table) => table,
+                        Err(TableError::TableDoesNotExist(_)) => return Ok(res),
+                        Err(e) => return Err(e.into()),
+                    };
+                    for entry in table.range::<&str>(prefix..)? {
+                        let (k, v) = entry?;
+                        if !k.value().starts_with(prefix) {
+                            break;
+                        }
+                        res.push((k.value().to_string(), v.value().to_vec()));
+                    }
+                }
+            }
+            #[cfg(feature = "sled")]
+            Keystore::Sled(db) => {
+                for entry in db.scan_prefix(prefix) {
+                    let (k, v) = entry?;
+                    res.push((String::from_utf8(k.to_vec())?, v.to_vec()));
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Return all the keys, sorted. The pending writes are included.
+    pub fn keys(&self) -> Result<Vec<String>> {
+        Ok(self.iter()?.map(|(k, _)| k).collect())
+    }
+
+    /// Iterate over all the entries sorted by key, from a snapshot that includes the pending
+    /// writes.
+    pub fn iter(&self) -> Result<impl Iterator<Item = (String, Vec<u8>)>> {
+        Ok(self.iter_prefix("")?.into_iter())
+    }
+
+    /// Commit the pending writes. This is a no-op for the HashMap backend, and it syncs the
+    /// database to disk for the sled backend.
+    pub fn flush(&mut self) -> Result<()> {
+        match self {
+            Keystore::HashMap(_) => {}
+            Keystore::REDB { transaction, .. } => {
+                if let Some(transaction) = transaction.take() {
+                    transaction.commit()?;
+                }
+            }
+            #[cfg(feature = "sled")]
+            Keystore::Sled(db) => {
+                db.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn store_winternitz(&mut self, secret_key: &WinternitzSecretKey) -> Result<()> {
+        let bytes = bincode::serialize(secret_key)?;
+        self.put(&secret_key.metadata.name, &bytes)
+    }
+
+    pub fn load_winternitz_key(&self, name: &str) -> Result<Option<WinternitzSecretKey>> {
+        match self.get(name)? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Load the key stored under `name` and check that it has the expected `w` and `l`.
+    pub fn load_winternitz(&self, name: &str, w: usize, l: usize) -> Result<WinternitzSecretKey> {
+        let secret_key = self
+            .load_winternitz_key(name)?
+            .ok_or_else(|| Error::msg(format!("No Winternitz key is stored as {}.", name)))?;
+
+        if secret_key.metadata.w != w || secret_key.metadata.l != l {
+            return Err(Error::msg(format!(
+                "The Winternitz key {} has w = {} and l = {}, but w = {} and l = {} are requested.",
+                name, secret_key.metadata.w, secret_key.metadata.l, w, l
+            )));
+        }
+        Ok(secret_key)
+    }
+
+    /// Sign with a Winternitz key, recording the metadata from which it is derived so that the
+    /// key never signs a different message, even if it is derived again. Signing the same message
+    /// again is allowed, as it gives the same signature. The record is flushed before the
+    /// signature is returned.
+    pub fn sign_winternitz_once(
+        &mut self,
+        secret_key: &WinternitzSecretKey,
+        data: &[bool],
+    ) -> Result<WinternitzSignature> {
+        let name = &secret_key.metadata.name;
+        let record_key = winternitz_used_record_key(&secret_key.metadata);
+
+        let mut sha = Sha256::new();
+        for &bit in data.iter() {
+            Digest::update(&mut sha, [bit as u8]);
+        }
+        let message_hash = sha.finalize().to_vec();
+
+        match self.get(&record_key)? {
+            Some(recorded) if recorded != message_hash => {
+                return Err(Error::msg(format!(
+                    "The Winternitz key {} has already signed a different message. It is a one-time key, and a signature over a second message reveals enough of the hash chains for anyone to forge signatures.",
+                    name
+                )));
+            }
+            Some(_) => {}
+            None => {
+                self.put(&record_key, &message_hash)?;
+                self.flush()?;
+            }
+        }
+
+        Ok(secret_key.sign(data))
+    }
+
+    /// Whether the Winternitz key with the given metadata has signed through
+    /// `sign_winternitz_once`.
+    pub fn is_winternitz_used(&self, metadata: &WinternitzMetadata) -> Result<bool> {
+        Ok(self.get(&winternitz_used_record_key(metadata))?.is_some())
+    }
+
+    fn redb_write_transaction(&mut self) -> Result<&WriteTransaction> {
+        match self {
+            Keystore::REDB {
+                database,
+                transaction,
+            } => {
+                if transaction.is_none() {
+                    *transaction = Some(database.begin_write()?);
+                }
+                Ok(transaction.as_ref().unwrap())
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn winternitz_used_record_key(metadata: &WinternitzMetadata) -> String {
+    format!("{}{}", WINTERNITZ_USED_PREFIX, metadata.domain_separator())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commitment::winternitz::{bytes_to_bits, Winternitz};
+    use crate::keystore::Keystore;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use redb::backends::InMemoryBackend;
+    use redb::Database;
+
+    fn new_redb_keystore() -> Keystore {
+        let database = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        Keystore::new_redb(database)
+    }
+
+    fn check_keystore(keystore: &mut Keystore) {
+        assert_eq!(keystore.get("a").unwrap(), None);
+        assert!(keystore.iter_prefix("").unwrap().is_empty());
+
+        keystore.put("a", &[1, 2, 3]).unwrap();
+        keystore.put("b/1", &[4]).unwrap();
+        keystore.put("b/0", &[5]).unwrap();
+        keystore.put("c", &[]).unwrap();
+        assert_eq!(keystore.get("a").unwrap(), Some(vec![1, 2, 3]));
+
+        keystore.flush().unwrap();
+        assert_eq!(keystore.get("a").unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(keystore.get("c").unwrap(), Some(vec![]));
+
+        keystore.put("a", &[6]).unwrap();
+        assert_eq!(keystore.get("a").unwrap(), Some(vec![6]));
+
+        assert_eq!(
+            keystore.iter_prefix("b/").unwrap(),
+            vec![("b/0".to_string(), vec![5]), ("b/1".to_string(), vec![4])]
+        );
+
+        keystore.delete("b/0").unwrap();
+        keystore.flush().unwrap();
+        assert_eq!(keystore.get("b/0").unwrap(), None);
+        assert_eq!(
+            keystore.iter_prefix("b/").unwrap(),
+            vec![("b/1".to_string(), vec![4])]
+        );
+    }
+
+    fn check_winternitz(keystore: &mut Keystore) {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 4, 10);
+
+        keystore.store_winternitz(&secret_key).unwrap();
+        keystore.flush().unwrap();
+
+        assert_eq!(
+            keystore.load_winternitz_key("test").unwrap(),
+            Some(secret_key.clone())
+        );
+        assert_eq!(keystore.load_winternitz_key("missing").unwrap(), None);
+
+        assert_eq!(keystore.load_winternitz("test", 4, 10).unwrap(), secret_key);
+        assert!(keystore.load_winternitz("test", 8, 10).is_err());
+        assert!(keystore.load_winternitz("test", 4, 5).is_err());
+        assert!(keystore.load_winternitz("missing", 4, 10).is_err());
+    }
+
+    fn check_winternitz_reuse(keystore: &mut Keystore) {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let winternitz = Winternitz::keygen(&mut prng);
+        let secret_key = winternitz.get_secret_key("test", 8, 4);
+        let public_key = secret_key.to_public_key();
+
+        let first = bytes_to_bits(&[1, 2, 3, 4]);
+        let second = bytes_to_bits(&[1, 2, 3, 5]);
+
+        assert!(!keystore.is_winternitz_used(&secret_key.metadata).unwrap());
+        let signature = keystore.sign_winternitz_once(&secret_key, &first).unwrap();
+        public_key.verify(&first, &signature).unwrap();
+        assert!(keystore.is_winternitz_used(&secret_key.metadata).unwrap());
+
+        // signing the same message again gives the same signature
+        assert_eq!(
+            keystore.sign_winternitz_once(&secret_key, &first).unwrap(),
+            signature
+        );
+
+        // the key derived again by name is still recognized as used
+        let derived_again = winternitz.get_secret_key("test", 8, 4);
+        assert!(keystore
+            .sign_winternitz_once(&derived_again, &second)
+            .is_err());
+
+        let other = winternitz.get_secret_key("other", 8, 4);
+        keystore.sign_winternitz_once(&other, &second).unwrap();
+
+        // the same name with another w derives a different key
+        let other_w = winternitz.get_secret_key("test", 4, 8);
+        assert!(!keystore.is_winternitz_used(&other_w.metadata).unwrap());
+        keystore.sign_winternitz_once(&other_w, &second).unwrap();
+    }
+
+    #[test]
+    fn test_keystore_winternitz_reuse() {
+        check_winternitz_reuse(&mut Keystore::new_hashmap());
+        check_winternitz_reuse(&mut new_redb_keystore());
+    }
+
+    #[test]
+    fn test_keystore_insert_backends_agree() {
+        let mut hashmap = Keystore::new_hashmap();
+        let mut redb = new_redb_keystore();
+
+        let entries = [
+            ("x", vec![1u8]),
+            ("y", vec![]),
+            ("x", vec![2, 3]),
+            ("z/0", vec![4]),
+        ];
+        for (key, value) in entries.iter() {
+            hashmap.insert(key, value.clone()).unwrap();
+            redb.insert(key, value.clone()).unwrap();
+            assert_eq!(hashmap.get(key).unwrap(), redb.get(key).unwrap());
+        }
+        redb.flush().unwrap();
+
+        for key in ["x", "y", "z/0", "missing"] {
+            assert_eq!(hashmap.get(key).unwrap(), redb.get(key).unwrap());
+        }
+        assert_eq!(hashmap.get("x").unwrap(), Some(vec![2, 3]));
+        assert_eq!(
+            hashmap.iter_prefix("").unwrap(),
+            redb.iter_prefix("").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_keystore_keys_and_iter() {
+        let mut hashmap = Keystore::new_hashmap();
+        let mut redb = new_redb_keystore();
+
+        for keystore in [&mut hashmap, &mut redb] {
+            keystore.put("b", &[2]).unwrap();
+            keystore.put("a", &[1]).unwrap();
+            keystore.flush().unwrap();
+            // a pending write is visible before the flush
+            keystore.put("c/0", &[3]).unwrap();
+        }
+
+        let expected_keys = vec!["a".to_string(), "b".to_string(), "c/0".to_string()];
+        assert_eq!(redb.keys().unwrap(), expected_keys);
+        assert_eq!(hashmap.keys().unwrap(), expected_keys);
+
+        let redb_entries = redb.iter().unwrap().collect::<Vec<_>>();
+        assert_eq!(hashmap.iter().unwrap().collect::<Vec<_>>(), redb_entries);
+        assert_eq!(redb_entries[2], ("c/0".to_string(), vec![3]));
+    }
+
+    #[test]
+    fn test_keystore_hashmap() {
+        let mut keystore = Keystore::new_hashmap();
+        check_keystore(&mut keystore);
+        check_winternitz(&mut keystore);
+    }
+
+    #[test]
+    fn test_keystore_redb() {
+        let mut keystore = new_redb_keystore();
+        check_keystore(&mut keystore);
+        check_winternitz(&mut keystore);
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_keystore_sled() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let mut keystore = Keystore::new_sled(db);
+        check_keystore(&mut keystore);
+        check_winternitz(&mut keystore);
+
+        keystore.insert("z", vec![7]).unwrap();
+        assert_eq!(keystore.keys().unwrap().last().unwrap(), "z");
+    }
+}
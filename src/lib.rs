@@ -2,3 +2,14 @@ pub mod limbs;
 
 pub mod commitment;
 pub mod compression;
+
+pub mod cost;
+
+pub mod keystore;
+
+pub mod memory;
+
+pub mod watchdog;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
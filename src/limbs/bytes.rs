@@ -0,0 +1,59 @@
+use anyhow::Result;
+use bitcoin_script_dsl::builtins::u8::U8Var;
+use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
+use bitcoin_script_dsl::constraint_system::ConstraintSystemRef;
+
+/// A byte string with one `U8Var` per byte, in the order in which the bytes are read.
+#[derive(Clone)]
+pub struct BytesVar {
+    pub bytes: Vec<U8Var>,
+    pub cs: ConstraintSystemRef,
+}
+
+impl BytesVar {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl BVar for BytesVar {
+    type Value = Vec<u8>;
+
+    fn cs(&self) -> ConstraintSystemRef {
+        self.cs.clone()
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        self.bytes.iter().map(|byte| byte.variable).collect()
+    }
+
+    /// The length varies with the number of bytes, so this is the length of an empty string.
+    fn length() -> usize {
+        0
+    }
+
+    fn value(&self) -> Result<Self::Value> {
+        self.bytes.iter().map(|byte| byte.value()).collect()
+    }
+}
+
+impl AllocVar for BytesVar {
+    fn new_variable(
+        cs: &ConstraintSystemRef,
+        data: <Self as BVar>::Value,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        let mut bytes = vec![];
+        for byte in data {
+            bytes.push(U8Var::new_variable(cs, byte, mode)?);
+        }
+        Ok(Self {
+            bytes,
+            cs: cs.clone(),
+        })
+    }
+}
@@ -1,2 +1,23 @@
+use anyhow::Result;
+
+pub mod bytes;
+pub mod u16;
 pub mod u32;
 pub mod u4;
+pub mod u64;
+pub mod u8;
+
+/// The fallible form of `Add`, which returns an error, such as a missing table or a limit of
+/// the watchdog, instead of panicking.
+pub trait TryAdd<Rhs> {
+    type Output;
+
+    fn try_add(self, rhs: Rhs) -> Result<Self::Output>;
+}
+
+/// The fallible form of `Sub`, as `TryAdd` is for `Add`.
+pub trait TrySub<Rhs> {
+    type Output;
+
+    fn try_sub(self, rhs: Rhs) -> Result<Self::Output>;
+}
@@ -0,0 +1,171 @@
+use crate::compression::blake3::lookup_table::LookupTableVar;
+use crate::limbs::u4::{NoCarry, U4Var};
+use anyhow::Result;
+use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
+use bitcoin_script_dsl::constraint_system::ConstraintSystemRef;
+use std::ops::{Add, BitXor};
+
+/// A 16-bit value as four little-endian nibbles, with the same layout as the lower half of a
+/// `U32Var`.
+#[derive(Debug, Clone)]
+pub struct U16Var {
+    pub limbs: [U4Var; 4],
+}
+
+impl BVar for U16Var {
+    type Value = u16;
+
+    fn cs(&self) -> ConstraintSystemRef {
+        let mut cs = self.limbs[0].cs();
+        for limb in self.limbs.iter().skip(1) {
+            cs = cs.and(&limb.cs());
+        }
+        cs
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        self.limbs.iter().map(|limb| limb.variable).collect()
+    }
+
+    fn length() -> usize {
+        4
+    }
+
+    fn value(&self) -> Result<Self::Value> {
+        let mut value = 0;
+        for limb in self.limbs.iter().rev() {
+            value <<= 4;
+            value += limb.value()? as u16;
+        }
+        Ok(value)
+    }
+}
+
+impl AllocVar for U16Var {
+    fn new_variable(
+        cs: &ConstraintSystemRef,
+        data: <Self as BVar>::Value,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        let limbs = [
+            U4Var::new_variable(cs, (data & 15) as u32, mode)?,
+            U4Var::new_variable(cs, ((data >> 4) & 15) as u32, mode)?,
+            U4Var::new_variable(cs, ((data >> 8) & 15) as u32, mode)?,
+            U4Var::new_variable(cs, (data >> 12) as u32, mode)?,
+        ];
+        Ok(Self { limbs })
+    }
+}
+
+impl Add<(&LookupTableVar, &U16Var)> for &U16Var {
+    type Output = U16Var;
+
+    fn add(self, rhs: (&LookupTableVar, &U16Var)) -> Self::Output {
+        let table = rhs.0;
+        let rhs = rhs.1;
+
+        let (limb_0, carry) = &self.limbs[0] + (table, &rhs.limbs[0]);
+        let (limb_1, carry) = &self.limbs[1] + (table, &rhs.limbs[1], &carry);
+        let (limb_2, carry) = &self.limbs[2] + (table, &rhs.limbs[2], &carry);
+        let limb_3 = &self.limbs[3] + (table, &rhs.limbs[3], &carry, NoCarry::default());
+
+        U16Var {
+            limbs: [limb_0, limb_1, limb_2, limb_3],
+        }
+    }
+}
+
+impl BitXor<(&LookupTableVar, &U16Var)> for &U16Var {
+    type Output = U16Var;
+
+    fn bitxor(self, rhs: (&LookupTableVar, &U16Var)) -> Self::Output {
+        let table = rhs.0;
+        let rhs = rhs.1;
+
+        let limbs = self
+            .limbs
+            .iter()
+            .zip(rhs.limbs.iter())
+            .map(|(l, r)| l ^ (table, r))
+            .collect::<Vec<_>>();
+
+        U16Var {
+            limbs: limbs.try_into().unwrap(),
+        }
+    }
+}
+
+impl U16Var {
+    /// Rotate right by `n` bits, as a permutation of `n / 4` nibbles followed by a shift of
+    /// `n % 4` bits using the shift tables.
+    pub fn rotate_right(self, n: u32, table: &LookupTableVar) -> Self {
+        self.try_rotate_right(n, table).unwrap()
+    }
+
+    pub fn try_rotate_right(self, n: u32, table: &LookupTableVar) -> Result<Self> {
+        assert!(n < 16);
+        let nibbles = (n / 4) as usize;
+        let bits = (n % 4) as usize;
+
+        let mut limbs = vec![];
+        for i in 0..4 {
+            if bits == 0 {
+                limbs.push(self.limbs[(i + nibbles) % 4].clone());
+            } else {
+                let first = &self.limbs[(i + nibbles) % 4].try_get_shr(bits, table)?;
+                let second = &self.limbs[(i + nibbles + 1) % 4].try_get_shl(4 - bits, table)?;
+                limbs.push(first.try_add_no_overflow(second)?);
+            }
+        }
+        Ok(Self {
+            limbs: limbs.try_into().unwrap(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::blake3::lookup_table::LookupTableVar;
+    use crate::limbs::u16::U16Var;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_u16_add_xor_rotate() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n in 0..16 {
+            let cs = ConstraintSystem::new_ref();
+            let table = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let a: u16 = prng.gen();
+            let b: u16 = prng.gen();
+
+            let a_var = U16Var::new_program_input(&cs, a).unwrap();
+            let b_var = U16Var::new_program_input(&cs, b).unwrap();
+
+            let sum_var = &a_var + (&table, &b_var);
+            assert_eq!(sum_var.value().unwrap(), a.wrapping_add(b));
+            sum_var
+                .equalverify(&U16Var::new_constant(&cs, a.wrapping_add(b)).unwrap())
+                .unwrap();
+
+            let xor_var = &a_var ^ (&table, &b_var);
+            xor_var
+                .equalverify(&U16Var::new_constant(&cs, a ^ b).unwrap())
+                .unwrap();
+
+            let rotated_var = a_var.rotate_right(n, &table);
+            assert_eq!(rotated_var.value().unwrap(), a.rotate_right(n));
+            rotated_var
+                .equalverify(&U16Var::new_constant(&cs, a.rotate_right(n)).unwrap())
+                .unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+}
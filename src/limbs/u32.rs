@@ -1,14 +1,37 @@
 use crate::compression::blake3::lookup_table::LookupTableVar;
-use crate::limbs::u4::{NoCarry, U4Var};
-use anyhow::Result;
+use crate::limbs::u4::{u4_range_verify, CarryVar, NoCarry, U4Var};
+use crate::limbs::u8::u8_check_u4_pair;
+use crate::limbs::{TryAdd, TrySub};
+use crate::watchdog::{track_alloc, track_var, WatchdogExt};
+use anyhow::{Error, Result};
 use bitcoin_circle_stark::treepp::*;
+use bitcoin_script_dsl::builtins::hash::HashVar;
+use bitcoin_script_dsl::builtins::i32::I32Var;
+use bitcoin_script_dsl::builtins::u8::U8Var;
 use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
 use bitcoin_script_dsl::constraint_system::{ConstraintSystemRef, Element};
-use std::ops::{Add, BitOrAssign, BitXor};
+use bitcoin_script_dsl::options::Options;
+use bitcoin_script_dsl::stack::Stack;
+use std::ops::{Add, BitAnd, BitOr, BitOrAssign, BitXor, Not, Sub};
 
 #[derive(Debug, Clone)]
 pub struct U32Var {
-    pub limbs: [U4Var; 8],
+    limbs: [U4Var; 8],
+}
+
+impl U32Var {
+    /// Assemble a word from its nibbles, the lowest one first, which returns an error if any
+    /// limb holds a value beyond u4.
+    pub fn from_limbs(limbs: [U4Var; 8]) -> Result<Self> {
+        for limb in limbs.iter() {
+            limb.value()?;
+        }
+        Ok(Self { limbs })
+    }
+
+    pub fn limbs(&self) -> &[U4Var; 8] {
+        &self.limbs
+    }
 }
 
 impl BVar for U32Var {
@@ -71,43 +94,134 @@ impl AllocVar for U32Var {
     }
 }
 
+impl TryAdd<(&LookupTableVar, &U32Var)> for &U32Var {
+    type Output = U32Var;
+
+    fn try_add(self, rhs: (&LookupTableVar, &U32Var)) -> Result<Self::Output> {
+        let table = rhs.0;
+        let rhs = rhs.1;
+
+        // adding a constant zero does not change the value, so no script is needed
+        if rhs.is_constant_zero() {
+            return Ok(self.clone());
+        }
+        if self.is_constant_zero() {
+            return Ok(rhs.clone());
+        }
+        if let Some(res) = fold_constants(self, rhs, u32::wrapping_add) {
+            return Ok(res);
+        }
+
+        let mut limbs = vec![];
+
+        let (limb, carry) = self.limbs[0].try_add((table, &rhs.limbs[0]))?;
+        limbs.push(limb);
+
+        let (limb, carry) = self.limbs[1].try_add((table, &rhs.limbs[1], &carry))?;
+        limbs.push(limb);
+
+        let (limb, carry) = self.limbs[2].try_add((table, &rhs.limbs[2], &carry))?;
+        limbs.push(limb);
+
+        let (limb, carry) = self.limbs[3].try_add((table, &rhs.limbs[3], &carry))?;
+        limbs.push(limb);
+
+        let (limb, carry) = self.limbs[4].try_add((table, &rhs.limbs[4], &carry))?;
+        limbs.push(limb);
+
+        let (limb, carry) = self.limbs[5].try_add((table, &rhs.limbs[5], &carry))?;
+        limbs.push(limb);
+
+        let (limb, carry) = self.limbs[6].try_add((table, &rhs.limbs[6], &carry))?;
+        limbs.push(limb);
+
+        let limb = self.limbs[7].try_add((table, &rhs.limbs[7], &carry, NoCarry::default()))?;
+        limbs.push(limb);
+
+        Ok(U32Var {
+            limbs: limbs.try_into().unwrap(),
+        })
+    }
+}
+
 impl Add<(&LookupTableVar, &U32Var)> for &U32Var {
     type Output = U32Var;
 
     fn add(self, rhs: (&LookupTableVar, &U32Var)) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+impl TryAdd<(&LookupTableVar, &U32Var, &U32Var)> for &U32Var {
+    type Output = U32Var;
+
+    fn try_add(self, rhs: (&LookupTableVar, &U32Var, &U32Var)) -> Result<Self::Output> {
         let table = rhs.0;
-        let rhs = rhs.1;
+        let rhs_1 = rhs.1;
+        let rhs_2 = rhs.2;
+
+        // fall back to the two-operand addition if any of the operands is a constant zero
+        if rhs_2.is_constant_zero() {
+            return self.try_add((table, rhs_1));
+        }
+        if rhs_1.is_constant_zero() {
+            return self.try_add((table, rhs_2));
+        }
+        if self.is_constant_zero() {
+            return rhs_1.try_add((table, rhs_2));
+        }
+        // fold a pair of constants first, so that only one addition remains in script
+        if self.is_constant() && rhs_1.is_constant() {
+            return self.try_add((table, rhs_1))?.try_add((table, rhs_2));
+        }
+        if rhs_1.is_constant() && rhs_2.is_constant() {
+            return self.try_add((table, &rhs_1.try_add((table, rhs_2))?));
+        }
+        if self.is_constant() && rhs_2.is_constant() {
+            return self.try_add((table, rhs_2))?.try_add((table, rhs_1));
+        }
 
         let mut limbs = vec![];
 
-        let (limb, carry) = &self.limbs[0] + (table, &rhs.limbs[0]);
+        let (limb, carry) = self.limbs[0].try_add((table, &rhs_1.limbs[0], &rhs_2.limbs[0]))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[1] + (table, &rhs.limbs[1], &carry);
+        let (limb, carry) =
+            self.limbs[1].try_add((table, &rhs_1.limbs[1], &rhs_2.limbs[1], &carry))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[2] + (table, &rhs.limbs[2], &carry);
+        let (limb, carry) =
+            self.limbs[2].try_add((table, &rhs_1.limbs[2], &rhs_2.limbs[2], &carry))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[3] + (table, &rhs.limbs[3], &carry);
+        let (limb, carry) =
+            self.limbs[3].try_add((table, &rhs_1.limbs[3], &rhs_2.limbs[3], &carry))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[4] + (table, &rhs.limbs[4], &carry);
+        let (limb, carry) =
+            self.limbs[4].try_add((table, &rhs_1.limbs[4], &rhs_2.limbs[4], &carry))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[5] + (table, &rhs.limbs[5], &carry);
+        let (limb, carry) =
+            self.limbs[5].try_add((table, &rhs_1.limbs[5], &rhs_2.limbs[5], &carry))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[6] + (table, &rhs.limbs[6], &carry);
+        let (limb, carry) =
+            self.limbs[6].try_add((table, &rhs_1.limbs[6], &rhs_2.limbs[6], &carry))?;
         limbs.push(limb);
 
-        let limb = &self.limbs[7] + (table, &rhs.limbs[7], &carry, NoCarry::default());
+        let limb = self.limbs[7].try_add((
+            table,
+            &rhs_1.limbs[7],
+            &rhs_2.limbs[7],
+            &carry,
+            NoCarry::default(),
+        ))?;
         limbs.push(limb);
 
-        let res_var = U32Var {
+        Ok(U32Var {
             limbs: limbs.try_into().unwrap(),
-        };
-        res_var
+        })
     }
 }
 
@@ -115,47 +229,60 @@ impl Add<(&LookupTableVar, &U32Var, &U32Var)> for &U32Var {
     type Output = U32Var;
 
     fn add(self, rhs: (&LookupTableVar, &U32Var, &U32Var)) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+impl TrySub<(&LookupTableVar, &U32Var)> for &U32Var {
+    type Output = U32Var;
+
+    fn try_sub(self, rhs: (&LookupTableVar, &U32Var)) -> Result<Self::Output> {
         let table = rhs.0;
-        let rhs_1 = rhs.1;
-        let rhs_2 = rhs.2;
+        let rhs = rhs.1;
+
+        // subtracting a constant zero does not change the value, so no script is needed
+        if rhs.is_constant_zero() {
+            return Ok(self.clone());
+        }
 
         let mut limbs = vec![];
 
-        let (limb, carry) = &self.limbs[0] + (table, &rhs_1.limbs[0], &rhs_2.limbs[0]);
+        let (limb, borrow) = self.limbs[0].try_sub((table, &rhs.limbs[0]))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[1] + (table, &rhs_1.limbs[1], &rhs_2.limbs[1], &carry);
+        let (limb, borrow) = self.limbs[1].try_sub((table, &rhs.limbs[1], &borrow))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[2] + (table, &rhs_1.limbs[2], &rhs_2.limbs[2], &carry);
+        let (limb, borrow) = self.limbs[2].try_sub((table, &rhs.limbs[2], &borrow))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[3] + (table, &rhs_1.limbs[3], &rhs_2.limbs[3], &carry);
+        let (limb, borrow) = self.limbs[3].try_sub((table, &rhs.limbs[3], &borrow))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[4] + (table, &rhs_1.limbs[4], &rhs_2.limbs[4], &carry);
+        let (limb, borrow) = self.limbs[4].try_sub((table, &rhs.limbs[4], &borrow))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[5] + (table, &rhs_1.limbs[5], &rhs_2.limbs[5], &carry);
+        let (limb, borrow) = self.limbs[5].try_sub((table, &rhs.limbs[5], &borrow))?;
         limbs.push(limb);
 
-        let (limb, carry) = &self.limbs[6] + (table, &rhs_1.limbs[6], &rhs_2.limbs[6], &carry);
+        let (limb, borrow) = self.limbs[6].try_sub((table, &rhs.limbs[6], &borrow))?;
         limbs.push(limb);
 
-        let limb = &self.limbs[7]
-            + (
-                table,
-                &rhs_1.limbs[7],
-                &rhs_2.limbs[7],
-                &carry,
-                NoCarry::default(),
-            );
+        // the borrow off the top limb is dropped, which makes the subtraction wrapping
+        let limb = self.limbs[7].try_sub((table, &rhs.limbs[7], &borrow, NoCarry::default()))?;
         limbs.push(limb);
 
-        let res_var = U32Var {
+        Ok(U32Var {
             limbs: limbs.try_into().unwrap(),
-        };
-        res_var
+        })
+    }
+}
+
+impl Sub<(&LookupTableVar, &U32Var)> for &U32Var {
+    type Output = U32Var;
+
+    fn sub(self, rhs: (&LookupTableVar, &U32Var)) -> Self::Output {
+        self.try_sub(rhs).unwrap()
     }
 }
 
@@ -163,21 +290,375 @@ impl BitXor<(&LookupTableVar, &U32Var)> for &U32Var {
     type Output = U32Var;
 
     fn bitxor(self, rhs: (&LookupTableVar, &U32Var)) -> Self::Output {
-        let mut limbs = vec![];
-        let table = rhs.0;
-        let rhs = rhs.1;
+        self.try_xor(rhs.0, rhs.1).unwrap()
+    }
+}
+
+impl BitAnd<(&LookupTableVar, &U32Var)> for &U32Var {
+    type Output = U32Var;
+
+    fn bitand(self, rhs: (&LookupTableVar, &U32Var)) -> Self::Output {
+        self.try_and(rhs.0, rhs.1).unwrap()
+    }
+}
+
+impl BitOr<(&LookupTableVar, &U32Var)> for &U32Var {
+    type Output = U32Var;
+
+    fn bitor(self, rhs: (&LookupTableVar, &U32Var)) -> Self::Output {
+        self.try_or(rhs.0, rhs.1).unwrap()
+    }
+}
+
+/// Compute the result off-chain and allocate it as a constant if both operands are constants,
+/// so that no script is needed. A constant combined with any other variable returns `None`.
+fn fold_constants(a: &U32Var, b: &U32Var, f: impl Fn(u32, u32) -> u32) -> Option<U32Var> {
+    if !a.is_constant() || !b.is_constant() {
+        return None;
+    }
+    let cs = a.cs().and(&b.cs());
+    Some(U32Var::new_constant(&cs, f(a.value().ok()?, b.value().ok()?)).unwrap())
+}
+
+impl Not for &U32Var {
+    type Output = U32Var;
+
+    fn not(self) -> Self::Output {
+        self.try_not().unwrap()
+    }
+}
+
+impl U32Var {
+    /// The fallible forms of the bitwise operators, which return an error, such as a missing
+    /// table or a limit of the watchdog, instead of panicking.
+    pub fn try_xor(&self, table: &LookupTableVar, rhs: &U32Var) -> Result<U32Var> {
+        if let Some(res) = fold_constants(self, rhs, |a, b| a ^ b) {
+            return Ok(res);
+        }
+        self.limbwise(rhs, |l, r| l.try_xor(table, r))
+    }
+
+    pub fn try_and(&self, table: &LookupTableVar, rhs: &U32Var) -> Result<U32Var> {
+        self.limbwise(rhs, |l, r| l.try_and(table, r))
+    }
 
+    pub fn try_or(&self, table: &LookupTableVar, rhs: &U32Var) -> Result<U32Var> {
+        self.limbwise(rhs, |l, r| l.try_or(table, r))
+    }
+
+    pub fn try_not(&self) -> Result<U32Var> {
+        self.limbwise(self, |l, _| l.try_not())
+    }
+
+    fn limbwise(
+        &self,
+        rhs: &U32Var,
+        f: impl Fn(&U4Var, &U4Var) -> Result<U4Var>,
+    ) -> Result<U32Var> {
+        let mut limbs = vec![];
         for (l, r) in self.limbs.iter().zip(rhs.limbs.iter()) {
-            limbs.push(l ^ (table, r));
+            limbs.push(f(l, r)?);
+        }
+        Ok(U32Var {
+            limbs: limbs.try_into().unwrap(),
+        })
+    }
+}
+
+impl U32Var {
+    pub fn is_constant(&self) -> bool {
+        self.limbs.iter().all(|limb| limb.is_constant())
+    }
+
+    /// Compute `0 - self` in wrapping arithmetic.
+    pub fn negate(&self, table: &LookupTableVar) -> Self {
+        let zero = U32Var::new_constant(&self.cs(), 0).unwrap();
+        &zero - (table, self)
+    }
+
+    /// Alias of `negate`, named after `u32::wrapping_neg`.
+    pub fn wrapping_neg(&self, table: &LookupTableVar) -> Self {
+        self.negate(table)
+    }
+
+    /// Interpret the value as a two's complement signed integer, for debugging.
+    pub fn as_i32_value(&self) -> Result<i32> {
+        Ok(self.value()? as i32)
+    }
+
+    /// Return 1 if the two values are equal and 0 otherwise, without aborting the script.
+    ///
+    /// Pairs of limbs that are both constants are compared at compile time, so no script is
+    /// emitted for them.
+    pub fn is_equal(&self, other: &U32Var) -> U4Var {
+        let cs = self.cs().and(&other.cs());
+
+        let mut variables = vec![];
+        let mut num_pairs = 0;
+        for (l, r) in self.limbs.iter().zip(other.limbs.iter()) {
+            if l.is_constant() && r.is_constant() {
+                if l.value != r.value {
+                    return U4Var::new_constant(&cs, 0).unwrap();
+                }
+            } else {
+                variables.push(l.variable);
+                variables.push(r.variable);
+                num_pairs += 1;
+            }
         }
 
+        if num_pairs == 0 {
+            return U4Var::new_constant(&cs, 1).unwrap();
+        }
+
+        let res = (self.value().unwrap() == other.value().unwrap()) as u32;
+        cs.insert_gadget_complex(
+            "u32_is_equal",
+            u32_is_equal,
+            variables,
+            &Options::new().with_u32("num_pairs", num_pairs),
+        )
+        .unwrap();
+        U4Var::new_function_output(&cs, res).unwrap()
+    }
+
+    /// Return `if_true` if `cond` is 1 and `if_false` if `cond` is 0, where any other value of
+    /// `cond` fails the script.
+    pub fn select(cond: &U4Var, if_true: &U32Var, if_false: &U32Var) -> U32Var {
+        let cs = cond.cs().and(&if_true.cs()).and(&if_false.cs());
+
+        let res = if cond.value == 1 { if_true } else { if_false };
+
+        cs.insert_gadget(
+            "u32_select",
+            u32_select,
+            if_true
+                .variables()
+                .iter()
+                .chain(if_false.variables().iter())
+                .chain(cond.variables().iter())
+                .copied(),
+        )
+        .unwrap();
+
+        let mut limbs = vec![];
+        for limb in res.limbs.iter() {
+            limbs.push(U4Var::new_function_output(&cs, limb.value).unwrap());
+        }
         U32Var {
             limbs: limbs.try_into().unwrap(),
         }
     }
-}
 
-impl U32Var {
+    /// Return 1 if `self < other` as unsigned integers and 0 otherwise.
+    pub fn less_than(&self, other: &U32Var) -> U4Var {
+        let res = self.value().unwrap() < other.value().unwrap();
+        self.compare(other, false, res)
+    }
+
+    /// Return 1 if `self >= other` as unsigned integers and 0 otherwise.
+    pub fn greater_than_or_equal(&self, other: &U32Var) -> U4Var {
+        let res = self.value().unwrap() >= other.value().unwrap();
+        self.compare(other, true, res)
+    }
+
+    fn compare(&self, other: &U32Var, is_gte: bool, res: bool) -> U4Var {
+        let cs = self.cs().and(&other.cs());
+        cs.insert_gadget_complex(
+            "u32_less_than",
+            u32_less_than,
+            self.variables()
+                .iter()
+                .chain(other.variables().iter())
+                .copied(),
+            &Options::new().with_u32("is_gte", is_gte as u32),
+        )
+        .unwrap();
+        U4Var::new_function_output(&cs, res as u32).unwrap()
+    }
+
+    /// Return 1 if bit `i` is set and 0 otherwise. Only the limb that holds the bit is used, and
+    /// the bit is extracted by comparisons, so no lookup table is needed.
+    pub fn test_bit(&self, i: u32) -> I32Var {
+        assert!(i < 32);
+
+        let limb = &self.limbs[(i / 4) as usize];
+        let cs = limb.cs();
+        cs.insert_gadget_complex(
+            "u4_test_bit",
+            u4_test_bit,
+            [limb.variable],
+            &Options::new().with_u32("bit", i % 4),
+        )
+        .unwrap();
+        track_var(I32Var::new_function_output(&cs, ((limb.value >> (i % 4)) & 1) as i32).unwrap())
+    }
+
+    /// Split the value into four big-endian bytes, each made of two nibbles.
+    pub fn to_be_bytes(&self) -> Result<[U8Var; 4]> {
+        let cs = self.cs();
+        cs.insert_gadget("u32_to_be_bytes", u32_to_be_bytes, self.variables())?;
+
+        let mut bytes = vec![];
+        for &byte in self.value()?.to_be_bytes().iter() {
+            bytes.push(U8Var::new_function_output(&cs, byte).map(track_var)?);
+        }
+        Ok(bytes.try_into().unwrap())
+    }
+
+    /// Join four big-endian bytes into a value. The nibbles are hints, which are range-checked
+    /// when allocated, and the script checks that each pair recomposes the byte.
+    pub fn from_be_bytes(bytes: &[U8Var; 4]) -> Result<U32Var> {
+        let mut cs = bytes[0].cs();
+        for byte in bytes.iter().skip(1) {
+            cs = cs.and(&byte.cs());
+        }
+
+        let mut value = 0u32;
+        for byte in bytes.iter() {
+            value = (value << 8) | byte.value()? as u32;
+        }
+        let res = U32Var::new_hint(&cs, value)?;
+
+        // the most significant byte is made of the two top limbs, and each group is ordered as
+        // the high nibble, the low nibble, and the byte
+        let mut variables = vec![];
+        for (i, byte) in bytes.iter().enumerate() {
+            variables.push(res.limbs[7 - 2 * i].variable);
+            variables.push(res.limbs[6 - 2 * i].variable);
+            variables.push(byte.variable);
+        }
+        cs.insert_gadget(
+            "u32_from_be_bytes_check",
+            u32_from_be_bytes_check,
+            variables,
+        )?;
+
+        Ok(res)
+    }
+
+    /// Sum the values modulo 2^32.
+    ///
+    /// A single pass adds as many values as the quotient table allows, which is three for the
+    /// default table up to 47, where a limb column sums to at most 3 * 15 plus a carry of 2.
+    /// Longer lists are folded into a running sum.
+    pub fn sum(table: &LookupTableVar, values: &[&U32Var]) -> U32Var {
+        Self::try_sum(table, values).unwrap()
+    }
+
+    pub fn try_sum(table: &LookupTableVar, values: &[&U32Var]) -> Result<U32Var> {
+        assert!(!values.is_empty());
+        let max_addends = table.max_addends()?;
+
+        let (first, rest) = values.split_at(values.len().min(max_addends));
+        let mut acc = Self::sum_single_pass(table, first)?;
+        for chunk in rest.chunks(max_addends - 1) {
+            let mut addends = vec![&acc];
+            addends.extend_from_slice(chunk);
+            acc = Self::sum_single_pass(table, &addends)?;
+        }
+        Ok(acc)
+    }
+
+    fn sum_single_pass(table: &LookupTableVar, values: &[&U32Var]) -> Result<U32Var> {
+        if values.len() == 1 {
+            return Ok(values[0].clone());
+        }
+
+        let mut limbs = vec![];
+        let mut carry: Option<CarryVar> = None;
+        for i in 0..8 {
+            let column = values
+                .iter()
+                .map(|value| &value.limbs[i])
+                .collect::<Vec<_>>();
+            let (limb, next) = U4Var::try_sum_with_carry(table, &column, carry.as_ref(), i < 7)?;
+            limbs.push(limb);
+            carry = next;
+        }
+
+        Ok(U32Var {
+            limbs: limbs.try_into().unwrap(),
+        })
+    }
+
+    /// Add the two values and return the sum modulo 2^32 and the carry out of the top limb.
+    ///
+    /// The carry is looked up from the quotient table in script, as is every intermediate carry,
+    /// so it is constrained to be 0 or 1 as long as the limbs of both operands are in range.
+    pub fn add_with_carry(&self, table: &LookupTableVar, rhs: &U32Var) -> (U32Var, U4Var) {
+        self.try_add_with_carry(table, rhs).unwrap()
+    }
+
+    pub fn try_add_with_carry(
+        &self,
+        table: &LookupTableVar,
+        rhs: &U32Var,
+    ) -> Result<(U32Var, U4Var)> {
+        let (res, carry) = self.add_with_carry_chain(table, rhs, None, true)?;
+        Ok((res, carry.unwrap().0))
+    }
+
+    /// Add the two values and an optional incoming carry, where the carry out of the top limb is
+    /// only computed if `carry_out` is set.
+    pub(crate) fn add_with_carry_chain(
+        &self,
+        table: &LookupTableVar,
+        rhs: &U32Var,
+        carry_in: Option<&CarryVar>,
+        carry_out: bool,
+    ) -> Result<(U32Var, Option<CarryVar>)> {
+        let mut limbs = vec![];
+        let mut carry: Option<CarryVar> = None;
+
+        for (i, (l, r)) in self.limbs.iter().zip(rhs.limbs.iter()).enumerate() {
+            let is_last = i == 7 && !carry_out;
+            let incoming = if i == 0 { carry_in } else { carry.as_ref() };
+
+            let (limb, next) = match (incoming, is_last) {
+                (None, false) => {
+                    let (limb, next) = l.try_add((table, r))?;
+                    (limb, Some(next))
+                }
+                (None, true) => (l.try_add((table, r, NoCarry::default()))?, None),
+                (Some(c), false) => {
+                    let (limb, next) = l.try_add((table, r, c))?;
+                    (limb, Some(next))
+                }
+                (Some(c), true) => (l.try_add((table, r, c, NoCarry::default()))?, None),
+            };
+            limbs.push(limb);
+            carry = next;
+        }
+
+        Ok((
+            U32Var {
+                limbs: limbs.try_into().unwrap(),
+            },
+            carry,
+        ))
+    }
+
+    /// Verify in script that every limb is in 0..=15.
+    pub fn range_verify(&self) -> Result<()> {
+        self.cs()
+            .insert_gadget("u32_range_verify", u32_range_verify, self.variables())
+    }
+
+    /// Allocate the value claimed by the prover as a hint and verify in script that it is
+    /// consistent with this variable.
+    pub fn equalverify_hint(&self, claimed: u32) -> Result<U32Var> {
+        let hint_var = U32Var::new_hint(&self.cs(), claimed)?;
+        self.equalverify(&hint_var)?;
+        Ok(hint_var)
+    }
+
+    fn is_constant_zero(&self) -> bool {
+        self.limbs
+            .iter()
+            .all(|limb| limb.is_constant() && limb.value == 0)
+    }
+
     pub fn rotate_right_shift_16(self) -> Self {
         let limbs = self.limbs;
         let new_limbs = [
@@ -224,80 +705,259 @@ impl U32Var {
     }
 
     pub fn rotate_right_shift_7(self, table: &LookupTableVar) -> Self {
+        self.try_rotate_right_shift_7(table).unwrap()
+    }
+
+    pub fn try_rotate_right_shift_7(self, table: &LookupTableVar) -> Result<Self> {
         let mut limbs = vec![];
         for i in 0..8 {
-            let first = &self.limbs[(i + 1) % 8].get_shr3(table);
-            let second = &self.limbs[(i + 2) % 8].get_shl1(table);
-            limbs.push(first.add_no_overflow(second));
+            let first = &self.limbs[(i + 1) % 8].try_get_shr3(table)?;
+            let second = &self.limbs[(i + 2) % 8].try_get_shl1(table)?;
+            limbs.push(first.try_add_no_overflow(second)?);
         }
         let limbs: [U4Var; 8] = limbs.try_into().unwrap();
-        Self { limbs }
+        Ok(Self { limbs })
     }
-}
 
-#[derive(Clone)]
-pub struct U32CompactVar {
-    pub variable: usize,
-    pub value: u32,
-    pub cs: ConstraintSystemRef,
-}
+    pub fn rotate_left_shift_16(self) -> Self {
+        self.rotate_right_shift_16()
+    }
 
-impl BVar for U32CompactVar {
-    type Value = u32;
+    pub fn rotate_left_shift_12(self) -> Self {
+        self.rotate_right_nibbles(5)
+    }
 
-    fn cs(&self) -> ConstraintSystemRef {
-        self.cs.clone()
+    pub fn rotate_left_shift_8(self) -> Self {
+        self.rotate_right_nibbles(6)
     }
 
-    fn variables(&self) -> Vec<usize> {
-        vec![self.variable]
+    /// Rotate left by 7, which is a right rotation by 25, i.e., six nibbles and one bit.
+    pub fn rotate_left_shift_7(self, table: &LookupTableVar) -> Self {
+        self.try_rotate_left_shift_7(table).unwrap()
     }
 
-    fn length() -> usize {
-        1
+    pub fn try_rotate_left_shift_7(self, table: &LookupTableVar) -> Result<Self> {
+        self.rotate_right_nibbles_and_bits(6, 1, table)
     }
 
-    fn value(&self) -> Result<Self::Value> {
-        Ok(self.value)
+    /// Rotate right by a constant number of bits, where the nibble permutation and the
+    /// sub-nibble shift are resolved at compile time.
+    pub fn rotate_right_const<const N: u32>(self, table: &LookupTableVar) -> Self {
+        self.try_rotate_right_const::<N>(table).unwrap()
     }
-}
 
-impl AllocVar for U32CompactVar {
-    fn new_variable(
-        cs: &ConstraintSystemRef,
-        data: <Self as BVar>::Value,
-        mode: AllocationMode,
-    ) -> Result<Self> {
-        let variable = cs.alloc(Element::Str(get_u32_compact_representation(data)), mode)?;
-        Ok(Self {
-            variable,
-            value: data,
-            cs: cs.clone(),
-        })
+    pub fn try_rotate_right_const<const N: u32>(self, table: &LookupTableVar) -> Result<Self> {
+        self.try_rotate_right(N, table)
     }
-}
 
-fn get_u32_compact_representation(mut v: u32) -> Vec<u8> {
-    let is_negative = v >= 2147483648u32;
+    /// Rotate right by `n` bits, as a permutation of `n / 4` nibbles followed by a shift of
+    /// `n % 4` bits using the shift tables.
+    pub fn rotate_right(self, n: u32, table: &LookupTableVar) -> Self {
+        self.try_rotate_right(n, table).unwrap()
+    }
 
-    if v >= 2147483648u32 {
-        v -= 2147483648u32;
+    pub fn try_rotate_right(self, n: u32, table: &LookupTableVar) -> Result<Self> {
+        assert!(n < 32);
+        self.rotate_right_nibbles_and_bits((n / 4) as usize, (n % 4) as usize, table)
     }
 
-    let mut bytes = Vec::new();
-    while v > 0 {
-        bytes.push((v & 0xff) as u8);
-        v >>= 8;
+    /// Shift right by `n` bits, where the bits shifted in from the top are zeros.
+    pub fn shr(self, n: u32, table: &LookupTableVar) -> Self {
+        self.try_shr(n, table).unwrap()
     }
 
-    if is_negative == false {
-        if bytes.last().is_some() && bytes.last().unwrap() & 0x80 != 0 {
-            bytes.push(0);
+    pub fn try_shr(self, n: u32, table: &LookupTableVar) -> Result<Self> {
+        assert!(n < 32);
+        let nibbles = (n / 4) as usize;
+        let bits = (n % 4) as usize;
+
+        let zero = U4Var::new_constant(&self.cs(), 0)?;
+        let mut limbs = vec![];
+        for i in 0..8 {
+            if i + nibbles >= 8 {
+                limbs.push(zero.clone());
+            } else if bits == 0 {
+                limbs.push(self.limbs[i + nibbles].clone());
+            } else if i + nibbles + 1 >= 8 {
+                limbs.push(self.limbs[i + nibbles].try_get_shr(bits, table)?);
+            } else {
+                let first = &self.limbs[i + nibbles].try_get_shr(bits, table)?;
+                let second = &self.limbs[i + nibbles + 1].try_get_shl(4 - bits, table)?;
+                limbs.push(first.try_add_no_overflow(second)?);
+            }
         }
-    } else {
-        if bytes.last().is_some() && bytes.last().unwrap() & 0x80 != 0 {
-            bytes.push(0x80);
-        } else {
+        let limbs: [U4Var; 8] = limbs.try_into().unwrap();
+        Ok(Self { limbs })
+    }
+
+    /// Rotate right by whole nibbles, which only permutes the limbs.
+    fn rotate_right_nibbles(self, nibbles: usize) -> Self {
+        let mut limbs = self.limbs;
+        limbs.rotate_left(nibbles);
+        Self { limbs }
+    }
+
+    fn rotate_right_nibbles_and_bits(
+        self,
+        nibbles: usize,
+        bits: usize,
+        table: &LookupTableVar,
+    ) -> Result<Self> {
+        let mut limbs = vec![];
+        for i in 0..8 {
+            if bits == 0 {
+                limbs.push(self.limbs[(i + nibbles) % 8].clone());
+            } else {
+                let first = &self.limbs[(i + nibbles) % 8].try_get_shr(bits, table)?;
+                let second = &self.limbs[(i + nibbles + 1) % 8].try_get_shl(4 - bits, table)?;
+                limbs.push(first.try_add_no_overflow(second)?);
+            }
+        }
+        let limbs: [U4Var; 8] = limbs.try_into().unwrap();
+        Ok(Self { limbs })
+    }
+}
+
+fn u4_test_bit(_: &mut Stack, options: &Options) -> Result<Script> {
+    let bit = options.get_u32("bit")?;
+    Ok(script! {
+        // remove the bits above the one being tested
+        for k in (bit + 1..4).rev() {
+            OP_DUP { 1 << k } OP_GREATERTHANOREQUAL OP_IF
+                { 1 << k } OP_SUB
+            OP_ENDIF
+        }
+        { 1 << bit } OP_GREATERTHANOREQUAL
+    })
+}
+
+fn u32_range_verify() -> Script {
+    script! {
+        for _ in 0..8 {
+            u4_range_verify
+        }
+    }
+}
+
+fn u32_is_equal(_: &mut Stack, options: &Options) -> Result<Script> {
+    let num_pairs = options.get_u32("num_pairs")?;
+    Ok(script! {
+        OP_EQUAL
+        for _ in 1..num_pairs {
+            OP_ROT OP_ROT OP_EQUAL OP_BOOLAND
+        }
+    })
+}
+
+fn u32_select() -> Script {
+    script! {
+        OP_DUP 0 2 OP_WITHIN OP_VERIFY
+        OP_IF
+            OP_2DROP OP_2DROP OP_2DROP OP_2DROP
+        OP_ELSE
+            for _ in 0..8 {
+                8 OP_ROLL OP_DROP
+            }
+        OP_ENDIF
+    }
+}
+
+fn u32_less_than(_: &mut Stack, options: &Options) -> Result<Script> {
+    let is_gte = options.get_u32("is_gte")? == 1;
+
+    Ok(script! {
+        // merge each half of the two values into a 16-bit number, which avoids the sign bit of
+        // the 32-bit script numbers
+        for _ in 0..2 {
+            for _ in 0..2 {
+                OP_16MUL OP_ADD
+                OP_16MUL OP_ADD
+                OP_16MUL OP_ADD
+                OP_TOALTSTACK
+            }
+        }
+        OP_FROMALTSTACK OP_FROMALTSTACK OP_FROMALTSTACK OP_FROMALTSTACK
+
+        // self_lo, self_hi, other_lo, other_hi
+        OP_ROT
+        OP_2DUP OP_GREATERTHAN OP_TOALTSTACK
+        OP_NUMEQUAL
+        OP_ROT OP_ROT OP_LESSTHAN
+        OP_BOOLAND
+        OP_FROMALTSTACK OP_BOOLOR
+
+        if is_gte {
+            OP_NOT
+        }
+    })
+}
+
+#[derive(Clone)]
+pub struct U32CompactVar {
+    pub variable: usize,
+    pub value: u32,
+    pub cs: ConstraintSystemRef,
+}
+
+impl BVar for U32CompactVar {
+    type Value = u32;
+
+    fn cs(&self) -> ConstraintSystemRef {
+        self.cs.clone()
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        vec![self.variable]
+    }
+
+    fn length() -> usize {
+        1
+    }
+
+    fn value(&self) -> Result<Self::Value> {
+        Ok(self.value)
+    }
+}
+
+impl AllocVar for U32CompactVar {
+    fn new_variable(
+        cs: &ConstraintSystemRef,
+        data: <Self as BVar>::Value,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        let variable = cs.alloc(Element::Str(get_u32_compact_representation(data)), mode)?;
+        if mode != AllocationMode::Constant {
+            track_alloc(cs, variable);
+        }
+        Ok(Self {
+            variable,
+            value: data,
+            cs: cs.clone(),
+        })
+    }
+}
+
+fn get_u32_compact_representation(mut v: u32) -> Vec<u8> {
+    let is_negative = v >= 2147483648u32;
+
+    if v >= 2147483648u32 {
+        v -= 2147483648u32;
+    }
+
+    let mut bytes = Vec::new();
+    while v > 0 {
+        bytes.push((v & 0xff) as u8);
+        v >>= 8;
+    }
+
+    if is_negative == false {
+        if bytes.last().is_some() && bytes.last().unwrap() & 0x80 != 0 {
+            bytes.push(0);
+        }
+    } else {
+        if bytes.last().is_some() && bytes.last().unwrap() & 0x80 != 0 {
+            bytes.push(0x80);
+        } else {
             if bytes.last().is_some() {
                 bytes.last_mut().unwrap().bitor_assign(&0x80);
             } else {
@@ -309,11 +969,320 @@ fn get_u32_compact_representation(mut v: u32) -> Vec<u8> {
     bytes
 }
 
+impl U32CompactVar {
+    /// Verify that the two variables hold the same value. The encodings are split into the
+    /// lower 31 bits and the highest bit and compared as numbers, rather than as byte strings.
+    ///
+    /// Note that the negative zero `0x80` encodes 0x80000000, so it does not equal 0.
+    pub fn equalverify_numeric(&self, rhs: &Self) -> Result<()> {
+        let cs = self.cs().and(&rhs.cs());
+        cs.insert_gadget(
+            "u32compact_equalverify",
+            u32compact_equalverify,
+            [self.variable, rhs.variable],
+        )
+    }
+
+    /// Return 1 if `self < other` as unsigned integers and 0 otherwise.
+    pub fn less_than(&self, other: &U32CompactVar) -> U4Var {
+        self.try_less_than(other).unwrap()
+    }
+
+    pub fn try_less_than(&self, other: &U32CompactVar) -> Result<U4Var> {
+        let cs = self.cs().and(&other.cs());
+        cs.insert_gadget(
+            "u32compact_less_than",
+            u32compact_less_than,
+            [self.variable, other.variable],
+        )?;
+        U4Var::new_function_output(&cs, (self.value < other.value) as u32)
+    }
+
+    /// Encode the value as exactly four little-endian bytes, the byte order in which Blake3
+    /// reads message words, so that it can be concatenated with OP_CAT.
+    pub fn to_hash_input(&self) -> Result<HashVar> {
+        let limbs = U32Var::from(self);
+        let cs = self.cs();
+        cs.insert_gadget("u32_to_le_bytes", u32_to_le_bytes, limbs.variables())?;
+        HashVar::new_function_output(&cs, self.value.to_le_bytes().to_vec()).map(track_var)
+    }
+
+    /// Decompose the value into digits of `w` bits, the lowest digit first, where `w` divides
+    /// 32 and is at most 8. The digits are hints, each checked to be below 2^w, and they are
+    /// checked to recompose the compact element.
+    pub fn to_digits(&self, w: usize) -> Result<Vec<U8Var>> {
+        if w == 0 || w > 8 || 32 % w != 0 {
+            return Err(Error::msg(
+                "The digits must have a number of bits that divides 32 and is at most 8.",
+            ));
+        }
+
+        let digits = (0..32 / w)
+            .map(|i| ((self.value >> (i * w)) & ((1 << w) - 1)) as u8)
+            .collect::<Vec<_>>();
+        self.to_digits_with_hints(w, &digits)
+    }
+
+    fn to_digits_with_hints(&self, w: usize, digits: &[u8]) -> Result<Vec<U8Var>> {
+        let cs = self.cs();
+
+        let mut digit_vars = vec![];
+        for &digit in digits.iter() {
+            digit_vars.push(U8Var::new_hint(&cs, digit).map(track_var)?);
+        }
+
+        cs.insert_gadget_complex(
+            "u32compact_check_digits",
+            u32compact_check_digits,
+            std::iter::once(self.variable).chain(digit_vars.iter().map(|digit| digit.variable)),
+            &Options::new().with_u32("w", w as u32),
+        )?;
+
+        Ok(digit_vars)
+    }
+}
+
+impl TryAdd<&U32CompactVar> for &U32CompactVar {
+    type Output = U32CompactVar;
+
+    fn try_add(self, rhs: &U32CompactVar) -> Result<Self::Output> {
+        let cs = self.cs().and(&rhs.cs());
+        cs.insert_gadget(
+            "u32compact_add",
+            u32compact_add,
+            [self.variable, rhs.variable],
+        )?;
+        U32CompactVar::new_function_output(&cs, self.value.wrapping_add(rhs.value))
+    }
+}
+
+impl Add<&U32CompactVar> for &U32CompactVar {
+    type Output = U32CompactVar;
+
+    fn add(self, rhs: &U32CompactVar) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+impl TrySub<&U32CompactVar> for &U32CompactVar {
+    type Output = U32CompactVar;
+
+    fn try_sub(self, rhs: &U32CompactVar) -> Result<Self::Output> {
+        let cs = self.cs().and(&rhs.cs());
+        cs.insert_gadget(
+            "u32compact_sub",
+            u32compact_sub,
+            [self.variable, rhs.variable],
+        )?;
+        U32CompactVar::new_function_output(&cs, self.value.wrapping_sub(rhs.value))
+    }
+}
+
+impl Sub<&U32CompactVar> for &U32CompactVar {
+    type Output = U32CompactVar;
+
+    fn sub(self, rhs: &U32CompactVar) -> Self::Output {
+        self.try_sub(rhs).unwrap()
+    }
+}
+
+/// Split the compact representation into the lower 31 bits and the highest bit, the latter on
+/// the top of the stack.
+fn u32compact_to_sign_and_magnitude() -> Script {
+    script! {
+        OP_DUP OP_PUSHBYTES_1 OP_LEFT OP_EQUAL OP_IF
+            OP_DROP 0 1
+        OP_ELSE
+            OP_DUP OP_ABS OP_SWAP 0 OP_LESSTHAN
+        OP_ENDIF
+    }
+}
+
+/// Check that the digits, with the highest digit on the top of the stack, recompose the compact
+/// element below them. The highest bit is taken out of the highest digit, so that the
+/// recomposition stays within 31 bits, and the rest is accumulated by repeated doubling.
+fn u32compact_check_digits(_: &mut Stack, options: &Options) -> Result<Script> {
+    let w = options.get_u32("w")? as usize;
+    let num_digits = 32 / w;
+
+    Ok(script! {
+        { num_digits } OP_ROLL
+        u32compact_to_sign_and_magnitude
+        OP_SWAP OP_TOALTSTACK OP_TOALTSTACK
+
+        OP_DUP 0 { 1 << w } OP_WITHIN OP_VERIFY
+        OP_FROMALTSTACK OP_IF
+            { 1 << (w - 1) } OP_SUB
+        OP_ENDIF
+        OP_DUP 0 { 1 << (w - 1) } OP_WITHIN OP_VERIFY
+
+        for _ in 1..num_digits {
+            for _ in 0..w {
+                OP_DUP OP_ADD
+            }
+            OP_SWAP
+            OP_DUP 0 { 1 << w } OP_WITHIN OP_VERIFY
+            OP_ADD
+        }
+
+        OP_FROMALTSTACK OP_EQUALVERIFY
+    })
+}
+
+/// Merge the lower 31 bits with the highest bit from the altstack, in the same way as
+/// `from_u32_to_u32compact`.
+fn u32compact_from_sign_and_magnitude() -> Script {
+    script! {
+        OP_FROMALTSTACK
+        OP_IF
+            OP_DUP OP_NOT OP_IF
+                OP_DROP OP_PUSHBYTES_1 OP_LEFT
+            OP_ELSE
+                OP_NEGATE
+            OP_ENDIF
+        OP_ENDIF
+    }
+}
+
+/// Leave the two magnitudes on the stack and the xor of the two highest bits in the altstack.
+fn u32compact_merge_signs() -> Script {
+    script! {
+        u32compact_to_sign_and_magnitude
+        OP_TOALTSTACK
+        OP_SWAP
+        u32compact_to_sign_and_magnitude
+        OP_FROMALTSTACK OP_NUMNOTEQUAL OP_TOALTSTACK
+    }
+}
+
+fn u32compact_equalverify() -> Script {
+    script! {
+        u32compact_to_sign_and_magnitude
+        OP_ROT
+        u32compact_to_sign_and_magnitude
+        // m2, m1, s1, s2
+        OP_ROT
+        OP_NUMEQUALVERIFY
+        OP_NUMEQUALVERIFY
+    }
+}
+
+fn u32compact_less_than() -> Script {
+    script! {
+        u32compact_to_sign_and_magnitude
+        OP_ROT
+        u32compact_to_sign_and_magnitude
+        // m2, m1, s1, s2
+        OP_ROT
+        OP_2DUP OP_LESSTHAN OP_TOALTSTACK
+        OP_NUMEQUAL
+        OP_ROT OP_ROT OP_GREATERTHAN
+        OP_BOOLAND
+        OP_FROMALTSTACK OP_BOOLOR
+    }
+}
+
+fn u32compact_add() -> Script {
+    script! {
+        u32compact_merge_signs
+
+        // the sum of the magnitudes carries into the highest bit if m1 > 2^31 - 1 - m2, which
+        // avoids computing a sum that does not fit in a 4-byte script number
+        OP_SWAP
+        2147483647 OP_SWAP OP_SUB
+        OP_2DUP OP_GREATERTHAN
+        OP_DUP OP_FROMALTSTACK OP_NUMNOTEQUAL OP_TOALTSTACK
+        OP_IF
+            OP_SUB OP_1SUB
+        OP_ELSE
+            2147483647 OP_SWAP OP_SUB OP_ADD
+        OP_ENDIF
+
+        u32compact_from_sign_and_magnitude
+    }
+}
+
+fn u32compact_sub() -> Script {
+    script! {
+        u32compact_merge_signs
+
+        // the difference of the magnitudes borrows from the highest bit if it is negative
+        OP_SWAP OP_SUB
+        OP_DUP 0 OP_LESSTHAN
+        OP_DUP OP_FROMALTSTACK OP_NUMNOTEQUAL OP_TOALTSTACK
+        OP_IF
+            2147483647 OP_ADD OP_1ADD
+        OP_ENDIF
+
+        u32compact_from_sign_and_magnitude
+    }
+}
+
+fn u32_to_be_bytes() -> Script {
+    script! {
+        for _ in 0..4 {
+            OP_16MUL OP_ADD
+            OP_TOALTSTACK
+        }
+        for _ in 0..4 {
+            OP_FROMALTSTACK
+        }
+        // the bytes come out with the most significant one on the top, so reverse them
+        OP_SWAP OP_2SWAP OP_SWAP
+    }
+}
+
+fn u32_from_be_bytes_check() -> Script {
+    script! {
+        for _ in 0..4 {
+            u8_check_u4_pair
+        }
+    }
+}
+
+fn u32_to_le_bytes() -> Script {
+    script! {
+        for _ in 0..3 {
+            OP_16MUL OP_ADD
+            u8_to_single_byte
+            OP_TOALTSTACK
+        }
+        OP_16MUL OP_ADD
+        u8_to_single_byte
+        for _ in 0..3 {
+            OP_FROMALTSTACK OP_CAT
+        }
+    }
+}
+
+/// Convert a number in 0..=255 into the one-byte string of the same value. The script number
+/// encoding differs for 0, which is the empty string, and for 128..=255, which take two bytes
+/// unless written as the negative number with the sign bit set.
+fn u8_to_single_byte() -> Script {
+    script! {
+        OP_DUP 0 OP_EQUAL OP_IF
+            OP_DROP OP_PUSHBYTES_1 OP_PUSHBYTES_0
+        OP_ELSE
+            OP_DUP 128 OP_EQUAL OP_IF
+                OP_DROP OP_PUSHBYTES_1 OP_LEFT
+            OP_ELSE
+                OP_DUP 128 OP_GREATERTHAN OP_IF
+                    128 OP_SUB OP_NEGATE
+                OP_ENDIF
+            OP_ENDIF
+        OP_ENDIF
+    }
+}
+
 impl From<&U32Var> for U32CompactVar {
     fn from(limbs: &U32Var) -> Self {
         let cs = limbs.cs();
-        cs.insert_script(from_u32_to_u32compact, limbs.variables().iter().copied())
-            .unwrap();
+        cs.insert_gadget(
+            "from_u32_to_u32compact",
+            from_u32_to_u32compact,
+            limbs.variables().iter().copied(),
+        )
+        .unwrap();
         U32CompactVar::new_function_output(&cs, limbs.value().unwrap()).unwrap()
     }
 }
@@ -360,8 +1329,12 @@ impl From<&U32CompactVar> for U32Var {
             data >>= 4;
         }
 
-        cs.insert_script(from_u32compact_to_u32, [value.variable])
-            .unwrap();
+        cs.insert_gadget(
+            "from_u32compact_to_u32",
+            from_u32compact_to_u32,
+            [value.variable],
+        )
+        .unwrap();
 
         let mut limbs_vars = vec![];
         for &v in limbs.iter() {
@@ -429,7 +1402,7 @@ fn convert_4bits_from_altstack() -> Script {
 }
 
 #[allow(non_snake_case)]
-fn OP_16MUL() -> Script {
+pub(crate) fn OP_16MUL() -> Script {
     script! {
         OP_DUP OP_ADD
         OP_DUP OP_ADD
@@ -441,11 +1414,18 @@ fn OP_16MUL() -> Script {
 #[cfg(test)]
 mod test {
     use crate::compression::blake3::lookup_table::LookupTableVar;
-    use crate::limbs::u32::{U32CompactVar, U32Var};
+    use crate::limbs::u32::{u32_from_be_bytes_check, U32CompactVar, U32Var};
+    use crate::limbs::u4::U4Var;
+    use crate::test_utils::{
+        assert_u32_op_matches, assert_u32_unary_op_matches, expect_program_failure,
+        expect_program_failure_without_opcat, u32_to_nibbles, U32_OP_CASES, U32_OP_CASES_LONG,
+    };
     use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::builtins::u8::U8Var;
     use bitcoin_script_dsl::bvar::{AllocVar, BVar};
-    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
-    use bitcoin_script_dsl::test_program_without_opcat;
+    use bitcoin_script_dsl::compiler::Compiler;
+    use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+    use bitcoin_script_dsl::{test_program, test_program_without_opcat};
     use rand::{Rng, SeedableRng};
     use rand_chacha::ChaCha20Rng;
 
@@ -471,12 +1451,7 @@ mod test {
 
             cs.set_program_output(&res_var).unwrap();
 
-            let mut values = vec![];
-            let mut res = a.wrapping_add(b);
-            for _ in 0..8 {
-                values.push(res & 15);
-                res >>= 4;
-            }
+            let values = u32_to_nibbles(a.wrapping_add(b)).to_vec();
 
             test_program_without_opcat(
                 cs,
@@ -489,26 +1464,563 @@ mod test {
     }
 
     #[test]
-    fn test_u32_rotate_right_shift_7() {
+    fn test_u32_add_with_carry() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
 
-        let cs = ConstraintSystem::new_ref();
-        let a: u32 = prng.gen();
-        let shifted_a = a.rotate_right(7);
+        let mut pairs = vec![
+            (0xffffffffu32, 1u32),
+            (0xffffffff, 0xffffffff),
+            (0xffffffff, 0),
+            (0x80000000, 0x80000000),
+            (0x7fffffff, 0x80000000),
+            (0, 0),
+        ];
+        for _ in 0..20 {
+            pairs.push((prng.gen(), prng.gen()));
+        }
 
-        let a_var = U32Var::new_program_input(&cs, a).unwrap();
-        let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+        for (a, b) in pairs {
+            let cs = ConstraintSystem::new_ref();
+            let table = LookupTableVar::new_constant(&cs, ()).unwrap();
 
-        let shifted_a_var = a_var.rotate_right_shift_7(&table_var);
-        let expected_var = U32Var::new_constant(&cs, shifted_a).unwrap();
-        shifted_a_var.equalverify(&expected_var).unwrap();
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let b_var = U32Var::new_program_input(&cs, b).unwrap();
 
-        let mut values = vec![];
-        let mut res = shifted_a;
-        for _ in 0..8 {
-            values.push(res & 15);
-            res >>= 4;
-        }
+            let (sum_var, carry_var) = a_var.add_with_carry(&table, &b_var);
+            let expected_carry = ((a as u64 + b as u64) >> 32) as u32;
+            assert_eq!(sum_var.value().unwrap(), a.wrapping_add(b));
+            assert_eq!(carry_var.value, expected_carry);
+
+            cs.set_program_output(&sum_var).unwrap();
+            cs.set_program_output(&carry_var).unwrap();
+
+            let values = u32_to_nibbles(a.wrapping_add(b)).to_vec();
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { values }
+                    { expected_carry }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_sum() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        // three copies of 0xffffffff make every limb column above the first sum to 47
+        let mut cases = vec![vec![u32::MAX; 3], vec![u32::MAX; 7], vec![u32::MAX; 1]];
+        for n in 1..=8 {
+            cases.push((0..n).map(|_| prng.gen()).collect::<Vec<u32>>());
+        }
+
+        for values in cases {
+            let cs = ConstraintSystem::new_ref();
+            let table = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let vars = values
+                .iter()
+                .map(|&v| U32Var::new_program_input(&cs, v).unwrap())
+                .collect::<Vec<_>>();
+            let sum_var = U32Var::sum(&table, &vars.iter().collect::<Vec<_>>());
+
+            let expected = values.iter().fold(0u32, |acc, &v| acc.wrapping_add(v));
+            assert_eq!(sum_var.value().unwrap(), expected);
+            sum_var
+                .equalverify(&U32Var::new_constant(&cs, expected).unwrap())
+                .unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_sum_wide_table() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut cases = vec![vec![u32::MAX; 7], vec![u32::MAX; 13]];
+        for n in [2, 7, 9] {
+            cases.push((0..n).map(|_| prng.gen()).collect::<Vec<u32>>());
+        }
+
+        for values in cases {
+            let cs = ConstraintSystem::new_ref();
+            let table = LookupTableVar::builder()
+                .with_max_sum(111)
+                .build(&cs)
+                .unwrap();
+            assert_eq!(table.max_addends().unwrap(), 7);
+
+            let vars = values
+                .iter()
+                .map(|&v| U32Var::new_program_input(&cs, v).unwrap())
+                .collect::<Vec<_>>();
+            let sum_var = U32Var::sum(&table, &vars.iter().collect::<Vec<_>>());
+
+            let expected = values.iter().fold(0u32, |acc, &v| acc.wrapping_add(v));
+            assert_eq!(sum_var.value().unwrap(), expected);
+            sum_var
+                .equalverify(&U32Var::new_constant(&cs, expected).unwrap())
+                .unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_sub() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut pairs = vec![
+            (0u32, 0u32),
+            (0, 1),
+            (0, u32::MAX),
+            (5, 5),
+            (u32::MAX, 0x80000000),
+        ];
+        for _ in 0..10 {
+            let x: u32 = prng.gen();
+            pairs.push((x, x));
+        }
+        for _ in 0..100 {
+            pairs.push((prng.gen(), prng.gen()));
+        }
+
+        for (a, b) in pairs {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let b_var = U32Var::new_program_input(&cs, b).unwrap();
+
+            let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let res_var = &a_var - (&table_var, &b_var);
+            let expected_var = U32Var::new_constant(&cs, a.wrapping_sub(b)).unwrap();
+
+            res_var.equalverify(&expected_var).unwrap();
+
+            cs.set_program_output(&res_var).unwrap();
+
+            let values = u32_to_nibbles(a.wrapping_sub(b)).to_vec();
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { values }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "LookupTableBuilder::with_add")]
+    fn test_u32_add_uses_lookup_table() {
+        // the only addition goes through the quotient and remainder tables, so it cannot run
+        // with a table that lacks them
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder().with_xor().build(&cs).unwrap();
+
+        let a_var = U32Var::new_program_input(&cs, 1).unwrap();
+        let _ = &a_var + (&table, &a_var);
+    }
+
+    #[test]
+    fn test_u32_wrapping_neg() {
+        for a in [0u32, 1, 0x7fffffff, 0x80000000, 0x80000001, u32::MAX] {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let res_var = a_var.wrapping_neg(&table_var);
+            assert_eq!(res_var.value().unwrap(), a.wrapping_neg());
+            assert_eq!(a_var.as_i32_value().unwrap(), a as i32);
+            assert_eq!(res_var.as_i32_value().unwrap(), (a as i32).wrapping_neg());
+
+            cs.set_program_output(&res_var).unwrap();
+
+            let values = u32_to_nibbles(a.wrapping_neg()).to_vec();
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { values }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_is_equal() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let a: u32 = prng.gen();
+        let cases = [(a, 1), (a ^ 0x100, 0), (!a, 0)];
+
+        for (b, expected) in cases {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let b_var = U32Var::new_program_input(&cs, b).unwrap();
+
+            let res_var = a_var.is_equal(&b_var);
+            assert_eq!(res_var.value().unwrap(), expected);
+            cs.set_program_output(&res_var).unwrap();
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { expected }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_is_equal_constant_limbs() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let a: u32 = prng.gen();
+        for (b, expected) in [(a, 1), (a ^ 0x100, 0)] {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U32Var::new_constant(&cs, a).unwrap();
+            let b_var = U32Var::new_constant(&cs, b).unwrap();
+
+            let res_var = a_var.is_equal(&b_var);
+            assert!(res_var.is_constant());
+            assert_eq!(res_var.value().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_u32_constant_folding() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let a: u32 = prng.gen();
+        let b: u32 = prng.gen();
+        let c: u32 = prng.gen();
+
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+        let a_var = U32Var::new_constant(&cs, a).unwrap();
+        let b_var = U32Var::new_constant(&cs, b).unwrap();
+        let c_var = U32Var::new_program_input(&cs, c).unwrap();
+
+        let sum_var = &a_var + (&table, &b_var);
+        let xor_var = &a_var ^ (&table, &b_var);
+        assert!(sum_var.is_constant());
+        assert!(xor_var.is_constant());
+        assert_eq!(sum_var.value().unwrap(), a.wrapping_add(b));
+        assert_eq!(xor_var.value().unwrap(), a ^ b);
+
+        // a constant combined with a program input is still constrained in script
+        let mixed_var = &a_var + (&table, &b_var, &c_var);
+        assert!(!mixed_var.is_constant());
+        assert_eq!(
+            mixed_var.value().unwrap(),
+            a.wrapping_add(b).wrapping_add(c)
+        );
+
+        let expected_var = U32Var::new_constant(&cs, a.wrapping_add(b).wrapping_add(c)).unwrap();
+        mixed_var.equalverify(&expected_var).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_u32_test_bit() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut values = vec![0u32, u32::MAX, 0x80000001];
+        for _ in 0..3 {
+            values.push(prng.gen());
+        }
+
+        for a in values {
+            let cs = ConstraintSystem::new_ref();
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+
+            let mut expected = vec![];
+            for i in 0..32 {
+                let bit_var = a_var.test_bit(i);
+                assert_eq!(bit_var.value().unwrap(), ((a >> i) & 1) as i32);
+                cs.set_program_output(&bit_var).unwrap();
+                expected.push((a >> i) & 1);
+            }
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { expected }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_not() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let cs = ConstraintSystem::new_ref();
+
+            let a: u32 = prng.gen();
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+
+            let res_var = !&a_var;
+            assert_eq!(res_var.value().unwrap(), !a);
+            cs.set_program_output(&res_var).unwrap();
+
+            let values = u32_to_nibbles(!a).to_vec();
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { values }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_less_than() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let a: u32 = prng.gen();
+        let mut pairs = vec![
+            (0u32, 0u32),
+            (0, 1),
+            (1, 0),
+            (0, u32::MAX),
+            (u32::MAX, 0),
+            (u32::MAX, u32::MAX),
+            (0x7fffffff, 0x80000000),
+            (0x80000000, 0x7fffffff),
+            (0xffff, 0x10000),
+            (a & !1, a | 1),
+            (a | 1, a & !1),
+        ];
+        for _ in 0..20 {
+            pairs.push((prng.gen(), prng.gen()));
+        }
+
+        for (a, b) in pairs {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let b_var = U32Var::new_program_input(&cs, b).unwrap();
+
+            let lt_var = a_var.less_than(&b_var);
+            let gte_var = a_var.greater_than_or_equal(&b_var);
+            assert_eq!(lt_var.value().unwrap(), (a < b) as u32);
+            assert_eq!(gte_var.value().unwrap(), (a >= b) as u32);
+
+            cs.set_program_output(&lt_var).unwrap();
+            cs.set_program_output(&gte_var).unwrap();
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { (a < b) as u32 }
+                    { (a >= b) as u32 }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_select() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for cond in [0, 1] {
+            let cs = ConstraintSystem::new_ref();
+
+            let a: u32 = prng.gen();
+            let b: u32 = prng.gen();
+
+            let cond_var = U4Var::new_program_input(&cs, cond).unwrap();
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let b_var = U32Var::new_program_input(&cs, b).unwrap();
+
+            let expected = if cond == 1 { a } else { b };
+
+            let res_var = U32Var::select(&cond_var, &a_var, &b_var);
+            assert_eq!(res_var.value().unwrap(), expected);
+            cs.set_program_output(&res_var).unwrap();
+
+            let values = u32_to_nibbles(expected).to_vec();
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { values }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_select_malformed_cond() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let cs = ConstraintSystem::new_ref();
+
+        let a: u32 = prng.gen();
+        let b: u32 = prng.gen();
+
+        let cond_var = U4Var::new_program_input(&cs, 2).unwrap();
+        let a_var = U32Var::new_program_input(&cs, a).unwrap();
+        let b_var = U32Var::new_program_input(&cs, b).unwrap();
+
+        let _ = U32Var::select(&cond_var, &a_var, &b_var);
+
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_u32_and() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let cs = ConstraintSystem::new_ref();
+
+            let a: u32 = prng.gen();
+            let b: u32 = prng.gen();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let b_var = U32Var::new_program_input(&cs, b).unwrap();
+
+            let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let res_var = &a_var & (&table_var, &b_var);
+            assert_eq!(res_var.value().unwrap(), a & b);
+            cs.set_program_output(&res_var).unwrap();
+
+            let values = u32_to_nibbles(a & b).to_vec();
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { values }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_or() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let cs = ConstraintSystem::new_ref();
+
+            let a: u32 = prng.gen();
+            let b: u32 = prng.gen();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let b_var = U32Var::new_program_input(&cs, b).unwrap();
+
+            let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let res_var = &a_var | (&table_var, &b_var);
+            assert_eq!(res_var.value().unwrap(), a | b);
+            cs.set_program_output(&res_var).unwrap();
+
+            let values = u32_to_nibbles(a | b).to_vec();
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { values }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_add_constant_zero() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let a: u32 = prng.gen();
+        let b: u32 = prng.gen();
+
+        let cs = ConstraintSystem::new_ref();
+        let a_var = U32Var::new_program_input(&cs, a).unwrap();
+        let zero_var = U32Var::new_constant(&cs, 0).unwrap();
+        let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+        let res_var = &a_var + (&table_var, &zero_var);
+        assert_eq!(res_var.value().unwrap(), a);
+        cs.set_program_output(&res_var).unwrap();
+        let len_with_zero = Compiler::compile(cs).unwrap().script.len();
+
+        let cs = ConstraintSystem::new_ref();
+        let a_var = U32Var::new_program_input(&cs, a).unwrap();
+        let b_var = U32Var::new_program_input(&cs, b).unwrap();
+        let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+        let res_var = &a_var + (&table_var, &b_var);
+        cs.set_program_output(&res_var).unwrap();
+        let len_with_var = Compiler::compile(cs).unwrap().script.len();
+
+        assert!(len_with_zero < len_with_var);
+    }
+
+    #[test]
+    fn test_u32_equalverify_hint() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let cs = ConstraintSystem::new_ref();
+        let a: u32 = prng.gen();
+
+        let a_var = U32Var::new_program_input(&cs, a).unwrap();
+        a_var.equalverify_hint(a).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_u32_equalverify_hint_mismatch() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let cs = ConstraintSystem::new_ref();
+        let a: u32 = prng.gen();
+
+        let a_var = U32Var::new_program_input(&cs, a).unwrap();
+        a_var.equalverify_hint(a ^ 1).unwrap();
+
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_u32_rotate_right_shift_7() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let cs = ConstraintSystem::new_ref();
+        let a: u32 = prng.gen();
+        let shifted_a = a.rotate_right(7);
+
+        let a_var = U32Var::new_program_input(&cs, a).unwrap();
+        let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+        let shifted_a_var = a_var.rotate_right_shift_7(&table_var);
+        let expected_var = U32Var::new_constant(&cs, shifted_a).unwrap();
+        shifted_a_var.equalverify(&expected_var).unwrap();
+
+        let values = u32_to_nibbles(shifted_a).to_vec();
 
         cs.set_program_output(&shifted_a_var).unwrap();
 
@@ -521,6 +2033,276 @@ mod test {
         .unwrap();
     }
 
+    #[test]
+    fn test_u32_rotate_left() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n in [16, 12, 8, 7] {
+            let cs = ConstraintSystem::new_ref();
+            let a: u32 = prng.gen();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let shifted_a_var = match n {
+                16 => a_var.rotate_left_shift_16(),
+                12 => a_var.rotate_left_shift_12(),
+                8 => a_var.rotate_left_shift_8(),
+                7 => a_var.rotate_left_shift_7(&table_var),
+                _ => unreachable!(),
+            };
+            assert_eq!(shifted_a_var.value().unwrap(), a.rotate_left(n));
+
+            let expected_var = U32Var::new_constant(&cs, a.rotate_left(n)).unwrap();
+            shifted_a_var.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_rotate_right() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n in 0..32 {
+            let cs = ConstraintSystem::new_ref();
+            let a: u32 = prng.gen();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let shifted_a_var = a_var.rotate_right(n, &table_var);
+            assert_eq!(shifted_a_var.value().unwrap(), a.rotate_right(n));
+
+            let expected_var = U32Var::new_constant(&cs, a.rotate_right(n)).unwrap();
+            shifted_a_var.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_shr() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n in 0..32 {
+            let cs = ConstraintSystem::new_ref();
+            let a: u32 = prng.gen();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let shifted_a_var = a_var.shr(n, &table_var);
+            assert_eq!(shifted_a_var.value().unwrap(), a >> n);
+
+            let expected_var = U32Var::new_constant(&cs, a >> n).unwrap();
+            shifted_a_var.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_rotate_right_const() {
+        fn check<const N: u32>(a: u32) {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let table_var = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let shifted_a_var = a_var.rotate_right_const::<N>(&table_var);
+            let expected_var = U32Var::new_constant(&cs, a.rotate_right(N)).unwrap();
+            shifted_a_var.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        for _ in 0..10 {
+            let a: u32 = prng.gen();
+            check::<0>(a);
+            check::<1>(a);
+            check::<2>(a);
+            check::<3>(a);
+            check::<7>(a);
+            check::<13>(a);
+            check::<18>(a);
+            check::<31>(a);
+        }
+    }
+
+    #[test]
+    fn test_u32_add_matches_reference() {
+        assert_u32_op_matches(
+            U32_OP_CASES,
+            |table, a, b| a + (table, b),
+            u32::wrapping_add,
+        );
+    }
+
+    #[test]
+    fn test_u32_xor_matches_reference() {
+        assert_u32_op_matches(U32_OP_CASES, |table, a, b| a ^ (table, b), |a, b| a ^ b);
+    }
+
+    fn check_rotations_match_reference(cases: u32) {
+        assert_u32_unary_op_matches(
+            cases,
+            |_, a| a.rotate_right_shift_16(),
+            |a| a.rotate_right(16),
+        );
+        assert_u32_unary_op_matches(
+            cases,
+            |_, a| a.rotate_right_shift_12(),
+            |a| a.rotate_right(12),
+        );
+        assert_u32_unary_op_matches(
+            cases,
+            |_, a| a.rotate_right_shift_8(),
+            |a| a.rotate_right(8),
+        );
+        assert_u32_unary_op_matches(
+            cases,
+            |table, a| a.rotate_right_shift_7(table),
+            |a| a.rotate_right(7),
+        );
+        assert_u32_unary_op_matches(
+            cases,
+            |_, a| a.rotate_left_shift_16(),
+            |a| a.rotate_left(16),
+        );
+        assert_u32_unary_op_matches(
+            cases,
+            |_, a| a.rotate_left_shift_12(),
+            |a| a.rotate_left(12),
+        );
+        assert_u32_unary_op_matches(cases, |_, a| a.rotate_left_shift_8(), |a| a.rotate_left(8));
+        assert_u32_unary_op_matches(
+            cases,
+            |table, a| a.rotate_left_shift_7(table),
+            |a| a.rotate_left(7),
+        );
+        for n in [1, 2, 3, 5, 9, 30, 31] {
+            assert_u32_unary_op_matches(
+                cases,
+                |table, a| a.rotate_right(n, table),
+                |a| a.rotate_right(n),
+            );
+        }
+    }
+
+    #[test]
+    fn test_u32_rotations_match_reference() {
+        check_rotations_match_reference(U32_OP_CASES);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_u32_ops_match_reference_long() {
+        let cases = U32_OP_CASES_LONG;
+        assert_u32_op_matches(cases, |table, a, b| a + (table, b), u32::wrapping_add);
+        assert_u32_op_matches(cases, |table, a, b| a ^ (table, b), |a, b| a ^ b);
+        check_rotations_match_reference(cases);
+    }
+
+    #[test]
+    fn test_u32_be_bytes() {
+        let cs = ConstraintSystem::new_ref();
+
+        let a_var = U32Var::new_program_input(&cs, 0x01234567).unwrap();
+        let bytes = a_var.to_be_bytes().unwrap();
+        for byte in bytes.iter() {
+            cs.set_program_output(byte).unwrap();
+        }
+
+        test_program_without_opcat(
+            cs,
+            script! {
+                0x01 0x23 0x45 0x67
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_u32_be_bytes_round_trip() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..10 {
+            let cs = ConstraintSystem::new_ref();
+            let a: u32 = prng.gen();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let bytes = a_var.to_be_bytes().unwrap();
+            let a_recovered_var = U32Var::from_be_bytes(&bytes).unwrap();
+            assert_eq!(a_recovered_var.value().unwrap(), a);
+
+            a_var.equalverify(&a_recovered_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_from_be_bytes_malicious_nibbles() {
+        let cs = ConstraintSystem::new_ref();
+
+        // 0x67 = 6 * 16 + 7 = 5 * 16 + 23, where 23 is out of range
+        let groups = [(0, 1, 0x01), (2, 3, 0x23), (4, 5, 0x45), (5, 23, 0x67)];
+
+        let mut variables = vec![];
+        for &(hi, lo, byte) in groups.iter() {
+            variables.push(U4Var::new_hint(&cs, hi).unwrap().variable);
+            variables.push(U4Var::new_hint(&cs, lo).unwrap().variable);
+            variables.push(U8Var::new_program_input(&cs, byte).unwrap().variable);
+        }
+        cs.insert_script(u32_from_be_bytes_check, variables)
+            .unwrap();
+
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_u32_compact_add_sub() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut pairs = vec![
+            (0x7fffffff, 1),
+            (0x7fffffff, 0x7fffffff),
+            (0x80000000, 0x80000000),
+            (0x80000000, 0),
+            (0, 0x80000000),
+            (0xffffffff, 1),
+            (0xffffffff, 0x80000001),
+            (0, 0),
+            (0, 1),
+            (1, 0x7fffffff),
+        ];
+        for _ in 0..20 {
+            pairs.push((prng.gen(), prng.gen()));
+        }
+
+        for (a, b) in pairs {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U32CompactVar::new_program_input(&cs, a).unwrap();
+            let b_var = U32CompactVar::new_program_input(&cs, b).unwrap();
+
+            let sum_var = &a_var + &b_var;
+            let diff_var = &a_var - &b_var;
+            assert_eq!(sum_var.value, a.wrapping_add(b));
+            assert_eq!(diff_var.value, a.wrapping_sub(b));
+
+            // the compact representation is canonical, so this also checks the encoding
+            let expected_sum_var = U32CompactVar::new_constant(&cs, a.wrapping_add(b)).unwrap();
+            let expected_diff_var = U32CompactVar::new_constant(&cs, a.wrapping_sub(b)).unwrap();
+            sum_var.equalverify(&expected_sum_var).unwrap();
+            diff_var.equalverify(&expected_diff_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
     #[test]
     fn test_u32_compact_from_to_u32() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
@@ -539,6 +2321,165 @@ mod test {
         test_program_without_opcat(cs, script! {}).unwrap();
     }
 
+    #[test]
+    fn test_u32_compact_equalverify_corner() {
+        for a in [0u32, 0x7f, 0x80, 0xff, 0x80000000, 0x80000080, u32::MAX] {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U32Var::new_program_input(&cs, a).unwrap();
+            let a_compact_var = U32CompactVar::from(&a_var);
+            let expected_var = U32CompactVar::new_constant(&cs, a).unwrap();
+            a_compact_var.equalverify_numeric(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_compact_less_than() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut pairs = vec![
+            (0u32, 0x80000000u32),
+            (0x80000000, 0),
+            (0x7fffffff, 0x80000000),
+            (0x80000000, 0x80000001),
+            (0xffffffff, 0),
+            (0, 0),
+            (0x80000000, 0x80000000),
+        ];
+        for _ in 0..20 {
+            pairs.push((prng.gen(), prng.gen()));
+        }
+
+        for (a, b) in pairs {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U32CompactVar::new_program_input(&cs, a).unwrap();
+            let b_var = U32CompactVar::new_program_input(&cs, b).unwrap();
+
+            let res_var = a_var.less_than(&b_var);
+            assert_eq!(res_var.value, (a < b) as u32);
+            cs.set_program_output(&res_var).unwrap();
+
+            test_program_without_opcat(
+                cs,
+                script! {
+                    { (a < b) as u32 }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u32_compact_equalverify_negative_zero() {
+        // 0x80000000 is encoded as the negative zero 0x80, which must not equal 0
+        let cs = ConstraintSystem::new_ref();
+
+        let a_compact_var = U32CompactVar::new_program_input(&cs, 0x80000000).unwrap();
+        let zero_var = U32CompactVar::new_constant(&cs, 0).unwrap();
+        a_compact_var.equalverify_numeric(&zero_var).unwrap();
+
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_u32_compact_to_hash_input() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut values = vec![
+            0u32,
+            1,
+            0x7f,
+            0x80,
+            0xff,
+            0x100,
+            0x8000,
+            0x800000,
+            0x7fffffff,
+            0x80000000,
+            0x80000001,
+            0x80808080,
+            u32::MAX,
+        ];
+        for _ in 0..10 {
+            values.push(prng.gen());
+        }
+
+        for a in values {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_compact_var = U32CompactVar::new_program_input(&cs, a).unwrap();
+            let bytes_var = a_compact_var.to_hash_input().unwrap();
+            assert_eq!(bytes_var.value().unwrap(), a.to_le_bytes().to_vec());
+            cs.set_program_output(&bytes_var).unwrap();
+
+            test_program(
+                cs,
+                script! {
+                    { a.to_le_bytes().to_vec() }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    fn u32_compact_to_digits_test_case(
+        w: usize,
+        value: u32,
+        digits: Option<Vec<u8>>,
+    ) -> ConstraintSystemRef {
+        let cs = ConstraintSystem::new_ref();
+        let a_var = U32CompactVar::new_program_input(&cs, value).unwrap();
+
+        let digit_vars = match digits {
+            Some(digits) => a_var.to_digits_with_hints(w, &digits).unwrap(),
+            None => a_var.to_digits(w).unwrap(),
+        };
+
+        let mut recomposed = 0u32;
+        for (i, digit) in digit_vars.iter().enumerate() {
+            recomposed |= (digit.value().unwrap() as u32) << (i * w);
+        }
+        assert_eq!(recomposed, value);
+
+        cs
+    }
+
+    #[test]
+    fn test_u32_compact_to_digits() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for w in [4, 8] {
+            let mut values = vec![0, 1, 0x7fffffff, 0x80000000, 0x80000001, 0xffffffff];
+            for _ in 0..10 {
+                values.push(prng.gen());
+            }
+
+            for value in values {
+                let cs = u32_compact_to_digits_test_case(w, value, None);
+                test_program(cs, script! {}).unwrap();
+            }
+        }
+
+        assert!(
+            U32CompactVar::new_program_input(&ConstraintSystem::new_ref(), 0)
+                .unwrap()
+                .to_digits(3)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_u32_compact_to_digits_out_of_range() {
+        // the lowest digit is 16 too large and the next one is 1 too small, which recomposes
+        // the same value
+        let cs =
+            u32_compact_to_digits_test_case(4, 0x80000010, Some(vec![16, 0, 0, 0, 0, 0, 0, 8]));
+        expect_program_failure(cs, script! {}).unwrap();
+    }
+
     #[test]
     fn test_u32_compact_from_to_u32_corner() {
         let cs = ConstraintSystem::new_ref();
@@ -1,4 +1,6 @@
-use crate::compression::blake3::lookup_table::LookupTableVar;
+use crate::compression::blake3::lookup_table::{table_depth, LookupTableVar};
+use crate::limbs::{TryAdd, TrySub};
+use crate::watchdog::{track_alloc, WatchdogExt};
 use anyhow::{Error, Result};
 use bitcoin::opcodes::Ordinary::OP_ADD;
 use bitcoin_circle_stark::treepp::*;
@@ -6,12 +8,13 @@ use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
 use bitcoin_script_dsl::constraint_system::{ConstraintSystemRef, Element};
 use bitcoin_script_dsl::options::Options;
 use bitcoin_script_dsl::stack::Stack;
-use std::ops::{Add, BitXor};
+use std::ops::{Add, BitAnd, BitOr, BitXor, Not, Sub};
 
 #[derive(Debug, Clone)]
 pub struct U4Var {
     pub variable: usize,
     pub value: u32,
+    pub mode: AllocationMode,
     pub cs: ConstraintSystemRef,
 }
 
@@ -43,34 +46,37 @@ impl BitXor<(&LookupTableVar, &U4Var)> for &U4Var {
     type Output = U4Var;
 
     fn bitxor(self, rhs: (&LookupTableVar, &U4Var)) -> Self::Output {
-        let table = rhs.0;
-        let rhs = rhs.1;
+        self.try_xor(rhs.0, rhs.1).unwrap()
+    }
+}
 
+impl U4Var {
+    /// Compute the XOR, which returns an error instead of panicking if the table does not
+    /// include the XOR table.
+    pub fn try_xor(&self, table: &LookupTableVar, rhs: &U4Var) -> Result<U4Var> {
         let res = self.value ^ rhs.value;
         let cs = self.cs().and(&table.cs()).and(&rhs.cs());
 
         let options = Options::new()
-            .with_u32("xor_table_ref", table.xor_table_var.variables[0] as u32)
-            .with_u32("row_table_ref", table.row_table.variables[0] as u32);
-        cs.insert_script_complex(
+            .with_u32("xor_table_ref", table.xor_table_ref()?)
+            .with_u32("row_table_ref", table.row_table_ref()?);
+        cs.insert_gadget_complex(
+            "u4var_xor",
             u4var_xor,
             self.variables()
                 .iter()
                 .chain(rhs.variables().iter())
                 .copied(),
             &options,
-        )
-        .unwrap();
-        U4Var::new_function_output(&cs, res).unwrap()
+        )?;
+        U4Var::new_function_output(&cs, res)
     }
 }
 
 fn u4var_xor(stack: &mut Stack, options: &Options) -> Result<Script> {
-    let last_xor_table_elem = options.get_u32("xor_table_ref")?;
-    let k_xor = stack.get_relative_position(last_xor_table_elem as usize)? - 255;
+    let k_xor = table_depth(stack, options, "xor_table_ref")?;
 
-    let last_row_table_elem = options.get_u32("row_table_ref")?;
-    let k_row = stack.get_relative_position(last_row_table_elem as usize)? - 15;
+    let k_row = table_depth(stack, options, "row_table_ref")?;
 
     Ok(script! {
         { k_row + 1 } OP_ADD OP_PICK OP_ADD
@@ -78,6 +84,116 @@ fn u4var_xor(stack: &mut Stack, options: &Options) -> Result<Script> {
     })
 }
 
+pub(crate) fn u4_range_verify() -> Script {
+    script! {
+        0 16 OP_WITHIN OP_VERIFY
+    }
+}
+
+impl BitAnd<(&LookupTableVar, &U4Var)> for &U4Var {
+    type Output = U4Var;
+
+    fn bitand(self, rhs: (&LookupTableVar, &U4Var)) -> Self::Output {
+        self.try_and(rhs.0, rhs.1).unwrap()
+    }
+}
+
+impl BitOr<(&LookupTableVar, &U4Var)> for &U4Var {
+    type Output = U4Var;
+
+    fn bitor(self, rhs: (&LookupTableVar, &U4Var)) -> Self::Output {
+        self.try_or(rhs.0, rhs.1).unwrap()
+    }
+}
+
+impl Not for &U4Var {
+    type Output = U4Var;
+
+    fn not(self) -> Self::Output {
+        self.try_not().unwrap()
+    }
+}
+
+impl U4Var {
+    /// Compute 15 - self, which returns an error instead of panicking if the watchdog rejects
+    /// the gadget.
+    pub fn try_not(&self) -> Result<U4Var> {
+        let cs = self.cs();
+        cs.insert_gadget("u4_not", u4_not, [self.variable])?;
+        U4Var::new_function_output(&cs, 15 - self.value)
+    }
+
+    /// Compute the AND, which returns an error instead of panicking if the table does not
+    /// include the XOR table.
+    pub fn try_and(&self, table: &LookupTableVar, rhs: &U4Var) -> Result<U4Var> {
+        self.and_or_from_xor(table, rhs, false)
+    }
+
+    /// Compute the OR, which returns an error instead of panicking if the table does not
+    /// include the XOR table.
+    pub fn try_or(&self, table: &LookupTableVar, rhs: &U4Var) -> Result<U4Var> {
+        self.and_or_from_xor(table, rhs, true)
+    }
+}
+
+fn u4_not() -> Script {
+    script! {
+        15 OP_SWAP OP_SUB
+    }
+}
+
+impl U4Var {
+    /// Compute AND or OR from the XOR table, using a + b = (a ^ b) + 2 (a & b) and
+    /// a | b = (a ^ b) + (a & b). The result is a hint, which the script checks by doubling it.
+    fn and_or_from_xor(&self, table: &LookupTableVar, rhs: &U4Var, is_or: bool) -> Result<U4Var> {
+        let res = if is_or {
+            self.value | rhs.value
+        } else {
+            self.value & rhs.value
+        };
+        let cs = self.cs().and(&table.cs()).and(&rhs.cs());
+
+        let res_var = U4Var::new_hint(&cs, res)?;
+
+        let options = Options::new()
+            .with_u32("xor_table_ref", table.xor_table_ref()?)
+            .with_u32("row_table_ref", table.row_table_ref()?)
+            .with_u32("is_or", is_or as u32);
+        cs.insert_gadget_complex(
+            "u4var_check_and_or",
+            u4var_check_and_or,
+            [self.variable, rhs.variable, res_var.variable],
+            &options,
+        )?;
+        Ok(res_var)
+    }
+}
+
+fn u4var_check_and_or(stack: &mut Stack, options: &Options) -> Result<Script> {
+    let k_xor = table_depth(stack, options, "xor_table_ref")?;
+
+    let k_row = table_depth(stack, options, "row_table_ref")?;
+
+    let is_or = options.get_u32("is_or")? == 1;
+
+    Ok(script! {
+        // move the doubled result and a + b to the altstack
+        OP_DUP OP_ADD OP_TOALTSTACK
+        OP_2DUP OP_ADD OP_TOALTSTACK
+
+        { k_row + 1 } OP_ADD OP_PICK OP_ADD
+        { k_xor } OP_ADD OP_PICK
+
+        OP_FROMALTSTACK
+        if is_or {
+            OP_ADD
+        } else {
+            OP_SWAP OP_SUB
+        }
+        OP_FROMALTSTACK OP_EQUALVERIFY
+    })
+}
+
 impl AllocVar for U4Var {
     fn new_variable(
         cs: &ConstraintSystemRef,
@@ -85,23 +201,35 @@ impl AllocVar for U4Var {
         mode: AllocationMode,
     ) -> Result<Self> {
         let variable = cs.alloc(Element::Num(data as i32), mode)?;
-        Ok(Self {
+        let res = Self {
             variable,
             value: data,
+            mode,
             cs: cs.clone(),
-        })
+        };
+        // the prover chooses program inputs and hints, so their bound must be enforced in script
+        if matches!(mode, AllocationMode::ProgramInput | AllocationMode::Hint) {
+            res.range_verify()?;
+        }
+        if mode != AllocationMode::Constant {
+            track_alloc(cs, variable);
+        }
+        Ok(res)
     }
 }
 
-pub struct CarryVar(U4Var);
+/// The carry of a limb-wise addition, which is the quotient of the sum by 16 looked up from the
+/// table. It is 0 or 1 for two limbs, up to 2 for three limbs and a carry, and at most
+/// `LookupTableVar::max_sum() / 16` in general.
+pub struct CarryVar(pub U4Var);
 
 #[derive(Default, Copy, Clone)]
 pub struct NoCarry();
 
-impl Add<(&LookupTableVar, &U4Var)> for &U4Var {
+impl TryAdd<(&LookupTableVar, &U4Var)> for &U4Var {
     type Output = (U4Var, CarryVar);
 
-    fn add(self, rhs: (&LookupTableVar, &U4Var)) -> Self::Output {
+    fn try_add(self, rhs: (&LookupTableVar, &U4Var)) -> Result<Self::Output> {
         let table = rhs.0;
         let rhs = rhs.1;
         let cs = self.cs().and(&rhs.cs()).and(&table.cs());
@@ -109,59 +237,65 @@ impl Add<(&LookupTableVar, &U4Var)> for &U4Var {
         let quotient = (self.value + rhs.value) / 16;
         let remainder = (self.value + rhs.value) % 16;
 
-        cs.insert_script_complex(
+        cs.insert_gadget_complex(
+            "u4_add_and_reduce",
             u4_add_and_reduce,
             [self.variable, rhs.variable],
             &Options::new()
-                .with_u32(
-                    "quotient_table_ref",
-                    table.quotient_table_var.variables[0] as u32,
-                )
-                .with_u32(
-                    "remainder_table_ref",
-                    table.remainder_table_var.variables[0] as u32,
-                )
+                .with_u32("quotient_table_ref", table.quotient_table_ref()?)
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
                 .with_u32("num_additions", 1),
-        )
-        .unwrap();
+        )?;
 
-        let remainder_var = U4Var::new_function_output(&cs, remainder).unwrap();
-        let quotient_var = CarryVar(U4Var::new_function_output(&cs, quotient).unwrap());
+        let remainder_var = U4Var::new_function_output(&cs, remainder)?;
+        let quotient_var = CarryVar(U4Var::new_function_output(&cs, quotient)?);
 
-        (remainder_var, quotient_var)
+        Ok((remainder_var, quotient_var))
     }
 }
 
-impl Add<(&LookupTableVar, &U4Var, NoCarry)> for &U4Var {
+impl Add<(&LookupTableVar, &U4Var)> for &U4Var {
+    type Output = (U4Var, CarryVar);
+
+    fn add(self, rhs: (&LookupTableVar, &U4Var)) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+impl TryAdd<(&LookupTableVar, &U4Var, NoCarry)> for &U4Var {
     type Output = U4Var;
 
-    fn add(self, rhs: (&LookupTableVar, &U4Var, NoCarry)) -> Self::Output {
+    fn try_add(self, rhs: (&LookupTableVar, &U4Var, NoCarry)) -> Result<Self::Output> {
         let table = rhs.0;
         let rhs = rhs.1;
         let cs = self.cs().and(&rhs.cs()).and(&table.cs());
         let remainder = (self.value + rhs.value) % 16;
 
-        cs.insert_script_complex(
+        cs.insert_gadget_complex(
+            "u4_add_and_reduce_nocarry",
             u4_add_and_reduce_nocarry,
             [self.variable, rhs.variable],
             &Options::new()
-                .with_u32(
-                    "remainder_table_ref",
-                    table.remainder_table_var.variables[0] as u32,
-                )
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
                 .with_u32("num_additions", 1),
-        )
-        .unwrap();
+        )?;
 
-        let remainder_var = U4Var::new_function_output(&cs, remainder).unwrap();
-        remainder_var
+        U4Var::new_function_output(&cs, remainder)
     }
 }
 
-impl Add<(&LookupTableVar, &U4Var, &CarryVar)> for &U4Var {
+impl Add<(&LookupTableVar, &U4Var, NoCarry)> for &U4Var {
+    type Output = U4Var;
+
+    fn add(self, rhs: (&LookupTableVar, &U4Var, NoCarry)) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+impl TryAdd<(&LookupTableVar, &U4Var, &CarryVar)> for &U4Var {
     type Output = (U4Var, CarryVar);
 
-    fn add(self, rhs: (&LookupTableVar, &U4Var, &CarryVar)) -> Self::Output {
+    fn try_add(self, rhs: (&LookupTableVar, &U4Var, &CarryVar)) -> Result<Self::Output> {
         let table = rhs.0;
         let carry = rhs.2;
         let rhs = rhs.1;
@@ -170,33 +304,35 @@ impl Add<(&LookupTableVar, &U4Var, &CarryVar)> for &U4Var {
         let quotient = (self.value + rhs.value + carry.0.value) / 16;
         let remainder = (self.value + rhs.value + carry.0.value) % 16;
 
-        cs.insert_script_complex(
+        cs.insert_gadget_complex(
+            "u4_add_and_reduce",
             u4_add_and_reduce,
             [self.variable, rhs.variable, carry.0.variable],
             &Options::new()
-                .with_u32(
-                    "quotient_table_ref",
-                    table.quotient_table_var.variables[0] as u32,
-                )
-                .with_u32(
-                    "remainder_table_ref",
-                    table.remainder_table_var.variables[0] as u32,
-                )
+                .with_u32("quotient_table_ref", table.quotient_table_ref()?)
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
                 .with_u32("num_additions", 2),
-        )
-        .unwrap();
+        )?;
 
-        let remainder_var = U4Var::new_function_output(&cs, remainder).unwrap();
-        let quotient_var = CarryVar(U4Var::new_function_output(&cs, quotient).unwrap());
+        let remainder_var = U4Var::new_function_output(&cs, remainder)?;
+        let quotient_var = CarryVar(U4Var::new_function_output(&cs, quotient)?);
 
-        (remainder_var, quotient_var)
+        Ok((remainder_var, quotient_var))
     }
 }
 
-impl Add<(&LookupTableVar, &U4Var, &CarryVar, NoCarry)> for &U4Var {
+impl Add<(&LookupTableVar, &U4Var, &CarryVar)> for &U4Var {
+    type Output = (U4Var, CarryVar);
+
+    fn add(self, rhs: (&LookupTableVar, &U4Var, &CarryVar)) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+impl TryAdd<(&LookupTableVar, &U4Var, &CarryVar, NoCarry)> for &U4Var {
     type Output = U4Var;
 
-    fn add(self, rhs: (&LookupTableVar, &U4Var, &CarryVar, NoCarry)) -> Self::Output {
+    fn try_add(self, rhs: (&LookupTableVar, &U4Var, &CarryVar, NoCarry)) -> Result<Self::Output> {
         let table = rhs.0;
         let carry = rhs.2;
         let rhs = rhs.1;
@@ -204,28 +340,31 @@ impl Add<(&LookupTableVar, &U4Var, &CarryVar, NoCarry)> for &U4Var {
 
         let remainder = (self.value + rhs.value + carry.0.value) % 16;
 
-        cs.insert_script_complex(
+        cs.insert_gadget_complex(
+            "u4_add_and_reduce_nocarry",
             u4_add_and_reduce_nocarry,
             [self.variable, rhs.variable, carry.0.variable],
             &Options::new()
-                .with_u32(
-                    "remainder_table_ref",
-                    table.remainder_table_var.variables[0] as u32,
-                )
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
                 .with_u32("num_additions", 2),
-        )
-        .unwrap();
+        )?;
+
+        U4Var::new_function_output(&cs, remainder)
+    }
+}
 
-        let remainder_var = U4Var::new_function_output(&cs, remainder).unwrap();
+impl Add<(&LookupTableVar, &U4Var, &CarryVar, NoCarry)> for &U4Var {
+    type Output = U4Var;
 
-        remainder_var
+    fn add(self, rhs: (&LookupTableVar, &U4Var, &CarryVar, NoCarry)) -> Self::Output {
+        self.try_add(rhs).unwrap()
     }
 }
 
-impl Add<(&LookupTableVar, &U4Var, &U4Var)> for &U4Var {
+impl TryAdd<(&LookupTableVar, &U4Var, &U4Var)> for &U4Var {
     type Output = (U4Var, CarryVar);
 
-    fn add(self, rhs: (&LookupTableVar, &U4Var, &U4Var)) -> Self::Output {
+    fn try_add(self, rhs: (&LookupTableVar, &U4Var, &U4Var)) -> Result<Self::Output> {
         let table = rhs.0;
         let rhs_1 = rhs.1;
         let rhs_2 = rhs.2;
@@ -234,33 +373,35 @@ impl Add<(&LookupTableVar, &U4Var, &U4Var)> for &U4Var {
         let quotient = (self.value + rhs_1.value + rhs_2.value) / 16;
         let remainder = (self.value + rhs_1.value + rhs_2.value) % 16;
 
-        cs.insert_script_complex(
+        cs.insert_gadget_complex(
+            "u4_add_and_reduce",
             u4_add_and_reduce,
             [self.variable, rhs_1.variable, rhs_2.variable],
             &Options::new()
-                .with_u32(
-                    "quotient_table_ref",
-                    table.quotient_table_var.variables[0] as u32,
-                )
-                .with_u32(
-                    "remainder_table_ref",
-                    table.remainder_table_var.variables[0] as u32,
-                )
+                .with_u32("quotient_table_ref", table.quotient_table_ref()?)
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
                 .with_u32("num_additions", 2),
-        )
-        .unwrap();
+        )?;
 
-        let remainder_var = U4Var::new_function_output(&cs, remainder).unwrap();
-        let quotient_var = CarryVar(U4Var::new_function_output(&cs, quotient).unwrap());
+        let remainder_var = U4Var::new_function_output(&cs, remainder)?;
+        let quotient_var = CarryVar(U4Var::new_function_output(&cs, quotient)?);
 
-        (remainder_var, quotient_var)
+        Ok((remainder_var, quotient_var))
     }
 }
 
-impl Add<(&LookupTableVar, &U4Var, &U4Var, &CarryVar)> for &U4Var {
+impl Add<(&LookupTableVar, &U4Var, &U4Var)> for &U4Var {
     type Output = (U4Var, CarryVar);
 
-    fn add(self, rhs: (&LookupTableVar, &U4Var, &U4Var, &CarryVar)) -> Self::Output {
+    fn add(self, rhs: (&LookupTableVar, &U4Var, &U4Var)) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+impl TryAdd<(&LookupTableVar, &U4Var, &U4Var, &CarryVar)> for &U4Var {
+    type Output = (U4Var, CarryVar);
+
+    fn try_add(self, rhs: (&LookupTableVar, &U4Var, &U4Var, &CarryVar)) -> Result<Self::Output> {
         let table = rhs.0;
         let carry = rhs.3;
         let rhs_1 = rhs.1;
@@ -275,7 +416,8 @@ impl Add<(&LookupTableVar, &U4Var, &U4Var, &CarryVar)> for &U4Var {
         let quotient = (self.value + rhs_1.value + rhs_2.value + carry.0.value) / 16;
         let remainder = (self.value + rhs_1.value + rhs_2.value + carry.0.value) % 16;
 
-        cs.insert_script_complex(
+        cs.insert_gadget_complex(
+            "u4_add_and_reduce",
             u4_add_and_reduce,
             [
                 self.variable,
@@ -284,29 +426,33 @@ impl Add<(&LookupTableVar, &U4Var, &U4Var, &CarryVar)> for &U4Var {
                 carry.0.variable,
             ],
             &Options::new()
-                .with_u32(
-                    "quotient_table_ref",
-                    table.quotient_table_var.variables[0] as u32,
-                )
-                .with_u32(
-                    "remainder_table_ref",
-                    table.remainder_table_var.variables[0] as u32,
-                )
+                .with_u32("quotient_table_ref", table.quotient_table_ref()?)
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
                 .with_u32("num_additions", 3),
-        )
-        .unwrap();
+        )?;
 
-        let remainder_var = U4Var::new_function_output(&cs, remainder).unwrap();
-        let quotient_var = CarryVar(U4Var::new_function_output(&cs, quotient).unwrap());
+        let remainder_var = U4Var::new_function_output(&cs, remainder)?;
+        let quotient_var = CarryVar(U4Var::new_function_output(&cs, quotient)?);
 
-        (remainder_var, quotient_var)
+        Ok((remainder_var, quotient_var))
     }
 }
 
-impl Add<(&LookupTableVar, &U4Var, &U4Var, &CarryVar, NoCarry)> for &U4Var {
+impl Add<(&LookupTableVar, &U4Var, &U4Var, &CarryVar)> for &U4Var {
+    type Output = (U4Var, CarryVar);
+
+    fn add(self, rhs: (&LookupTableVar, &U4Var, &U4Var, &CarryVar)) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+impl TryAdd<(&LookupTableVar, &U4Var, &U4Var, &CarryVar, NoCarry)> for &U4Var {
     type Output = U4Var;
 
-    fn add(self, rhs: (&LookupTableVar, &U4Var, &U4Var, &CarryVar, NoCarry)) -> Self::Output {
+    fn try_add(
+        self,
+        rhs: (&LookupTableVar, &U4Var, &U4Var, &CarryVar, NoCarry),
+    ) -> Result<Self::Output> {
         let table = rhs.0;
         let carry = rhs.3;
         let rhs_1 = rhs.1;
@@ -320,7 +466,8 @@ impl Add<(&LookupTableVar, &U4Var, &U4Var, &CarryVar, NoCarry)> for &U4Var {
 
         let remainder = (self.value + rhs_1.value + rhs_2.value + carry.0.value) % 16;
 
-        cs.insert_script_complex(
+        cs.insert_gadget_complex(
+            "u4_add_and_reduce_nocarry",
             u4_add_and_reduce_nocarry,
             [
                 self.variable,
@@ -329,26 +476,180 @@ impl Add<(&LookupTableVar, &U4Var, &U4Var, &CarryVar, NoCarry)> for &U4Var {
                 carry.0.variable,
             ],
             &Options::new()
-                .with_u32(
-                    "remainder_table_ref",
-                    table.remainder_table_var.variables[0] as u32,
-                )
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
                 .with_u32("num_additions", 3),
-        )
-        .unwrap();
+        )?;
+
+        U4Var::new_function_output(&cs, remainder)
+    }
+}
+
+impl Add<(&LookupTableVar, &U4Var, &U4Var, &CarryVar, NoCarry)> for &U4Var {
+    type Output = U4Var;
+
+    fn add(self, rhs: (&LookupTableVar, &U4Var, &U4Var, &CarryVar, NoCarry)) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+/// The borrow of a limb-wise subtraction, stored as the quotient of `16 + a - b - borrow`,
+/// which is 1 if there is no borrow and 0 otherwise.
+pub struct BorrowVar(U4Var);
 
-        let remainder_var = U4Var::new_function_output(&cs, remainder).unwrap();
+impl TrySub<(&LookupTableVar, &U4Var)> for &U4Var {
+    type Output = (U4Var, BorrowVar);
 
-        remainder_var
+    fn try_sub(self, rhs: (&LookupTableVar, &U4Var)) -> Result<Self::Output> {
+        let table = rhs.0;
+        let rhs = rhs.1;
+        let cs = self.cs().and(&rhs.cs()).and(&table.cs());
+
+        let quotient = (16 + self.value - rhs.value) / 16;
+        let remainder = (16 + self.value - rhs.value) % 16;
+
+        cs.insert_gadget_complex(
+            "u4_sub_and_reduce",
+            u4_sub_and_reduce,
+            [self.variable, rhs.variable],
+            &Options::new()
+                .with_u32("quotient_table_ref", table.quotient_table_ref()?)
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
+                .with_u32("has_borrow", 0),
+        )?;
+
+        let remainder_var = U4Var::new_function_output(&cs, remainder)?;
+        let quotient_var = BorrowVar(U4Var::new_function_output(&cs, quotient)?);
+
+        Ok((remainder_var, quotient_var))
+    }
+}
+
+impl Sub<(&LookupTableVar, &U4Var)> for &U4Var {
+    type Output = (U4Var, BorrowVar);
+
+    fn sub(self, rhs: (&LookupTableVar, &U4Var)) -> Self::Output {
+        self.try_sub(rhs).unwrap()
+    }
+}
+
+impl TrySub<(&LookupTableVar, &U4Var, &BorrowVar)> for &U4Var {
+    type Output = (U4Var, BorrowVar);
+
+    fn try_sub(self, rhs: (&LookupTableVar, &U4Var, &BorrowVar)) -> Result<Self::Output> {
+        let table = rhs.0;
+        let borrow = rhs.2;
+        let rhs = rhs.1;
+        let cs = self
+            .cs()
+            .and(&rhs.cs())
+            .and(&table.cs())
+            .and(&borrow.0.cs());
+
+        let quotient = (15 + self.value + borrow.0.value - rhs.value) / 16;
+        let remainder = (15 + self.value + borrow.0.value - rhs.value) % 16;
+
+        cs.insert_gadget_complex(
+            "u4_sub_and_reduce",
+            u4_sub_and_reduce,
+            [self.variable, rhs.variable, borrow.0.variable],
+            &Options::new()
+                .with_u32("quotient_table_ref", table.quotient_table_ref()?)
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
+                .with_u32("has_borrow", 1),
+        )?;
+
+        let remainder_var = U4Var::new_function_output(&cs, remainder)?;
+        let quotient_var = BorrowVar(U4Var::new_function_output(&cs, quotient)?);
+
+        Ok((remainder_var, quotient_var))
+    }
+}
+
+impl Sub<(&LookupTableVar, &U4Var, &BorrowVar)> for &U4Var {
+    type Output = (U4Var, BorrowVar);
+
+    fn sub(self, rhs: (&LookupTableVar, &U4Var, &BorrowVar)) -> Self::Output {
+        self.try_sub(rhs).unwrap()
     }
 }
 
+impl TrySub<(&LookupTableVar, &U4Var, &BorrowVar, NoCarry)> for &U4Var {
+    type Output = U4Var;
+
+    fn try_sub(self, rhs: (&LookupTableVar, &U4Var, &BorrowVar, NoCarry)) -> Result<Self::Output> {
+        let table = rhs.0;
+        let borrow = rhs.2;
+        let rhs = rhs.1;
+        let cs = self
+            .cs()
+            .and(&rhs.cs())
+            .and(&table.cs())
+            .and(&borrow.0.cs());
+
+        let remainder = (15 + self.value + borrow.0.value - rhs.value) % 16;
+
+        cs.insert_gadget_complex(
+            "u4_sub_and_reduce_noborrow",
+            u4_sub_and_reduce_noborrow,
+            [self.variable, rhs.variable, borrow.0.variable],
+            &Options::new()
+                .with_u32("remainder_table_ref", table.remainder_table_ref()?)
+                .with_u32("has_borrow", 1),
+        )?;
+
+        U4Var::new_function_output(&cs, remainder)
+    }
+}
+
+impl Sub<(&LookupTableVar, &U4Var, &BorrowVar, NoCarry)> for &U4Var {
+    type Output = U4Var;
+
+    fn sub(self, rhs: (&LookupTableVar, &U4Var, &BorrowVar, NoCarry)) -> Self::Output {
+        self.try_sub(rhs).unwrap()
+    }
+}
+
+/// Compute `16 + a - b` if there is no incoming borrow, or `15 + a - b + quotient` otherwise.
+fn u4_sub_with_offset(has_borrow: bool) -> Script {
+    script! {
+        if has_borrow {
+            OP_SUB OP_SUB 15
+        } else {
+            OP_SUB 16
+        }
+        OP_ADD
+    }
+}
+
+fn u4_sub_and_reduce(stack: &mut Stack, options: &Options) -> Result<Script> {
+    let k_quotient = table_depth(stack, options, "quotient_table_ref")?;
+
+    let k_remainder = table_depth(stack, options, "remainder_table_ref")?;
+
+    let has_borrow = options.get_u32("has_borrow")? == 1;
+    Ok(script! {
+        { u4_sub_with_offset(has_borrow) }
+        OP_DUP
+        { k_remainder + 1 } OP_ADD OP_PICK
+        OP_SWAP
+        { k_quotient + 1 } OP_ADD OP_PICK
+    })
+}
+
+fn u4_sub_and_reduce_noborrow(stack: &mut Stack, options: &Options) -> Result<Script> {
+    let k_remainder = table_depth(stack, options, "remainder_table_ref")?;
+
+    let has_borrow = options.get_u32("has_borrow")? == 1;
+    Ok(script! {
+        { u4_sub_with_offset(has_borrow) }
+        { k_remainder } OP_ADD OP_PICK
+    })
+}
+
 fn u4_add_and_reduce(stack: &mut Stack, options: &Options) -> Result<Script> {
-    let last_quotient_table_elem = options.get_u32("quotient_table_ref")?;
-    let k_quotient = stack.get_relative_position(last_quotient_table_elem as usize)? - 47;
+    let k_quotient = table_depth(stack, options, "quotient_table_ref")?;
 
-    let last_remainder_table_elem = options.get_u32("remainder_table_ref")?;
-    let k_remainder = stack.get_relative_position(last_remainder_table_elem as usize)? - 47;
+    let k_remainder = table_depth(stack, options, "remainder_table_ref")?;
 
     let num_additions = options.get_u32("num_additions")? as usize;
     Ok(script! {
@@ -363,8 +664,7 @@ fn u4_add_and_reduce(stack: &mut Stack, options: &Options) -> Result<Script> {
 }
 
 fn u4_add_and_reduce_nocarry(stack: &mut Stack, options: &Options) -> Result<Script> {
-    let last_remainder_table_elem = options.get_u32("remainder_table_ref")?;
-    let k_remainder = stack.get_relative_position(last_remainder_table_elem as usize)? - 47;
+    let k_remainder = table_depth(stack, options, "remainder_table_ref")?;
     let num_additions = options.get_u32("num_additions")? as usize;
     Ok(script! {
         for _ in 0..num_additions {
@@ -375,7 +675,81 @@ fn u4_add_and_reduce_nocarry(stack: &mut Stack, options: &Options) -> Result<Scr
 }
 
 impl U4Var {
+    /// Add the limbs and an optional incoming carry with a single lookup, where the sum must be
+    /// covered by the quotient and remainder tables. The carry out is only looked up if
+    /// `carry_out` is set.
+    pub fn sum_with_carry(
+        table: &LookupTableVar,
+        limbs: &[&U4Var],
+        carry: Option<&CarryVar>,
+        carry_out: bool,
+    ) -> (U4Var, Option<CarryVar>) {
+        Self::try_sum_with_carry(table, limbs, carry, carry_out).unwrap()
+    }
+
+    pub fn try_sum_with_carry(
+        table: &LookupTableVar,
+        limbs: &[&U4Var],
+        carry: Option<&CarryVar>,
+        carry_out: bool,
+    ) -> Result<(U4Var, Option<CarryVar>)> {
+        let inputs = limbs
+            .iter()
+            .copied()
+            .chain(carry.map(|carry| &carry.0))
+            .collect::<Vec<_>>();
+        let sum = inputs.iter().map(|limb| limb.value).sum::<u32>();
+        assert!(sum <= table.max_sum()?);
+
+        let mut cs = table.cs();
+        for limb in inputs.iter() {
+            cs = cs.and(&limb.cs());
+        }
+
+        let options = Options::new()
+            .with_u32("remainder_table_ref", table.remainder_table_ref()?)
+            .with_u32("num_additions", inputs.len() as u32 - 1);
+        if carry_out {
+            cs.insert_gadget_complex(
+                "u4_add_and_reduce",
+                u4_add_and_reduce,
+                inputs.iter().map(|limb| limb.variable),
+                &options.with_u32("quotient_table_ref", table.quotient_table_ref()?),
+            )?;
+        } else {
+            cs.insert_gadget_complex(
+                "u4_add_and_reduce_nocarry",
+                u4_add_and_reduce_nocarry,
+                inputs.iter().map(|limb| limb.variable),
+                &options,
+            )?;
+        }
+
+        let remainder_var = U4Var::new_function_output(&cs, sum % 16)?;
+        let quotient_var = if carry_out {
+            Some(CarryVar(U4Var::new_function_output(&cs, sum / 16)?))
+        } else {
+            None
+        };
+        Ok((remainder_var, quotient_var))
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.mode == AllocationMode::Constant
+    }
+
+    /// Verify in script that the value is in 0..=15, which program inputs and hints already do
+    /// when allocated. The check is a comparison, so no table is needed.
+    pub fn range_verify(&self) -> Result<()> {
+        self.cs()
+            .insert_gadget("u4_range_verify", u4_range_verify, [self.variable])
+    }
+
     pub fn add_no_overflow(&self, rhs: &Self) -> Self {
+        self.try_add_no_overflow(rhs).unwrap()
+    }
+
+    pub fn try_add_no_overflow(&self, rhs: &Self) -> Result<Self> {
         let self_value = self.value;
         let rhs_value = rhs.value;
 
@@ -383,43 +757,106 @@ impl U4Var {
         assert!(res_value < 16);
 
         let cs = self.cs().and(&rhs.cs());
-        cs.insert_script(u4_add_no_overflow, [self.variable, rhs.variable])
-            .unwrap();
-        U4Var::new_function_output(&cs, res_value).unwrap()
+        cs.insert_gadget(
+            "u4_add_no_overflow",
+            u4_add_no_overflow,
+            [self.variable, rhs.variable],
+        )?;
+        U4Var::new_function_output(&cs, res_value)
     }
 
     pub fn get_shl1(&self, table: &LookupTableVar) -> Self {
+        self.try_get_shl1(table).unwrap()
+    }
+
+    pub fn try_get_shl1(&self, table: &LookupTableVar) -> Result<Self> {
         let res_value = (self.value << 1) & 15;
         let cs = self.cs().and(&table.cs());
-        cs.insert_script_complex(
+        cs.insert_gadget_complex(
+            "u4_get_shl1",
             u4_get_shl1,
             [self.variable],
-            &Options::new().with_u32("shl1_table_ref", table.shl1table_var.variables[0] as u32),
-        )
-        .unwrap();
-        U4Var::new_function_output(&cs, res_value).unwrap()
+            &Options::new().with_u32("shl1_table_ref", table.shl_table_ref(1)?),
+        )?;
+        U4Var::new_function_output(&cs, res_value)
     }
 
     pub fn get_shr3(&self, table: &LookupTableVar) -> Self {
+        self.try_get_shr3(table).unwrap()
+    }
+
+    pub fn try_get_shr3(&self, table: &LookupTableVar) -> Result<Self> {
         let res_value = self.value >> 3;
         let cs = self.cs().and(&table.cs());
-        cs.insert_script_complex(
+        cs.insert_gadget_complex(
+            "u4_get_shr3",
             u4_get_shr3,
             [self.variable],
-            &Options::new().with_u32("shr3_table_ref", table.shr3table_var.variables[0] as u32),
-        )
-        .unwrap();
-        U4Var::new_function_output(&cs, res_value).unwrap()
+            &Options::new().with_u32("shr3_table_ref", table.shr_table_ref(3)?),
+        )?;
+        U4Var::new_function_output(&cs, res_value)
     }
 }
 
+impl U4Var {
+    /// Shift right by `bits` in 1..=3, using the corresponding lookup table.
+    pub fn get_shr(&self, bits: usize, table: &LookupTableVar) -> Self {
+        self.try_get_shr(bits, table).unwrap()
+    }
+
+    pub fn try_get_shr(&self, bits: usize, table: &LookupTableVar) -> Result<Self> {
+        if bits == 3 {
+            return self.try_get_shr3(table);
+        }
+        let table_ref = table.shr_table_ref(bits)?;
+        self.lookup_16_entry_table(table, table_ref, self.value >> bits)
+    }
+
+    /// Shift left by `bits` in 1..=3 and keep the lower four bits, using the corresponding
+    /// lookup table.
+    pub fn get_shl(&self, bits: usize, table: &LookupTableVar) -> Self {
+        self.try_get_shl(bits, table).unwrap()
+    }
+
+    pub fn try_get_shl(&self, bits: usize, table: &LookupTableVar) -> Result<Self> {
+        if bits == 1 {
+            return self.try_get_shl1(table);
+        }
+        let table_ref = table.shl_table_ref(bits)?;
+        self.lookup_16_entry_table(table, table_ref, (self.value << bits) & 15)
+    }
+
+    fn lookup_16_entry_table(
+        &self,
+        table: &LookupTableVar,
+        table_ref: u32,
+        res_value: u32,
+    ) -> Result<Self> {
+        let cs = self.cs().and(&table.cs());
+        cs.insert_gadget_complex(
+            "u4_lookup_16_entry_table",
+            u4_lookup_16_entry_table,
+            [self.variable],
+            &Options::new().with_u32("table_ref", table_ref),
+        )?;
+        U4Var::new_function_output(&cs, res_value)
+    }
+}
+
+fn u4_lookup_16_entry_table(stack: &mut Stack, options: &Options) -> Result<Script> {
+    let k = table_depth(stack, options, "table_ref")?;
+
+    Ok(script! {
+        { k } OP_ADD OP_PICK
+    })
+}
+
 fn u4_add_no_overflow() -> Script {
     Script::from(vec![OP_ADD.to_u8()])
 }
 
 fn u4_get_shl1(stack: &mut Stack, options: &Options) -> Result<Script> {
-    let last_shl1_table_elem = options.get_u32("shl1_table_ref")?;
-    let k_shl1 = stack.get_relative_position(last_shl1_table_elem as usize)? - 15;
+    let k_shl1 = table_depth(stack, options, "shl1_table_ref")?;
 
     Ok(script! {
         { k_shl1 } OP_ADD OP_PICK
@@ -427,8 +864,7 @@ fn u4_get_shl1(stack: &mut Stack, options: &Options) -> Result<Script> {
 }
 
 fn u4_get_shr3(stack: &mut Stack, options: &Options) -> Result<Script> {
-    let last_shr3_table_elem = options.get_u32("shr3_table_ref")?;
-    let k_shr3 = stack.get_relative_position(last_shr3_table_elem as usize)? - 15;
+    let k_shr3 = table_depth(stack, options, "shr3_table_ref")?;
 
     Ok(script! {
         { k_shr3 } OP_ADD OP_PICK
@@ -439,6 +875,7 @@ fn u4_get_shr3(stack: &mut Stack, options: &Options) -> Result<Script> {
 mod test {
     use crate::compression::blake3::lookup_table::LookupTableVar;
     use crate::limbs::u4::U4Var;
+    use crate::test_utils::expect_program_failure_without_opcat;
     use bitcoin_circle_stark::treepp::*;
     use bitcoin_script_dsl::bvar::AllocVar;
     use bitcoin_script_dsl::constraint_system::ConstraintSystem;
@@ -446,6 +883,39 @@ mod test {
     use rand::{Rng, SeedableRng};
     use rand_chacha::ChaCha20Rng;
 
+    #[test]
+    fn test_range_verify() {
+        let cs = ConstraintSystem::new_ref();
+        for v in 0..16 {
+            U4Var::new_hint(&cs, v).unwrap().range_verify().unwrap();
+        }
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_range_verify_out_of_range() {
+        let cs = ConstraintSystem::new_ref();
+        U4Var::new_hint(&cs, 17).unwrap().range_verify().unwrap();
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_alloc_out_of_range() {
+        let cs = ConstraintSystem::new_ref();
+        U4Var::new_program_input(&cs, 16).unwrap();
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+
+        let cs = ConstraintSystem::new_ref();
+        U4Var::new_hint(&cs, 16).unwrap();
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+
+        // the bounds themselves are accepted
+        let cs = ConstraintSystem::new_ref();
+        U4Var::new_program_input(&cs, 15).unwrap();
+        U4Var::new_hint(&cs, 0).unwrap();
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
     #[test]
     fn test_xor() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
@@ -0,0 +1,212 @@
+use crate::compression::blake3::lookup_table::LookupTableVar;
+use crate::limbs::u32::U32Var;
+use crate::limbs::u4::U4Var;
+use crate::limbs::TryAdd;
+use anyhow::Result;
+use bitcoin_circle_stark::treepp::*;
+use bitcoin_script_dsl::bvar::{AllocVar, AllocationMode, BVar};
+use bitcoin_script_dsl::constraint_system::ConstraintSystemRef;
+use std::ops::Add;
+
+#[derive(Debug, Clone)]
+pub struct U64Var {
+    pub lo: U32Var,
+    pub hi: U32Var,
+}
+
+impl BVar for U64Var {
+    type Value = u64;
+
+    fn cs(&self) -> ConstraintSystemRef {
+        self.lo.cs().and(&self.hi.cs())
+    }
+
+    fn variables(&self) -> Vec<usize> {
+        let mut variables = self.lo.variables();
+        variables.extend(self.hi.variables());
+        variables
+    }
+
+    fn length() -> usize {
+        16
+    }
+
+    fn value(&self) -> Result<Self::Value> {
+        Ok(((self.hi.value()? as u64) << 32) | self.lo.value()? as u64)
+    }
+}
+
+impl AllocVar for U64Var {
+    fn new_variable(
+        cs: &ConstraintSystemRef,
+        data: <Self as BVar>::Value,
+        mode: AllocationMode,
+    ) -> Result<Self> {
+        Ok(Self {
+            lo: U32Var::new_variable(cs, data as u32, mode)?,
+            hi: U32Var::new_variable(cs, (data >> 32) as u32, mode)?,
+        })
+    }
+}
+
+impl TryAdd<(&LookupTableVar, &U64Var)> for &U64Var {
+    type Output = U64Var;
+
+    fn try_add(self, rhs: (&LookupTableVar, &U64Var)) -> Result<Self::Output> {
+        let table = rhs.0;
+        let rhs = rhs.1;
+
+        let (lo, carry) = self.lo.add_with_carry_chain(table, &rhs.lo, None, true)?;
+        let (hi, _) = self
+            .hi
+            .add_with_carry_chain(table, &rhs.hi, carry.as_ref(), false)?;
+
+        Ok(U64Var { lo, hi })
+    }
+}
+
+impl Add<(&LookupTableVar, &U64Var)> for &U64Var {
+    type Output = U64Var;
+
+    fn add(self, rhs: (&LookupTableVar, &U64Var)) -> Self::Output {
+        self.try_add(rhs).unwrap()
+    }
+}
+
+impl U64Var {
+    pub fn rotate_right_shift_32(self) -> Self {
+        Self {
+            lo: self.hi,
+            hi: self.lo,
+        }
+    }
+
+    /// Return 1 if `self < other` as unsigned integers and 0 otherwise, which compares the high
+    /// halves and falls back to the low halves when they are equal.
+    pub fn less_than(&self, other: &U64Var) -> U4Var {
+        let hi_less_than = self.hi.less_than(&other.hi);
+        let hi_is_equal = self.hi.is_equal(&other.hi);
+        let lo_less_than = self.lo.less_than(&other.lo);
+
+        let cs = hi_less_than
+            .cs()
+            .and(&hi_is_equal.cs())
+            .and(&lo_less_than.cs());
+        cs.insert_script(
+            u64_less_than_from_halves,
+            [
+                hi_less_than.variable,
+                hi_is_equal.variable,
+                lo_less_than.variable,
+            ],
+        )
+        .unwrap();
+
+        let res = self.value().unwrap() < other.value().unwrap();
+        U4Var::new_function_output(&cs, res as u32).unwrap()
+    }
+}
+
+fn u64_less_than_from_halves() -> Script {
+    script! {
+        OP_BOOLAND OP_BOOLOR
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::blake3::lookup_table::LookupTableVar;
+    use crate::limbs::u4::U4Var;
+    use crate::limbs::u64::U64Var;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_u64_add() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut pairs = vec![
+            (0xffffffffu64, 1u64),
+            (u64::MAX, 1),
+            (u64::MAX, u64::MAX),
+            (0, 0),
+        ];
+        for _ in 0..20 {
+            pairs.push((prng.gen(), prng.gen()));
+        }
+
+        for (a, b) in pairs {
+            let cs = ConstraintSystem::new_ref();
+            let table = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+            let a_var = U64Var::new_program_input(&cs, a).unwrap();
+            let b_var = U64Var::new_program_input(&cs, b).unwrap();
+
+            let sum_var = &a_var + (&table, &b_var);
+            assert_eq!(sum_var.value().unwrap(), a.wrapping_add(b));
+
+            let expected_var = U64Var::new_constant(&cs, a.wrapping_add(b)).unwrap();
+            sum_var.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_u64_rotate_right_shift_32() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let cs = ConstraintSystem::new_ref();
+        let a: u64 = prng.gen();
+
+        let a_var = U64Var::new_program_input(&cs, a).unwrap();
+        let res_var = a_var.rotate_right_shift_32();
+        assert_eq!(res_var.value().unwrap(), a.rotate_right(32));
+
+        let expected_var = U64Var::new_constant(&cs, a.rotate_right(32)).unwrap();
+        res_var.equalverify(&expected_var).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_u64_less_than() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut pairs = vec![
+            (0xffffffffu64, 0x100000000u64),
+            (0x100000000, 0xffffffff),
+            (0x500000001, 0x500000002),
+            (0x500000002, 0x500000001),
+            (u64::MAX, u64::MAX),
+            (0, 0),
+        ];
+        for _ in 0..20 {
+            pairs.push((prng.gen(), prng.gen()));
+        }
+        for _ in 0..10 {
+            // equal high halves, so the low halves decide
+            let hi = (prng.gen::<u32>() as u64) << 32;
+            pairs.push((hi | prng.gen::<u32>() as u64, hi | prng.gen::<u32>() as u64));
+        }
+
+        for (a, b) in pairs {
+            let cs = ConstraintSystem::new_ref();
+
+            let a_var = U64Var::new_program_input(&cs, a).unwrap();
+            let b_var = U64Var::new_program_input(&cs, b).unwrap();
+
+            let res_var = a_var.less_than(&b_var);
+            assert_eq!(res_var.value().unwrap(), (a < b) as u32);
+
+            let expected_var = U4Var::new_constant(&cs, (a < b) as u32).unwrap();
+            res_var.equalverify(&expected_var).unwrap();
+
+            test_program_without_opcat(cs, script! {}).unwrap();
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::limbs::u32::OP_16MUL;
+use crate::limbs::u4::U4Var;
+use anyhow::Result;
+use bitcoin_circle_stark::treepp::*;
+use bitcoin_script_dsl::builtins::u8::U8Var;
+use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+
+/// Conversions between a `U8Var` and its two nibbles, which connect the byte-oriented
+/// Winternitz signatures with the nibble-oriented Blake3 gadgets.
+pub trait U8VarLimbs: Sized {
+    /// Compute `hi * 16 + lo` in script.
+    fn from_u4_pair(hi: &U4Var, lo: &U4Var) -> Result<Self>;
+
+    /// Split the byte into `(hi, lo)`, where the nibbles are hints and the script checks that
+    /// both are in range and that they recompose the byte.
+    fn to_u4_pair(&self) -> Result<(U4Var, U4Var)>;
+}
+
+impl U8VarLimbs for U8Var {
+    fn from_u4_pair(hi: &U4Var, lo: &U4Var) -> Result<Self> {
+        let cs = hi.cs().and(&lo.cs());
+        cs.insert_script(u8_from_u4_pair, [hi.variable, lo.variable])?;
+        U8Var::new_function_output(&cs, (hi.value * 16 + lo.value) as u8)
+    }
+
+    fn to_u4_pair(&self) -> Result<(U4Var, U4Var)> {
+        let cs = self.cs();
+        let value = self.value()? as u32;
+
+        let hi = U4Var::new_hint(&cs, value >> 4)?;
+        let lo = U4Var::new_hint(&cs, value & 15)?;
+        cs.insert_script(u8_check_u4_pair, [hi.variable, lo.variable, self.variable])?;
+
+        Ok((hi, lo))
+    }
+}
+
+fn u8_from_u4_pair() -> Script {
+    script! {
+        OP_SWAP OP_16MUL OP_ADD
+    }
+}
+
+/// Check that the high nibble, the low nibble, and the byte on the top of the stack are
+/// consistent, and consume them. The nibbles must already be range-checked.
+pub(crate) fn u8_check_u4_pair() -> Script {
+    script! {
+        OP_TOALTSTACK
+        OP_SWAP
+        OP_16MUL OP_ADD
+        OP_FROMALTSTACK OP_EQUALVERIFY
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::limbs::u4::U4Var;
+    use crate::limbs::u8::{u8_check_u4_pair, U8VarLimbs};
+    use crate::test_utils::expect_program_failure_without_opcat;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::builtins::u8::U8Var;
+    use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::test_program_without_opcat;
+
+    #[test]
+    fn test_u8_u4_pair_round_trip() {
+        let cs = ConstraintSystem::new_ref();
+
+        for byte in 0..=255u8 {
+            let byte_var = U8Var::new_program_input(&cs, byte).unwrap();
+            let (hi, lo) = byte_var.to_u4_pair().unwrap();
+            assert_eq!(hi.value, (byte >> 4) as u32);
+            assert_eq!(lo.value, (byte & 15) as u32);
+
+            let recovered_var = U8Var::from_u4_pair(&hi, &lo).unwrap();
+            assert_eq!(recovered_var.value().unwrap(), byte);
+            byte_var.equalverify(&recovered_var).unwrap();
+        }
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_u8_to_u4_pair_inconsistent_hint() {
+        let cs = ConstraintSystem::new_ref();
+
+        let hi = U4Var::new_hint(&cs, 6).unwrap();
+        let lo = U4Var::new_hint(&cs, 8).unwrap();
+        let byte_var = U8Var::new_program_input(&cs, 0x67).unwrap();
+        cs.insert_script(
+            u8_check_u4_pair,
+            [hi.variable, lo.variable, byte_var.variable],
+        )
+        .unwrap();
+
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+use crate::commitment::merkle::{MerklePath, MerklePathVar, MerkleTree};
+use crate::compression::blake3::{Blake3ConstantVar, Blake3HashVar};
+use crate::limbs::u32::U32Var;
+use crate::limbs::u4::U4Var;
+use anyhow::{Error, Result};
+use bitcoin_circle_stark::treepp::*;
+use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+use bitcoin_script_dsl::options::Options;
+use bitcoin_script_dsl::stack::Stack;
+
+/// A memory of 4-byte cells committed by a Blake3 Merkle tree, where the leaf of a cell is its
+/// value in little-endian.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    pub cells: Vec<u32>,
+    pub tree: MerkleTree,
+}
+
+impl Memory {
+    pub fn new(cells: Vec<u32>) -> Self {
+        assert!(cells.len().is_power_of_two());
+
+        let leaves = cells
+            .iter()
+            .map(|cell| cell.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let tree = MerkleTree::new(&leaves);
+
+        Self { cells, tree }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
+
+    /// Return the value of the cell and its Merkle path, as the hints for `verify_read`.
+    pub fn read(&self, addr: u32) -> (u32, MerklePath) {
+        let addr = addr as usize;
+        (self.cells[addr], self.tree.query(addr))
+    }
+
+    /// Write the cell and return the Merkle path of the cell, as the hints for `verify_write`.
+    /// The siblings are the same before and after the write.
+    pub fn write(&mut self, addr: u32, value: u32) -> MerklePath {
+        let addr = addr as usize;
+        let path = self.tree.query(addr);
+
+        self.cells[addr] = value;
+        self.tree.update(addr, &value.to_le_bytes());
+
+        path
+    }
+}
+
+/// Verify that the cell at `addr` holds `value` in the memory with the given root.
+pub fn verify_read(
+    constant: &Blake3ConstantVar,
+    root: &Blake3HashVar,
+    addr: &U32Var,
+    value: &U32Var,
+    path_hints: &MerklePath,
+) -> Result<()> {
+    let path_var = MerklePathVar::new_hint(&constant.cs, path_hints)?;
+    let direction_bits = address_to_direction_bits(addr, path_hints.siblings.len())?;
+
+    path_var.verify(constant, value.clone(), &direction_bits, root)
+}
+
+/// Verify that writing `new_value` to the cell at `addr`, which holds `old_value` in the memory
+/// with root `old_root`, results in the memory with root `new_root`.
+///
+/// The same sibling hints are checked against both roots, so the write cannot touch any other
+/// cell.
+pub fn verify_write(
+    constant: &Blake3ConstantVar,
+    old_root: &Blake3HashVar,
+    new_root: &Blake3HashVar,
+    addr: &U32Var,
+    old_value: &U32Var,
+    new_value: &U32Var,
+    path_hints: &MerklePath,
+) -> Result<()> {
+    let path_var = MerklePathVar::new_hint(&constant.cs, path_hints)?;
+    let direction_bits = address_to_direction_bits(addr, path_hints.siblings.len())?;
+
+    path_var.verify(constant, old_value.clone(), &direction_bits, old_root)?;
+    path_var.verify(constant, new_value.clone(), &direction_bits, new_root)
+}
+
+/// Decompose the lowest `depth` bits of the address into direction bits, which are hints
+/// checked in script to recompose the limbs of the address. The bits above `depth` must be zero.
+fn address_to_direction_bits(addr: &U32Var, depth: usize) -> Result<Vec<U4Var>> {
+    if depth > 32 {
+        return Err(Error::msg(
+            "The memory is deeper than a u32 address can index.",
+        ));
+    }
+
+    let cs = addr.cs();
+
+    let mut bits = vec![];
+    for (i, limb) in addr.limbs().iter().enumerate() {
+        let num_bits = depth.saturating_sub(4 * i).min(4);
+        if num_bits == 0 {
+            cs.insert_script(memory_limb_is_zero, [limb.variable])?;
+            continue;
+        }
+
+        let mut limb_bits = vec![];
+        for j in 0..num_bits {
+            limb_bits.push(U4Var::new_hint(&cs, (limb.value >> j) & 1)?);
+        }
+        cs.insert_script_complex(
+            memory_bits_recompose,
+            std::iter::once(limb.variable).chain(limb_bits.iter().map(|bit| bit.variable)),
+            &Options::new().with_u32("num_bits", num_bits as u32),
+        )?;
+        bits.extend(limb_bits);
+    }
+
+    Ok(bits)
+}
+
+fn memory_limb_is_zero() -> Script {
+    script! {
+        OP_NOT OP_VERIFY
+    }
+}
+
+fn memory_bits_recompose(_: &mut Stack, options: &Options) -> Result<Script> {
+    let num_bits = options.get_u32("num_bits")? as usize;
+
+    Ok(script! {
+        // the highest bit is on the top of the stack
+        OP_DUP 0 2 OP_WITHIN OP_VERIFY
+        for _ in 1..num_bits {
+            OP_DUP OP_ADD
+            OP_SWAP
+            OP_DUP 0 2 OP_WITHIN OP_VERIFY
+            OP_ADD
+        }
+        OP_EQUALVERIFY
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::blake3::{Blake3ConstantVar, Blake3HashVar};
+    use crate::limbs::u32::U32Var;
+    use crate::memory::{verify_read, verify_write, Memory};
+    use crate::test_utils::expect_program_failure_without_opcat;
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::AllocVar;
+    use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+    use bitcoin_script_dsl::test_program_without_opcat;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn random_memory(prng: &mut ChaCha20Rng, depth: usize) -> Memory {
+        Memory::new((0..1 << depth).map(|_| prng.gen()).collect())
+    }
+
+    fn read_test_case(depth: usize, wrong_value: bool, wrong_sibling: bool) -> ConstraintSystemRef {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let memory = random_memory(&mut prng, depth);
+
+        let addr = prng.gen_range(0..1 << depth);
+        let (mut value, mut path) = memory.read(addr);
+        if wrong_value {
+            value ^= 1;
+        }
+        if wrong_sibling {
+            path.siblings[depth / 2][0] ^= 1;
+        }
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let root_var = Blake3HashVar::new_constant(&cs, memory.root()).unwrap();
+        let addr_var = U32Var::new_program_input(&cs, addr).unwrap();
+        let value_var = U32Var::new_program_input(&cs, value).unwrap();
+        verify_read(&constant, &root_var, &addr_var, &value_var, &path).unwrap();
+
+        cs
+    }
+
+    #[test]
+    fn test_memory_read() {
+        test_program_without_opcat(read_test_case(4, false, false), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_memory_read_wrong_value() {
+        expect_program_failure_without_opcat(read_test_case(4, true, false), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_memory_read_wrong_sibling() {
+        expect_program_failure_without_opcat(read_test_case(4, false, true), script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_memory_write() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut memory = random_memory(&mut prng, 4);
+
+        let addr = prng.gen_range(0..16);
+        let old_root = memory.root();
+        let old_value = memory.cells[addr as usize];
+        let new_value: u32 = prng.gen();
+        let path = memory.write(addr, new_value);
+
+        assert_ne!(memory.root(), old_root);
+        assert_eq!(memory.root(), Memory::new(memory.cells.clone()).root());
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        verify_write(
+            &constant,
+            &Blake3HashVar::new_constant(&cs, old_root).unwrap(),
+            &Blake3HashVar::new_program_input(&cs, memory.root()).unwrap(),
+            &U32Var::new_program_input(&cs, addr).unwrap(),
+            &U32Var::new_program_input(&cs, old_value).unwrap(),
+            &U32Var::new_program_input(&cs, new_value).unwrap(),
+            &path,
+        )
+        .unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_memory_depth_8() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut memory = random_memory(&mut prng, 8);
+
+        let cs = ConstraintSystem::new_ref();
+        let constant = Blake3ConstantVar::new(&cs);
+
+        let mut root_var = Blake3HashVar::new_constant(&cs, memory.root()).unwrap();
+
+        // write a cell, and then read it back under the new root
+        let addr = prng.gen_range(0..256);
+        let old_value = memory.cells[addr as usize];
+        let new_value: u32 = prng.gen();
+        let path = memory.write(addr, new_value);
+
+        let addr_var = U32Var::new_program_input(&cs, addr).unwrap();
+        let new_value_var = U32Var::new_program_input(&cs, new_value).unwrap();
+        let new_root_var = Blake3HashVar::new_program_input(&cs, memory.root()).unwrap();
+        verify_write(
+            &constant,
+            &root_var,
+            &new_root_var,
+            &addr_var,
+            &U32Var::new_program_input(&cs, old_value).unwrap(),
+            &new_value_var,
+            &path,
+        )
+        .unwrap();
+        root_var = new_root_var;
+
+        let (value, path) = memory.read(addr);
+        assert_eq!(value, new_value);
+        verify_read(&constant, &root_var, &addr_var, &new_value_var, &path).unwrap();
+
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+use crate::compression::blake3::lookup_table::LookupTableVar;
+use crate::limbs::u32::U32Var;
+use anyhow::{Error, Result};
+use bitcoin_circle_stark::treepp::*;
+use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+use bitcoin_script_dsl::compiler::Compiler;
+use bitcoin_script_dsl::constraint_system::{ConstraintSystem, ConstraintSystemRef};
+use bitcoin_script_dsl::{test_program, test_program_without_opcat};
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestCaseError, TestRunner};
+use std::fmt::Debug;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// The number of random cases that the differential checks run for each operation.
+pub(crate) const U32_OP_CASES: u32 = 256;
+
+/// The number of random cases for the ignored long runs, e.g.,
+/// `cargo test -- --ignored match_reference_long`.
+pub(crate) const U32_OP_CASES_LONG: u32 = 4096;
+
+/// The 8 nibbles of a u32, from the lowest to the highest, as they appear on the stack.
+pub(crate) fn u32_to_nibbles(mut v: u32) -> [u32; 8] {
+    let mut res = [0u32; 8];
+    for nibble in res.iter_mut() {
+        *nibble = v & 15;
+        v >>= 4;
+    }
+    res
+}
+
+pub(crate) fn u32s_to_nibbles(vs: &[u32]) -> Vec<u32> {
+    let mut res = vec![];
+    for &v in vs.iter() {
+        res.extend_from_slice(&u32_to_nibbles(v));
+    }
+    res
+}
+
+/// Run the program like `test_program` and return `Ok(())` only if the script execution fails.
+///
+/// The program is compiled first, outside of the check, so that an error or a panic while
+/// compiling it fails the test instead of counting as a rejection by the script.
+pub(crate) fn expect_program_failure(
+    cs: ConstraintSystemRef,
+    expected_stack: Script,
+) -> Result<()> {
+    Compiler::compile(cs.clone())?;
+    check_program_failure(|| test_program(cs, expected_stack))
+}
+
+/// Run the program like `test_program_without_opcat` and return `Ok(())` only if the script
+/// execution fails.
+pub(crate) fn expect_program_failure_without_opcat(
+    cs: ConstraintSystemRef,
+    expected_stack: Script,
+) -> Result<()> {
+    Compiler::compile(cs.clone())?;
+    check_program_failure(|| test_program_without_opcat(cs, expected_stack))
+}
+
+fn check_program_failure(f: impl FnOnce() -> Result<()>) -> Result<()> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => Err(Error::msg("The program unexpectedly succeeded.")),
+        // the execution either reports the failure or panics on it
+        Ok(Err(_)) | Err(_) => Ok(()),
+    }
+}
+
+/// Random u32 values, with the boundaries where carries and rotations tend to go wrong drawn as
+/// often as uniform values.
+pub(crate) fn u32_strategy() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        Just(0u32),
+        Just(u32::MAX),
+        (0..32u32).prop_map(|i| 1 << i),
+        (0..32u32).prop_map(|i| (1u32 << i) - 1),
+        any::<u32>(),
+    ]
+}
+
+/// Check on `cases` random inputs that `op_var` computes `op_ref`, both in the values of the
+/// variables and in script, and panic with the shrunk inputs on a mismatch.
+pub(crate) fn assert_u32_op_matches(
+    cases: u32,
+    op_var: impl Fn(&LookupTableVar, &U32Var, &U32Var) -> U32Var,
+    op_ref: impl Fn(u32, u32) -> u32,
+) {
+    run_u32_cases(cases, (u32_strategy(), u32_strategy()), |(a, b)| {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+        let a_var = U32Var::new_program_input(&cs, a).unwrap();
+        let b_var = U32Var::new_program_input(&cs, b).unwrap();
+        let res_var = op_var(&table, &a_var, &b_var);
+
+        check_u32_result(cs, &res_var, op_ref(a, b))
+    });
+}
+
+/// Check a single-operand operation as `assert_u32_op_matches` does.
+pub(crate) fn assert_u32_unary_op_matches(
+    cases: u32,
+    op_var: impl Fn(&LookupTableVar, U32Var) -> U32Var,
+    op_ref: impl Fn(u32) -> u32,
+) {
+    run_u32_cases(cases, u32_strategy(), |a| {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::new_constant(&cs, ()).unwrap();
+
+        let a_var = U32Var::new_program_input(&cs, a).unwrap();
+        let res_var = op_var(&table, a_var);
+
+        check_u32_result(cs, &res_var, op_ref(a))
+    });
+}
+
+fn run_u32_cases<S: Strategy>(
+    cases: u32,
+    strategy: S,
+    check: impl Fn(S::Value) -> Result<(), TestCaseError>,
+) where
+    S::Value: Debug,
+{
+    let mut runner = TestRunner::new(Config::with_cases(cases));
+    if let Err(err) = runner.run(&strategy, check) {
+        panic!("{}", err);
+    }
+}
+
+fn check_u32_result(
+    cs: ConstraintSystemRef,
+    res_var: &U32Var,
+    expected: u32,
+) -> Result<(), TestCaseError> {
+    prop_assert_eq!(res_var.value().unwrap(), expected);
+
+    let expected_var = U32Var::new_constant(&cs, expected).unwrap();
+    res_var.equalverify(&expected_var).unwrap();
+
+    test_program_without_opcat(cs, script! {}).map_err(|err| TestCaseError::fail(err.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::limbs::u32::U32Var;
+    use crate::test_utils::{
+        expect_program_failure_without_opcat, u32_strategy, u32_to_nibbles, u32s_to_nibbles,
+    };
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::AllocVar;
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_u32_to_nibbles() {
+        assert_eq!(u32_to_nibbles(0x12345678), [8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(u32_to_nibbles(0), [0; 8]);
+        assert_eq!(u32_to_nibbles(u32::MAX), [15; 8]);
+        assert_eq!(
+            u32s_to_nibbles(&[0xfedcba98, 0x1]),
+            vec![8, 9, 10, 11, 12, 13, 14, 15, 1, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_expect_program_failure() {
+        let cs = ConstraintSystem::new_ref();
+        let a_var = U32Var::new_program_input(&cs, 1).unwrap();
+        let b_var = U32Var::new_constant(&cs, 2).unwrap();
+        a_var.equalverify(&b_var).unwrap();
+        expect_program_failure_without_opcat(cs, script! {}).unwrap();
+
+        let cs = ConstraintSystem::new_ref();
+        let a_var = U32Var::new_program_input(&cs, 2).unwrap();
+        let b_var = U32Var::new_constant(&cs, 2).unwrap();
+        a_var.equalverify(&b_var).unwrap();
+        assert!(expect_program_failure_without_opcat(cs, script! {}).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_u32_strategy_nibbles(v in u32_strategy()) {
+            let nibbles = u32_to_nibbles(v);
+            let recomposed = nibbles.iter().rev().fold(0u32, |acc, &nibble| (acc << 4) | nibble);
+            prop_assert!(nibbles.iter().all(|&nibble| nibble < 16));
+            prop_assert_eq!(recomposed, v);
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use crate::compression::blake3::cache::{get_or_create, Registry};
+use crate::cost::{estimated_script_len, Cost};
+use anyhow::{Error, Result};
+use bitcoin_circle_stark::treepp::*;
+use bitcoin_script_dsl::bvar::{AllocationMode, BVar};
+use bitcoin_script_dsl::constraint_system::{ConstraintSystemRef, Element};
+use bitcoin_script_dsl::options::Options;
+use bitcoin_script_dsl::stack::Stack;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// The most elements that Bitcoin allows on the stack and the altstack together.
+pub const MAX_STACK_ITEMS: usize = 1000;
+
+/// Limits on the program that a constraint system builds, checked as each gadget is inserted,
+/// so that the gadget that exceeds them fails instead of the execution of the program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_stack_items: Option<usize>,
+    /// Checking the script size compiles the program and the gadget before each insertion,
+    /// which is slow, so this is meant to find the gadget that makes a program too large.
+    pub max_script_bytes: Option<usize>,
+}
+
+impl Limits {
+    /// The stack limit of Bitcoin.
+    pub fn bitcoin() -> Self {
+        Self::default().with_max_stack_items(MAX_STACK_ITEMS)
+    }
+
+    pub fn with_max_stack_items(mut self, max_stack_items: usize) -> Self {
+        self.max_stack_items = Some(max_stack_items);
+        self
+    }
+
+    pub fn with_max_script_bytes(mut self, max_script_bytes: usize) -> Self {
+        self.max_script_bytes = Some(max_script_bytes);
+        self
+    }
+}
+
+/// The estimate of the stack of a constraint system, where an element allocated by this crate
+/// stays on the stack until its first use as the input of a gadget. Elements used more than
+/// once and elements allocated elsewhere are not counted, so this is not an upper bound.
+#[derive(Default)]
+struct Watchdog {
+    limits: Limits,
+    live: HashSet<usize>,
+    /// The elements that stay on the stack throughout, such as the lookup tables.
+    pinned: usize,
+    stack_high_water: usize,
+}
+
+thread_local! {
+    static WATCHDOGS: Registry<RefCell<Watchdog>> = RefCell::new(vec![]);
+}
+
+fn watchdog(cs: &ConstraintSystemRef) -> Rc<RefCell<Watchdog>> {
+    get_or_create(&WATCHDOGS, cs, |_| RefCell::new(Watchdog::default()))
+}
+
+/// Check the limits on the gadgets inserted into the constraint system from now on.
+pub fn register_limits(cs: &ConstraintSystemRef, limits: Limits) {
+    watchdog(cs).borrow_mut().limits = limits;
+}
+
+/// The most elements on the stack so far, as estimated from the gadgets of this crate.
+pub fn estimated_stack_high_water(cs: &ConstraintSystemRef) -> usize {
+    watchdog(cs).borrow().stack_high_water
+}
+
+/// The length in bytes of the script so far.
+pub fn estimated_script_bytes(cs: &ConstraintSystemRef) -> Result<usize> {
+    estimated_script_len(cs)
+}
+
+/// Record an element that the gadgets of this crate allocate.
+pub(crate) fn track_alloc(cs: &ConstraintSystemRef, variable: usize) {
+    let watchdog = watchdog(cs);
+    let mut watchdog = watchdog.borrow_mut();
+    watchdog.live.insert(variable);
+    watchdog.stack_high_water = watchdog
+        .stack_high_water
+        .max(watchdog.pinned + watchdog.live.len());
+}
+
+/// Record the elements of a variable of a type that the DSL defines, such as `U8Var`, when
+/// this crate allocates it.
+pub(crate) fn track_var<T: BVar>(var: T) -> T {
+    let cs = var.cs();
+    for variable in var.variables() {
+        track_alloc(&cs, variable);
+    }
+    var
+}
+
+/// Record `n` elements that stay on the stack until the end of the program.
+pub(crate) fn track_pinned(cs: &ConstraintSystemRef, n: usize) {
+    let watchdog = watchdog(cs);
+    let mut watchdog = watchdog.borrow_mut();
+    watchdog.pinned += n;
+    watchdog.stack_high_water = watchdog
+        .stack_high_water
+        .max(watchdog.pinned + watchdog.live.len());
+}
+
+/// Insert scripts like `ConstraintSystemRef::insert_script*`, checking the registered limits
+/// and reporting the gadget, usually the name of the script generator, that exceeds them.
+pub(crate) trait WatchdogExt {
+    fn insert_gadget(
+        &self,
+        gadget: &str,
+        script_generator: fn() -> Script,
+        variables: impl IntoIterator<Item = usize>,
+    ) -> Result<()>;
+
+    fn insert_gadget_complex(
+        &self,
+        gadget: &str,
+        script_generator: fn(&mut Stack, &Options) -> Result<Script>,
+        variables: impl IntoIterator<Item = usize>,
+        options: &Options,
+    ) -> Result<()>;
+}
+
+impl WatchdogExt for ConstraintSystemRef {
+    fn insert_gadget(
+        &self,
+        gadget: &str,
+        script_generator: fn() -> Script,
+        variables: impl IntoIterator<Item = usize>,
+    ) -> Result<()> {
+        let variables = variables.into_iter().collect::<Vec<_>>();
+        check_script_bytes(self, gadget, &variables, |scratch| {
+            scratch.insert_script(script_generator, variables.clone())
+        })?;
+        check_stack(self, gadget, &variables)?;
+        self.insert_script(script_generator, variables)
+    }
+
+    fn insert_gadget_complex(
+        &self,
+        gadget: &str,
+        script_generator: fn(&mut Stack, &Options) -> Result<Script>,
+        variables: impl IntoIterator<Item = usize>,
+        options: &Options,
+    ) -> Result<()> {
+        let variables = variables.into_iter().collect::<Vec<_>>();
+        check_script_bytes(self, gadget, &variables, |scratch| {
+            scratch.insert_script_complex(script_generator, variables.clone(), options)
+        })?;
+        check_stack(self, gadget, &variables)?;
+        self.insert_script_complex(script_generator, variables, options)
+    }
+}
+
+/// The inputs that are used for the first time are moved to the top of the stack, and the
+/// others are copied, after which the gadget consumes them.
+fn check_stack(cs: &ConstraintSystemRef, gadget: &str, variables: &[usize]) -> Result<()> {
+    let watchdog = watchdog(cs);
+    let mut watchdog = watchdog.borrow_mut();
+
+    let copies = variables
+        .iter()
+        .filter(|variable| !watchdog.live.contains(variable))
+        .count();
+    let stack_items = watchdog.pinned + watchdog.live.len() + copies;
+
+    if let Some(max_stack_items) = watchdog.limits.max_stack_items {
+        if stack_items > max_stack_items {
+            return Err(Error::msg(format!(
+                "The gadget {} would need about {} stack elements, beyond the limit of {}.",
+                gadget, stack_items, max_stack_items
+            )));
+        }
+    }
+
+    watchdog.stack_high_water = watchdog.stack_high_water.max(stack_items);
+    for variable in variables.iter() {
+        watchdog.live.remove(variable);
+    }
+    Ok(())
+}
+
+/// Since a script cannot be removed once inserted, the gadget is measured beforehand on a
+/// scratch constraint system that allocates placeholders up to the largest input, so that the
+/// inputs and the tables allocated before them keep their references. Every placeholder stays
+/// on the stack, so the picks and rolls are at least as deep as in the actual program.
+fn check_script_bytes(
+    cs: &ConstraintSystemRef,
+    gadget: &str,
+    variables: &[usize],
+    insert: impl FnOnce(&ConstraintSystemRef) -> Result<()>,
+) -> Result<()> {
+    let Some(max_script_bytes) = watchdog(cs).borrow().limits.max_script_bytes else {
+        return Ok(());
+    };
+    let last_variable = variables.iter().copied().max().unwrap_or_default();
+
+    let gadget_bytes = Cost::measure_op(
+        |scratch| {
+            while scratch.alloc(Element::Num(0), AllocationMode::ProgramInput)? < last_variable {}
+            Ok(scratch.clone())
+        },
+        insert,
+    )?
+    .script_len;
+
+    let script_bytes = estimated_script_len(cs)? + gadget_bytes;
+    if script_bytes > max_script_bytes {
+        return Err(Error::msg(format!(
+            "The gadget {} would make the script about {} bytes long, beyond the limit of {}.",
+            gadget, script_bytes, max_script_bytes
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::blake3::lookup_table::LookupTableVar;
+    use crate::limbs::u4::U4Var;
+    use crate::limbs::TryAdd;
+    use crate::watchdog::{
+        estimated_script_bytes, estimated_stack_high_water, register_limits, Limits,
+    };
+    use bitcoin_circle_stark::treepp::*;
+    use bitcoin_script_dsl::bvar::{AllocVar, BVar};
+    use bitcoin_script_dsl::constraint_system::ConstraintSystem;
+    use bitcoin_script_dsl::test_program_without_opcat;
+
+    #[test]
+    fn test_watchdog_estimates() {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder().with_xor().build(&cs).unwrap();
+        let tables = estimated_stack_high_water(&cs);
+        assert!(tables > 0);
+
+        let a = U4Var::new_program_input(&cs, 5).unwrap();
+        let b = U4Var::new_program_input(&cs, 9).unwrap();
+        assert_eq!(estimated_stack_high_water(&cs), tables + 2);
+
+        let c = a.try_xor(&table, &b).unwrap();
+        assert_eq!(estimated_stack_high_water(&cs), tables + 2);
+        assert!(estimated_script_bytes(&cs).unwrap() > 0);
+
+        c.equalverify(&U4Var::new_constant(&cs, 5 ^ 9).unwrap())
+            .unwrap();
+        test_program_without_opcat(cs, script! {}).unwrap();
+    }
+
+    #[test]
+    fn test_watchdog_stack_limit() {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder().with_xor().build(&cs).unwrap();
+        let tables = estimated_stack_high_water(&cs);
+        register_limits(&cs, Limits::default().with_max_stack_items(tables + 4));
+
+        let mut inputs = vec![];
+        for i in 0..4 {
+            inputs.push(U4Var::new_program_input(&cs, i).unwrap());
+        }
+        let _ = inputs[0].try_xor(&table, &inputs[1]).unwrap();
+        assert_eq!(estimated_stack_high_water(&cs), tables + 4);
+
+        // the inputs have been used, so the second XOR copies both of them on top of the three
+        // elements that are still on the stack
+        let err = inputs[0]
+            .try_xor(&table, &inputs[1])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("u4var_xor"), "{}", err);
+        assert!(err.contains(&format!("limit of {}", tables + 4)), "{}", err);
+    }
+
+    #[test]
+    fn test_watchdog_script_limit() {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder().with_xor().build(&cs).unwrap();
+        let a = U4Var::new_program_input(&cs, 1).unwrap();
+        let b = U4Var::new_program_input(&cs, 2).unwrap();
+        register_limits(
+            &cs,
+            Limits::default().with_max_script_bytes(estimated_script_bytes(&cs).unwrap()),
+        );
+
+        let script_bytes = estimated_script_bytes(&cs).unwrap();
+        let err = a.try_xor(&table, &b).err().unwrap().to_string();
+        assert!(err.contains("u4var_xor"), "{}", err);
+        assert!(err.contains("bytes long"), "{}", err);
+
+        // the gadget is rejected before it is inserted
+        assert_eq!(estimated_script_bytes(&cs).unwrap(), script_bytes);
+    }
+
+    #[test]
+    fn test_watchdog_names_failing_gadget() {
+        let cs = ConstraintSystem::new_ref();
+        let table = LookupTableVar::builder()
+            .with_xor()
+            .with_add()
+            .build(&cs)
+            .unwrap();
+        let tables = estimated_stack_high_water(&cs);
+        register_limits(&cs, Limits::default().with_max_stack_items(tables + 4));
+
+        let mut inputs = vec![];
+        for i in 0..4 {
+            inputs.push(U4Var::new_program_input(&cs, i).unwrap());
+        }
+        let _ = inputs[0].try_xor(&table, &inputs[1]).unwrap();
+
+        // the XOR fits, and the addition that copies its inputs again does not
+        let err = (&inputs[0])
+            .try_add((&table, &inputs[1]))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("u4_add_and_reduce"), "{}", err);
+        assert!(!err.contains("u4var_xor"), "{}", err);
+    }
+}